@@ -0,0 +1,44 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+// y = a * x + y, with a in a uniform and x, y in storage buffers.
+
+[[block]]
+struct Params {
+    a: f32;
+};
+
+[[block]]
+struct DataBuf {
+    data: [[stride(4)]] array<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: Params;
+
+[[group(0), binding(1)]]
+var<storage, read> x: DataBuf;
+
+[[group(0), binding(2)]]
+var<storage, read_write> y: DataBuf;
+
+[[stage(compute), workgroup_size(64)]]
+fn main([[builtin(workgroup_id)]] wg_id: vec3<u32>, [[builtin(local_invocation_index)]] local_ix: u32) {
+    let ix = linear_invocation_index(wg_id, local_ix, 64u);
+    if (ix < arrayLength(&y.data)) {
+        y.data[ix] = params.a * x.data[ix] + y.data[ix];
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Adapter selection, including the software fallback.
+
+use crate::Error;
+
+/// Environment variable that requests a software adapter when set to
+/// anything other than `0` or the empty string.
+pub const FALLBACK_ENV: &str = "WGPU_FORCE_FALLBACK_ADAPTER";
+
+/// How to choose an adapter.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AdapterOptions {
+    /// Request a software adapter (lavapipe, llvmpipe, WARP and friends)
+    /// instead of a hardware GPU.
+    pub force_fallback: bool,
+}
+
+impl AdapterOptions {
+    /// Options taken from the environment.
+    ///
+    /// The fallback adapter is requested if [`FALLBACK_ENV`] is set. The
+    /// set of backends can be restricted with `WGPU_BACKEND`, as elsewhere
+    /// in wgpu.
+    pub fn from_env() -> AdapterOptions {
+        let force_fallback = std::env::var(FALLBACK_ENV)
+            .map(|v| !v.is_empty() && v != "0")
+            .unwrap_or(false);
+        AdapterOptions { force_fallback }
+    }
+
+    /// The backends to search for an adapter.
+    ///
+    /// Software rasterizers are often only exposed through GL, so when the
+    /// fallback is requested all backends are considered.
+    pub fn backends(&self) -> wgpu::Backends {
+        wgpu::util::backend_bits_from_env().unwrap_or(if self.force_fallback {
+            wgpu::Backends::all()
+        } else {
+            wgpu::Backends::PRIMARY
+        })
+    }
+}
+
+/// Request an adapter, listing the adapters that were found if none match.
+pub async fn request_adapter(
+    instance: &wgpu::Instance,
+    options: &AdapterOptions,
+    compatible_surface: Option<&wgpu::Surface>,
+) -> Result<wgpu::Adapter, Error> {
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: Default::default(),
+            force_fallback_adapter: options.force_fallback,
+            compatible_surface,
+        })
+        .await;
+    adapter.ok_or_else(|| Error::NoAdapter {
+        force_fallback: options.force_fallback,
+        available: wgpu::Instance::new(wgpu::Backends::all())
+            .enumerate_adapters(wgpu::Backends::all())
+            .map(|adapter| adapter.get_info())
+            .collect(),
+    })
+}
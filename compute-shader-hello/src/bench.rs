@@ -0,0 +1,298 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Statistical micro-benchmarking of kernels.
+//!
+//! Each iteration is timed with GPU timestamp queries when the adapter
+//! supports them, and with wall-clock time around submission and completion
+//! otherwise. The latter includes submission overhead, so the two are not
+//! directly comparable; the report records which was used.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::staging::StagingStats;
+use crate::{ComputeRunner, Error, Kernel};
+
+/// How many times to run a kernel.
+#[derive(Clone, Copy, Debug)]
+pub struct BenchOptions {
+    /// Untimed iterations run first, to warm up caches and clocks.
+    pub warmup: usize,
+    /// Timed iterations.
+    pub iterations: usize,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        BenchOptions {
+            warmup: 10,
+            iterations: 100,
+        }
+    }
+}
+
+/// The clock used to time iterations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Timer {
+    Gpu,
+    Cpu,
+}
+
+/// Per-iteration timings of a kernel.
+pub struct Samples {
+    pub timer: Timer,
+    pub durations: Vec<Duration>,
+    /// Staging buffers asked for and created while benchmarking.
+    pub staging: StagingStats,
+}
+
+/// Summary statistics over a set of samples, in milliseconds.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Stats {
+    pub min: f64,
+    pub median: f64,
+    pub p95: f64,
+    pub mean: f64,
+    pub stddev: f64,
+}
+
+impl Stats {
+    /// Compute statistics over a non-empty set of durations.
+    pub fn from_durations(durations: &[Duration]) -> Stats {
+        let mut ms: Vec<f64> = durations.iter().map(|d| d.as_secs_f64() * 1e3).collect();
+        ms.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let n = ms.len();
+        let mean = ms.iter().sum::<f64>() / n as f64;
+        let variance = if n > 1 {
+            ms.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / (n - 1) as f64
+        } else {
+            0.0
+        };
+        Stats {
+            min: ms[0],
+            median: percentile(&ms, 50.0),
+            p95: percentile(&ms, 95.0),
+            mean,
+            stddev: variance.sqrt(),
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted data.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Throughput derived from the median time.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Throughput {
+    pub elements_per_sec: f64,
+    pub gb_per_sec: f64,
+    /// Only reported for kernels whose floating point work is known; see
+    /// [`BenchReport::with_flops`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gflop_per_sec: Option<f64>,
+}
+
+impl Throughput {
+    /// Throughput for processing `elements` elements occupying `bytes` bytes
+    /// in `median_ms` milliseconds.
+    pub fn new(elements: u64, bytes: u64, median_ms: f64) -> Throughput {
+        let secs = median_ms * 1e-3;
+        Throughput {
+            elements_per_sec: elements as f64 / secs,
+            gb_per_sec: bytes as f64 / secs * 1e-9,
+            gflop_per_sec: None,
+        }
+    }
+}
+
+/// The result of a benchmark run, in a form suitable for JSON output.
+#[derive(Clone, Debug, Serialize)]
+pub struct BenchReport {
+    pub adapter: String,
+    pub backend: String,
+    pub kernel: String,
+    pub timer: Timer,
+    pub warmup: usize,
+    pub iterations: usize,
+    pub elements: u64,
+    pub bytes: u64,
+    pub stats_ms: Stats,
+    pub throughput: Throughput,
+    pub staging: StagingStats,
+}
+
+impl BenchReport {
+    /// Summarize samples taken on `runner`.
+    ///
+    /// `bytes` is the amount of memory the kernel is considered to touch per
+    /// run; for GB/s to be meaningful, it should count both reads and writes.
+    pub fn new(
+        runner: &ComputeRunner,
+        kernel: &str,
+        options: &BenchOptions,
+        samples: &Samples,
+        elements: u64,
+        bytes: u64,
+    ) -> BenchReport {
+        let stats_ms = Stats::from_durations(&samples.durations);
+        BenchReport {
+            adapter: runner.adapter_info.name.clone(),
+            backend: format!("{:?}", runner.adapter_info.backend),
+            kernel: kernel.to_string(),
+            timer: samples.timer,
+            warmup: options.warmup,
+            iterations: options.iterations,
+            elements,
+            bytes,
+            stats_ms,
+            throughput: Throughput::new(elements, bytes, stats_ms.median),
+            staging: samples.staging,
+        }
+    }
+
+    /// Add the floating point operation rate, given the number of operations
+    /// in one run.
+    pub fn with_flops(mut self, flops: u64) -> BenchReport {
+        let secs = self.stats_ms.median * 1e-3;
+        self.throughput.gflop_per_sec = Some(flops as f64 / secs * 1e-9);
+        self
+    }
+}
+
+impl ComputeRunner {
+    /// Run a kernel repeatedly and collect the time taken by each run.
+    pub async fn bench(
+        &self,
+        kernel: &Kernel,
+        bindings: &[&wgpu::Buffer],
+        workgroups: (u32, u32, u32),
+        options: &BenchOptions,
+    ) -> Result<Samples, Error> {
+        self.bench_with(options, |encoder| {
+            self.encode_kernel(encoder, kernel, bindings, workgroups)
+        })
+        .await
+    }
+
+    /// Submit the commands recorded by `record` repeatedly, and collect the
+    /// time taken by each submission.
+    pub async fn bench_with(
+        &self,
+        options: &BenchOptions,
+        record: impl Fn(&mut wgpu::CommandEncoder),
+    ) -> Result<Samples, Error> {
+        self.bench_inner(options, record, None).await
+    }
+
+    /// Like [`bench_with`](ComputeRunner::bench_with), but also read the
+    /// first `size` bytes of `buf` back after each submission, as a program
+    /// using every result would. Reading back isn't included in the timings.
+    pub async fn bench_with_read_back(
+        &self,
+        options: &BenchOptions,
+        record: impl Fn(&mut wgpu::CommandEncoder),
+        buf: &wgpu::Buffer,
+        size: u64,
+    ) -> Result<Samples, Error> {
+        self.bench_inner(options, record, Some((buf, size))).await
+    }
+
+    async fn bench_inner(
+        &self,
+        options: &BenchOptions,
+        record: impl Fn(&mut wgpu::CommandEncoder),
+        read_back: Option<(&wgpu::Buffer, u64)>,
+    ) -> Result<Samples, Error> {
+        let staging_before = self.staging_stats();
+        let read_back = || async {
+            match read_back {
+                Some((buf, size)) => self.read_back_ranges(&[(buf, 0..size)]).await.map(drop),
+                None => Ok(()),
+            }
+        };
+        for _ in 0..options.warmup {
+            self.submit(&record).await?;
+            read_back().await?;
+        }
+        let timer = if self.query_set.is_some() {
+            Timer::Gpu
+        } else {
+            Timer::Cpu
+        };
+        let mut durations = Vec::with_capacity(options.iterations);
+        for _ in 0..options.iterations {
+            let start = Instant::now();
+            let gpu_time = self.submit(&record).await?;
+            durations.push(gpu_time.unwrap_or_else(|| start.elapsed()));
+            read_back().await?;
+        }
+        Ok(Samples {
+            timer,
+            durations,
+            staging: self.staging_stats() - staging_before,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(values: &[u64]) -> Vec<Duration> {
+        values.iter().map(|&v| Duration::from_millis(v)).collect()
+    }
+
+    #[test]
+    fn stats_of_a_known_sample() {
+        // Mean 5, with squared deviations adding up to 32.
+        let stats = Stats::from_durations(&ms(&[5, 9, 4, 2, 4, 7, 4, 5]));
+        assert_eq!(stats.min, 2.0);
+        assert_eq!(stats.median, 4.0);
+        assert_eq!(stats.p95, 9.0);
+        assert!((stats.mean - 5.0).abs() < 1e-9);
+        assert!((stats.stddev - (32.0f64 / 7.0).sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn stats_of_one_sample() {
+        let stats = Stats::from_durations(&ms(&[3]));
+        assert_eq!((stats.min, stats.median, stats.p95), (3.0, 3.0, 3.0));
+        assert_eq!(stats.stddev, 0.0);
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let sorted = (1..=20).map(f64::from).collect::<Vec<_>>();
+        assert_eq!(percentile(&sorted, 50.0), 10.0);
+        assert_eq!(percentile(&sorted, 95.0), 19.0);
+        assert_eq!(percentile(&sorted, 100.0), 20.0);
+        assert_eq!(percentile(&sorted, 0.0), 1.0);
+    }
+
+    #[test]
+    fn throughput_from_the_median() {
+        let throughput = Throughput::new(1_000_000, 8_000_000, 2.0);
+        assert!((throughput.elements_per_sec - 5e8).abs() < 1e-3);
+        assert!((throughput.gb_per_sec - 4.0).abs() < 1e-9);
+        assert_eq!(throughput.gflop_per_sec, None);
+    }
+}
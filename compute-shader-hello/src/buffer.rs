@@ -0,0 +1,380 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Buffers that know their element type.
+//!
+//! A [`GpuBuffer`] carries its element type and count, so sizes are never
+//! computed by hand, and can be checked against the struct the shader
+//! declares for its binding before anything is dispatched.
+
+use std::marker::PhantomData;
+use std::mem::size_of;
+
+use bytemuck::Pod;
+use wgpu::util::DeviceExt;
+
+use crate::reflect::{Binding, ShaderLayout};
+use crate::{ComputeRunner, Error};
+
+/// A buffer of `len` elements of type `T`.
+///
+/// Buffers can always be written from and read back to the host, so
+/// `COPY_DST` and `COPY_SRC` are added to the usage they are created with.
+/// The size is padded to a multiple of 4 bytes, as copies require.
+pub struct GpuBuffer<T: Pod> {
+    buffer: wgpu::Buffer,
+    len: usize,
+    usage: wgpu::BufferUsages,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Pod> GpuBuffer<T> {
+    /// A buffer of `len` zeroed elements.
+    pub fn new(device: &wgpu::Device, len: usize, usage: wgpu::BufferUsages) -> GpuBuffer<T> {
+        let usage = usage | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: padded_size(len * size_of::<T>()),
+            usage,
+            mapped_at_creation: false,
+        });
+        GpuBuffer {
+            buffer,
+            len,
+            usage,
+            _marker: PhantomData,
+        }
+    }
+
+    /// A buffer holding a copy of `data`.
+    pub fn from_slice(
+        device: &wgpu::Device,
+        data: &[T],
+        usage: wgpu::BufferUsages,
+    ) -> GpuBuffer<T> {
+        let usage = usage | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC;
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(data),
+            usage,
+        });
+        GpuBuffer {
+            buffer,
+            len: data.len(),
+            usage,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn usage(&self) -> wgpu::BufferUsages {
+        self.usage
+    }
+
+    /// The size of the contents in bytes, without padding.
+    pub fn size(&self) -> u64 {
+        (self.len * size_of::<T>()) as u64
+    }
+
+    /// The underlying buffer.
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Replace the contents of the buffer, which must have the same length.
+    pub fn write(&self, queue: &wgpu::Queue, data: &[T]) -> Result<(), Error> {
+        self.check_len(data.len())?;
+        let bytes: &[u8] = bytemuck::cast_slice(data);
+        if bytes.len() as u64 == padded_size(bytes.len()) {
+            queue.write_buffer(&self.buffer, 0, bytes);
+        } else {
+            // Writes must be a multiple of 4 bytes; the padding is ours.
+            let mut padded = bytes.to_vec();
+            padded.resize(padded_size(bytes.len()) as usize, 0);
+            queue.write_buffer(&self.buffer, 0, &padded);
+        }
+        Ok(())
+    }
+
+    /// Read the contents of the buffer back to the host.
+    pub async fn read(&self, runner: &ComputeRunner) -> Result<Vec<T>, Error> {
+        runner.read_back(&self.buffer, self.len).await
+    }
+
+    /// The whole buffer, to bind. The size bound is padded like the buffer.
+    pub fn as_binding(&self) -> wgpu::BindingResource<'_> {
+        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: &self.buffer,
+            offset: 0,
+            size: wgpu::BufferSize::new(padded_size(self.len * size_of::<T>())),
+        })
+    }
+
+    /// Check that the buffer can be bound at `group` and `binding` of
+    /// `layout`: the shader must declare a buffer there whose elements are
+    /// the size of `T`, with no other members before them, and as many of
+    /// them if their number is fixed. The usage must allow the binding too.
+    pub fn check(&self, layout: &ShaderLayout, group: u32, binding: u32) -> Result<(), Error> {
+        check_buffer(layout, group, binding, self.len, size_of::<T>(), self.usage)
+    }
+
+    fn check_len(&self, len: usize) -> Result<(), Error> {
+        if len == self.len {
+            Ok(())
+        } else {
+            Err(Error::InvalidInput(format!(
+                "buffer has {} elements, but {} were given",
+                self.len, len
+            )))
+        }
+    }
+}
+
+/// [`GpuBuffer::check`], for a buffer of `len` elements of `element_size`
+/// bytes, created with `usage`.
+fn check_buffer(
+    layout: &ShaderLayout,
+    group: u32,
+    binding: u32,
+    len: usize,
+    element_size: usize,
+    usage: wgpu::BufferUsages,
+) -> Result<(), Error> {
+    let b = layout.get(group, binding).ok_or_else(|| {
+        Error::Bindings(format!(
+            "group {}, binding {}: a buffer is bound, but the shader doesn't use one",
+            group, binding
+        ))
+    })?;
+    check_binding(b, len, element_size, usage).map_err(|e| Error::Bindings(format!("{}: {}", b, e)))
+}
+
+fn check_binding(
+    b: &Binding,
+    len: usize,
+    element_size: usize,
+    usage: wgpu::BufferUsages,
+) -> Result<(), String> {
+    let kind = b
+        .buffer_kind()
+        .ok_or("the shader doesn't declare a buffer there")?;
+    let needed = kind.usage() - wgpu::BufferUsages::COPY_DST;
+    if !usage.contains(needed) {
+        return Err(format!(
+            "bound as {}, but the buffer was created without {:?} usage",
+            kind, needed
+        ));
+    }
+    let element = match b.element {
+        Some(element) => element,
+        None => return Ok(()),
+    };
+    if element.offset != 0 {
+        return Err(format!(
+            "the shader's struct has {} bytes before its array, which a buffer of \
+             one element type can't describe",
+            element.offset
+        ));
+    }
+    if element.stride != element_size as u64 {
+        return Err(format!(
+            "elements are {} bytes on the host, but the shader's stride is {}",
+            element_size, element.stride
+        ));
+    }
+    match element.len {
+        Some(declared) if declared != len => Err(format!(
+            "the buffer has {} elements, but the shader declares {}",
+            len, declared
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Copies must be a multiple of 4 bytes; buffers are padded to match.
+pub(crate) fn padded_size(size: usize) -> u64 {
+    (size as u64).div_ceil(wgpu::COPY_BUFFER_ALIGNMENT) * wgpu::COPY_BUFFER_ALIGNMENT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHADER: &str = r#"
+[[block]]
+struct Params {
+    scale: f32;
+    bias: f32;
+};
+
+[[block]]
+struct Floats {
+    data: [[stride(4)]] array<f32>;
+};
+
+[[block]]
+struct Vec2s {
+    data: [[stride(8)]] array<vec2<f32>>;
+};
+
+[[block]]
+struct Fixed {
+    data: [[stride(4)]] array<u32, 16>;
+};
+
+[[block]]
+struct Counted {
+    n: u32;
+    data: [[stride(4)]] array<u32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: Params;
+
+[[group(0), binding(1)]]
+var<storage, read> input: Floats;
+
+[[group(0), binding(2)]]
+var<storage, read_write> points: Vec2s;
+
+[[group(0), binding(3)]]
+var<storage, read_write> fixed: Fixed;
+
+[[group(0), binding(4)]]
+var<storage, read_write> counted: Counted;
+
+[[group(0), binding(5)]]
+var image: texture_2d<f32>;
+
+[[stage(compute), workgroup_size(1)]]
+fn main() {
+    let p = input.data[0] * params.scale + params.bias;
+    points.data[0] = vec2<f32>(p, textureLoad(image, vec2<i32>(0, 0), 0).x);
+    fixed.data[0] = counted.data[counted.n];
+}
+"#;
+
+    const STORAGE: wgpu::BufferUsages = wgpu::BufferUsages::STORAGE;
+
+    fn layout() -> ShaderLayout {
+        ShaderLayout::from_wgsl(SHADER, &["main"]).unwrap()
+    }
+
+    /// Check a buffer of `len` `T`s with `usage` against `binding`, returning
+    /// the error message if there is one.
+    fn check<T>(binding: u32, len: usize, usage: wgpu::BufferUsages) -> Result<(), String> {
+        match check_buffer(&layout(), 0, binding, len, size_of::<T>(), usage) {
+            Ok(()) => Ok(()),
+            Err(Error::Bindings(msg)) => Err(msg),
+            Err(e) => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn matching_buffers_pass() {
+        assert_eq!(check::<[f32; 2]>(0, 1, wgpu::BufferUsages::UNIFORM), Ok(()));
+        assert_eq!(check::<f32>(1, 1000, STORAGE), Ok(()));
+        assert_eq!(check::<[f32; 2]>(2, 3, STORAGE), Ok(()));
+        assert_eq!(check::<u32>(3, 16, STORAGE), Ok(()));
+    }
+
+    #[test]
+    fn strides_must_match() {
+        assert_eq!(
+            check::<f32>(2, 6, STORAGE),
+            Err(
+                "group 0, binding 2 (`points`): elements are 4 bytes on the host, \
+                 but the shader's stride is 8"
+                    .into()
+            )
+        );
+        assert_eq!(
+            check::<f32>(0, 2, wgpu::BufferUsages::UNIFORM),
+            Err(
+                "group 0, binding 0 (`params`): elements are 4 bytes on the host, \
+                 but the shader's stride is 8"
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn fixed_lengths_must_match() {
+        assert_eq!(
+            check::<u32>(3, 15, STORAGE),
+            Err("group 0, binding 3 (`fixed`): the buffer has 15 elements, \
+                 but the shader declares 16"
+                .into())
+        );
+        assert_eq!(
+            check::<[f32; 2]>(0, 2, wgpu::BufferUsages::UNIFORM),
+            Err("group 0, binding 0 (`params`): the buffer has 2 elements, \
+                 but the shader declares 1"
+                .into())
+        );
+    }
+
+    #[test]
+    fn arrays_must_start_the_struct() {
+        assert_eq!(
+            check::<u32>(4, 8, STORAGE),
+            Err(
+                "group 0, binding 4 (`counted`): the shader's struct has 4 bytes before \
+                 its array, which a buffer of one element type can't describe"
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn usage_must_allow_the_binding() {
+        assert_eq!(
+            check::<f32>(1, 4, wgpu::BufferUsages::UNIFORM),
+            Err(
+                "group 0, binding 1 (`input`): bound as storage_read_only, but the buffer \
+                 was created without STORAGE usage"
+                    .into()
+            )
+        );
+        assert_eq!(
+            check::<[f32; 2]>(0, 1, STORAGE),
+            Err(
+                "group 0, binding 0 (`params`): bound as uniform, but the buffer \
+                 was created without UNIFORM usage"
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn bindings_must_be_buffers() {
+        assert_eq!(
+            check::<f32>(5, 4, STORAGE),
+            Err("group 0, binding 5 (`image`): the shader doesn't declare a buffer there".into())
+        );
+        assert_eq!(
+            check::<f32>(6, 4, STORAGE),
+            Err("group 0, binding 6: a buffer is bound, but the shader doesn't use one".into())
+        );
+    }
+}
@@ -0,0 +1,327 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Command line arguments.
+
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use compute_shader_hello::gemm::GemmDims;
+use compute_shader_hello::tune::WorkgroupSize;
+use compute_shader_hello::Dtype;
+
+#[derive(Parser)]
+#[command(about = "Run a compute shader over a buffer of data.")]
+pub struct Cli {
+    /// Use a software adapter instead of a GPU.
+    #[arg(long, global = true)]
+    pub fallback: bool,
+    /// Time every pass with timestamp queries and write the timings here as
+    /// a Chrome trace, for `chrome://tracing` or Perfetto.
+    #[arg(long, global = true)]
+    pub trace: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run a WGSL kernel over the contents of a file.
+    Run(RunArgs),
+    /// Run a kernel with the buffers described in a JSON manifest.
+    RunManifest(ManifestArgs),
+    /// Time repeated runs of a kernel and report statistics as JSON.
+    Bench(BenchArgs),
+    /// Find the fastest workgroup size for a kernel and record it in the
+    /// tuning cache.
+    Tune(TuneArgs),
+    /// Run the GPU prefix sum on random data and check it against the CPU.
+    Scan(ScanArgs),
+    /// Run the GPU radix sort on random data and check it against the CPU,
+    /// timing each stage.
+    Sort(SortArgs),
+    /// Check the GPU reductions against the CPU, and compare their
+    /// throughput with a naive atomic reduction.
+    Reduce(ReduceArgs),
+    /// Check the matrix multiplication kernels against the CPU and report
+    /// their GFLOP/s.
+    Gemm(GemmArgs),
+}
+
+/// Arguments describing a kernel and its input, shared by several commands.
+#[derive(Args)]
+pub struct KernelArgs {
+    /// Path to the shader: `.wgsl`, `.comp` (GLSL) or `.spv`, the last if
+    /// built with the `spirv` feature. The index helpers from
+    /// `src/shaders/index.wgsl` are prepended to WGSL. If a WGSL shader has
+    /// `workgroup_size(WORKGROUP_SIZE)` in place of a workgroup size, the
+    /// tuned size is filled in.
+    pub shader: PathBuf,
+    /// Entry point of the compute shader.
+    #[arg(long, default_value = "main")]
+    pub entry: String,
+    /// Input data, bound at group 0, binding 0: a `.npy` file, a `.npz`
+    /// archive, or raw little-endian data.
+    #[arg(long)]
+    pub input: PathBuf,
+    /// Array to use from a `.npz` input; needed if it holds more than one.
+    #[arg(long)]
+    pub array: Option<String>,
+    /// Element type of the input data; f32, f16, u32, i32 or u8. Defaults
+    /// to f32 for raw data, and must match the file for `.npy` and `.npz`.
+    #[arg(long)]
+    pub dtype: Option<Dtype>,
+    /// Number of workgroups as `x[,y[,z]]`; defaults to enough for one
+    /// invocation per element, spilling into y and z past the limit on
+    /// workgroups per dimension.
+    #[arg(long)]
+    pub workgroups: Option<Workgroups>,
+    /// Define a name for the WGSL preprocessor, as `NAME` or `NAME=VALUE`.
+    #[arg(long = "define", short = 'D', value_name = "NAME[=VALUE]")]
+    pub defines: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub kernel: KernelArgs,
+    /// Write the resulting buffer here instead of printing it, as `.npy`
+    /// or `.npz` with the shape of the input, or else as raw data.
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct ManifestArgs {
+    /// The manifest, listing the shader and the buffers it binds.
+    pub manifest: PathBuf,
+    /// Define a name for the WGSL preprocessor, as `NAME` or `NAME=VALUE`,
+    /// overriding the manifest's `defines`.
+    #[arg(long = "define", short = 'D', value_name = "NAME[=VALUE]")]
+    pub defines: Vec<String>,
+    /// Write the buffers marked `read_back` here instead of printing them,
+    /// as `.npz`, or as `.npy` if there is only one.
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct BenchArgs {
+    #[command(flatten)]
+    pub kernel: KernelArgs,
+    /// Untimed iterations before measuring.
+    #[arg(long, default_value_t = 10)]
+    pub warmup: usize,
+    /// Timed iterations.
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    pub iterations: u64,
+    /// Read the buffer back after every iteration, reporting how many
+    /// staging buffers that needed and how many were created.
+    #[arg(long)]
+    pub read_back: bool,
+    /// Write the JSON report here instead of to stdout.
+    #[arg(long)]
+    pub json: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct TuneArgs {
+    #[command(flatten)]
+    pub kernel: KernelArgs,
+    /// Workgroup sizes to try, as `x`, `xxy` or `xxyxz`.
+    #[arg(long, value_delimiter = ',', default_value = "32,64,128,256")]
+    pub candidates: Vec<WorkgroupSize>,
+    /// Invocations to cover as `x[,y[,z]]`; defaults to one per element.
+    #[arg(long)]
+    pub invocations: Option<Workgroups>,
+    /// Untimed iterations before measuring each candidate.
+    #[arg(long, default_value_t = 3)]
+    pub warmup: usize,
+    /// Timed iterations for each candidate.
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u64).range(1..))]
+    pub iterations: u64,
+    /// Tuning cache to update; defaults to the one the runner loads.
+    #[arg(long)]
+    pub cache: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct ScanArgs {
+    /// Element type; u32 or f32.
+    #[arg(long, default_value = "u32")]
+    pub dtype: Dtype,
+    /// Algorithm to check; both are checked by default.
+    #[arg(long)]
+    pub algorithm: Option<ScanAlgorithmArg>,
+    /// Compute an exclusive rather than inclusive scan.
+    #[arg(long)]
+    pub exclusive: bool,
+    /// Lengths to check; the defaults cover partial partitions and
+    /// multiple levels of partition totals.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "1,255,1024,1025,65537,1048576,1050000"
+    )]
+    pub len: Vec<usize>,
+    /// Seed for the random input data.
+    #[arg(long, default_value_t = 1)]
+    pub seed: u32,
+}
+
+#[derive(Args)]
+pub struct SortArgs {
+    /// Number of key bits sorted in each pass, from 1 to 8.
+    #[arg(long, default_value_t = 4)]
+    pub bits: u32,
+    /// Sort a value along with each key, and check the sort is stable.
+    #[arg(long)]
+    pub values: bool,
+    /// Lengths to check.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "1,255,256,257,65537,1048576"
+    )]
+    pub len: Vec<usize>,
+    /// Timed runs for each length; the median is reported.
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+    pub iterations: u64,
+    /// Seed for the random input data.
+    #[arg(long, default_value_t = 1)]
+    pub seed: u32,
+}
+
+#[derive(Args)]
+pub struct ReduceArgs {
+    /// Element type; u32 or f32. The atomic baseline only runs for u32.
+    #[arg(long, default_value = "u32")]
+    pub dtype: Dtype,
+    /// Operation to check; all are checked by default.
+    #[arg(long)]
+    pub op: Option<ReduceOpArg>,
+    /// Invocations per workgroup; a power of two up to 256.
+    #[arg(long, default_value_t = 256)]
+    pub workgroup_size: u32,
+    /// Lengths to check; the largest needs more workgroups than fit in one
+    /// dimension of a dispatch.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "1,255,1024,1025,65537,1048576,16777216"
+    )]
+    pub len: Vec<usize>,
+    /// Untimed iterations before measuring.
+    #[arg(long, default_value_t = 3)]
+    pub warmup: usize,
+    /// Timed iterations.
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u64).range(1..))]
+    pub iterations: u64,
+    /// Also write the benchmark reports here as JSON.
+    #[arg(long)]
+    pub json: Option<PathBuf>,
+    /// Seed for the random input data.
+    #[arg(long, default_value_t = 1)]
+    pub seed: u32,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ReduceOpArg {
+    Sum,
+    Min,
+    Max,
+    Argmin,
+}
+
+#[derive(Args)]
+pub struct GemmArgs {
+    /// Kernel to check; all are checked by default.
+    #[arg(long)]
+    pub variant: Option<GemmVariantArg>,
+    /// Problem sizes as `MxNxK`, or `N` for square matrices.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_parser = parse_gemm_dims,
+        default_value = "1,64,100x70x33,256,512"
+    )]
+    pub dims: Vec<GemmDims>,
+    /// Untimed iterations before measuring.
+    #[arg(long, default_value_t = 3)]
+    pub warmup: usize,
+    /// Timed iterations.
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u64).range(1..))]
+    pub iterations: u64,
+    /// Also write the benchmark reports here as JSON.
+    #[arg(long)]
+    pub json: Option<PathBuf>,
+    /// Seed for the random input data.
+    #[arg(long, default_value_t = 1)]
+    pub seed: u32,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum GemmVariantArg {
+    Naive,
+    Tiled,
+    RegisterBlocked,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ScanAlgorithmArg {
+    ReduceThenScan,
+    DecoupledLookback,
+}
+
+/// A dispatch size, parsed from a comma-separated list of up to three numbers.
+#[derive(Clone, Copy, Debug)]
+pub struct Workgroups(pub u32, pub u32, pub u32);
+
+impl FromStr for Workgroups {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let dims = s
+            .split(',')
+            .map(|d| d.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid workgroup count '{}': {}", s, e))?;
+        match dims[..] {
+            [x] => Ok(Workgroups(x, 1, 1)),
+            [x, y] => Ok(Workgroups(x, y, 1)),
+            [x, y, z] => Ok(Workgroups(x, y, z)),
+            _ => Err(format!("expected 1 to 3 dimensions, got '{}'", s)),
+        }
+    }
+}
+
+fn parse_gemm_dims(s: &str) -> Result<GemmDims, String> {
+    let dims = s
+        .split('x')
+        .map(|d| d.trim().parse::<u32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("invalid matrix size '{}': {}", s, e))?;
+    match dims[..] {
+        [n] if n > 0 => Ok(GemmDims { m: n, n, k: n }),
+        [m, n, k] if m > 0 && n > 0 && k > 0 => Ok(GemmDims { m, n, k }),
+        _ => Err(format!(
+            "expected N or MxNxK with non-zero sizes, got '{}'",
+            s
+        )),
+    }
+}
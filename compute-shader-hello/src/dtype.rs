@@ -0,0 +1,117 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Element types for buffers loaded from and written to files.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// The scalar type of the elements of a buffer.
+///
+/// WGSL has no 8 or 16-bit types, so `f16` and `u8` elements are packed into
+/// `u32` words, little-endian, and unpacked by the kernel (for example with
+/// `unpack2x16float`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Dtype {
+    F32,
+    F16,
+    U32,
+    I32,
+    U8,
+}
+
+impl Dtype {
+    /// Size of one element in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            Dtype::F32 | Dtype::U32 | Dtype::I32 => 4,
+            Dtype::F16 => 2,
+            Dtype::U8 => 1,
+        }
+    }
+
+    /// Whether elements are packed several to a `u32` on the GPU.
+    pub fn is_packed(self) -> bool {
+        self.size() < 4
+    }
+
+    /// Format raw little-endian bytes as a list of elements of this type.
+    pub fn format(self, bytes: &[u8]) -> String {
+        let words = || bytes.chunks_exact(4).map(|c| [c[0], c[1], c[2], c[3]]);
+        let elements: Vec<String> = match self {
+            Dtype::F32 => words()
+                .map(|w| format!("{:?}", f32::from_le_bytes(w)))
+                .collect(),
+            Dtype::U32 => words().map(|w| u32::from_le_bytes(w).to_string()).collect(),
+            Dtype::I32 => words().map(|w| i32::from_le_bytes(w).to_string()).collect(),
+            Dtype::F16 => bytes
+                .chunks_exact(2)
+                .map(|c| format!("{:?}", f16_to_f32(u16::from_le_bytes([c[0], c[1]]))))
+                .collect(),
+            Dtype::U8 => bytes.iter().map(|b| b.to_string()).collect(),
+        };
+        format!("[{}]", elements.join(", "))
+    }
+}
+
+/// Convert IEEE half precision bits to an `f32`.
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = ((bits >> 15) as u32) << 31;
+    let exp = ((bits >> 10) & 0x1f) as u32;
+    let mantissa = (bits & 0x3ff) as u32;
+    let magnitude = match exp {
+        // Zero and subnormals, which are normal as f32.
+        0 => (mantissa as f32 * (-24f32).exp2()).to_bits(),
+        // Infinity and NaN.
+        0x1f => 0x7f80_0000 | (mantissa << 13),
+        _ => ((exp + 127 - 15) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(sign | magnitude)
+}
+
+impl fmt::Display for Dtype {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Dtype::F32 => "f32",
+            Dtype::F16 => "f16",
+            Dtype::U32 => "u32",
+            Dtype::I32 => "i32",
+            Dtype::U8 => "u8",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Dtype {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "f32" => Ok(Dtype::F32),
+            "u32" => Ok(Dtype::U32),
+            "i32" => Ok(Dtype::I32),
+            "f16" => Ok(Dtype::F16),
+            "u8" => Ok(Dtype::U8),
+            _ => Err(format!(
+                "unknown dtype '{}' (expected f32, f16, u32, i32 or u8)",
+                s
+            )),
+        }
+    }
+}
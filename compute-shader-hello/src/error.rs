@@ -0,0 +1,114 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Errors reported by the compute runner.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// An error from setting up the GPU or running a kernel.
+#[derive(Debug)]
+pub enum Error {
+    /// No adapter matching the request was found. Holds all the adapters
+    /// that could be enumerated, for diagnosis.
+    NoAdapter {
+        force_fallback: bool,
+        available: Vec<wgpu::AdapterInfo>,
+    },
+    /// The adapter refused to create a device.
+    RequestDevice(wgpu::RequestDeviceError),
+    /// Mapping a buffer failed; names the buffer.
+    Map(String, wgpu::BufferAsyncError),
+    /// The shader failed to parse or validate; holds the formatted diagnostic.
+    Shader(String),
+    /// Reading or writing a file failed.
+    Io(PathBuf, io::Error),
+    /// A file was read but its contents couldn't be parsed.
+    Parse(PathBuf, String),
+    /// The resources bound to a shader don't match the ones it declares.
+    Bindings(String),
+    /// A pass graph is inconsistent: a pass uses an undeclared buffer, reads
+    /// one nothing writes, or the passes depend on each other in a cycle.
+    Graph(String),
+    /// Input data doesn't fit what the kernel expects.
+    InvalidInput(String),
+    /// A GPU result disagreed with the CPU reference.
+    Mismatch(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoAdapter {
+                force_fallback,
+                available,
+            } => {
+                if *force_fallback {
+                    write!(f, "no software fallback adapter found")?;
+                } else {
+                    write!(f, "no suitable GPU adapter found")?;
+                }
+                if available.is_empty() {
+                    write!(f, "; no adapters available on any backend")?;
+                } else {
+                    write!(f, "; available adapters:")?;
+                    for info in available {
+                        write!(
+                            f,
+                            "\n    {} ({:?}, {:?})",
+                            info.name, info.backend, info.device_type
+                        )?;
+                    }
+                }
+                if !force_fallback {
+                    write!(
+                        f,
+                        "\nto use a software adapter, pass --fallback or set {}=1",
+                        crate::FALLBACK_ENV
+                    )?;
+                }
+                Ok(())
+            }
+            Error::RequestDevice(e) => write!(f, "{}", e),
+            Error::Map(what, e) => write!(f, "mapping {}: {}", what, e),
+            Error::Shader(msg) => write!(f, "shader error:\n{}", msg),
+            Error::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            Error::Parse(path, msg) => write!(f, "{}: {}", path.display(), msg),
+            Error::Bindings(msg) => write!(f, "bindings don't match the shader: {}", msg),
+            Error::Graph(msg) => write!(f, "invalid pass graph: {}", msg),
+            Error::InvalidInput(msg) => write!(f, "invalid input: {}", msg),
+            Error::Mismatch(msg) => write!(f, "GPU result doesn't match CPU reference: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::RequestDevice(e) => Some(e),
+            Error::Map(_, e) => Some(e),
+            Error::Io(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<wgpu::RequestDeviceError> for Error {
+    fn from(e: wgpu::RequestDeviceError) -> Self {
+        Error::RequestDevice(e)
+    }
+}
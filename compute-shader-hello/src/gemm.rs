@@ -0,0 +1,254 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Matrix multiplication of row-major `f32` matrices.
+//!
+//! Three kernels of increasing sophistication are provided, see
+//! [`GemmVariant`]. Half precision variants would need the `SHADER_F16`
+//! feature, which wgpu 0.11 doesn't expose yet.
+
+use std::time::Duration;
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    BindingKind, BindingLayout, ComputeRunner, Error, Kernel, MAX_WORKGROUPS_PER_DIMENSION,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GemmVariant {
+    /// One invocation per output element, reading from global memory.
+    Naive,
+    /// 16x16 tiles of the inputs staged in workgroup memory.
+    Tiled,
+    /// 64x64 tiles, with each invocation computing a 4x4 block in registers.
+    RegisterBlocked,
+}
+
+impl GemmVariant {
+    pub const ALL: [GemmVariant; 3] = [
+        GemmVariant::Naive,
+        GemmVariant::Tiled,
+        GemmVariant::RegisterBlocked,
+    ];
+
+    /// Size of the square tile of C computed by one workgroup.
+    fn tile_size(self) -> u32 {
+        match self {
+            GemmVariant::Naive | GemmVariant::Tiled => 16,
+            GemmVariant::RegisterBlocked => 64,
+        }
+    }
+}
+
+/// Matrix dimensions: A is `m` x `k`, B is `k` x `n` and C is `m` x `n`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+#[repr(C)]
+pub struct GemmDims {
+    pub m: u32,
+    pub n: u32,
+    pub k: u32,
+}
+
+impl GemmDims {
+    /// Floating point operations in one multiplication, counting a multiply
+    /// and an add for each term.
+    pub fn flops(&self) -> u64 {
+        2 * self.m as u64 * self.n as u64 * self.k as u64
+    }
+}
+
+/// Compiled matrix multiplication kernels.
+pub struct Gemm {
+    naive: Kernel,
+    tiled: Kernel,
+    blocked: Kernel,
+}
+
+impl Gemm {
+    pub fn new(runner: &ComputeRunner) -> Result<Gemm, Error> {
+        let module = runner.create_shader_module(include_str!("shaders/gemm.wgsl"))?;
+        // The dimensions, then A and B, which are only read, then C.
+        let layout = [
+            BindingKind::Uniform,
+            BindingKind::StorageReadOnly,
+            BindingKind::StorageReadOnly,
+            BindingKind::Storage,
+        ]
+        .iter()
+        .zip(0..)
+        .map(|(&kind, binding)| BindingLayout {
+            group: 0,
+            binding,
+            kind,
+        })
+        .collect::<Vec<_>>();
+        let kernel = |entry| runner.create_kernel_with_layout(&module, entry, &layout);
+        Ok(Gemm {
+            naive: kernel("naive"),
+            tiled: kernel("tiled"),
+            blocked: kernel("blocked"),
+        })
+    }
+
+    /// Compute `c = a * b`, with the buffers given in that order, and wait
+    /// for it to complete.
+    ///
+    /// Returns the GPU time taken, if timestamp queries are available.
+    pub async fn multiply(
+        &self,
+        runner: &ComputeRunner,
+        buffers: [&wgpu::Buffer; 3],
+        dims: GemmDims,
+        variant: GemmVariant,
+    ) -> Result<Option<Duration>, Error> {
+        let pass = self.prepare(runner, buffers, dims, variant)?;
+        runner.submit(|encoder| pass.encode(runner, encoder)).await
+    }
+
+    /// Set up the multiplication `c = a * b`, so that it can be recorded
+    /// along with other work.
+    pub fn prepare<'a>(
+        &'a self,
+        runner: &ComputeRunner,
+        buffers: [&'a wgpu::Buffer; 3],
+        dims: GemmDims,
+        variant: GemmVariant,
+    ) -> Result<GemmPass<'a>, Error> {
+        let tile = variant.tile_size();
+        let workgroups = (dims.n.div_ceil(tile), dims.m.div_ceil(tile), 1);
+        if workgroups.0 > MAX_WORKGROUPS_PER_DIMENSION
+            || workgroups.1 > MAX_WORKGROUPS_PER_DIMENSION
+        {
+            return Err(Error::InvalidInput(format!(
+                "{}x{} output is too large for the {:?} kernel",
+                dims.m, dims.n, variant
+            )));
+        }
+        let kernel = match variant {
+            GemmVariant::Naive => &self.naive,
+            GemmVariant::Tiled => &self.tiled,
+            GemmVariant::RegisterBlocked => &self.blocked,
+        };
+        let params = runner
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::bytes_of(&dims),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+        Ok(GemmPass {
+            kernel,
+            params,
+            buffers,
+            workgroups,
+        })
+    }
+}
+
+/// A matrix multiplication, ready to be recorded.
+pub struct GemmPass<'a> {
+    kernel: &'a Kernel,
+    params: wgpu::Buffer,
+    buffers: [&'a wgpu::Buffer; 3],
+    workgroups: (u32, u32, u32),
+}
+
+impl<'a> GemmPass<'a> {
+    /// Record the multiplication into `encoder`.
+    pub fn encode(&self, runner: &ComputeRunner, encoder: &mut wgpu::CommandEncoder) {
+        let [a, b, c] = self.buffers;
+        let bindings = [&self.params, a, b, c];
+        runner.encode_kernel(encoder, self.kernel, &bindings, self.workgroups);
+    }
+}
+
+/// Multiply on the CPU, as a reference for checking the GPU results.
+pub fn gemm_cpu(a: &[f32], b: &[f32], dims: GemmDims) -> Vec<f32> {
+    let (m, n, k) = (dims.m as usize, dims.n as usize, dims.k as usize);
+    let mut c = vec![0.0; m * n];
+    for row in 0..m {
+        for i in 0..k {
+            let x = a[row * k + i];
+            for col in 0..n {
+                c[row * n + col] += x * b[i * n + col];
+            }
+        }
+    }
+    c
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reflect::ShaderLayout;
+
+    #[test]
+    fn layout_matches_the_shader() {
+        let entries = ["naive", "tiled", "blocked"];
+        let layout = ShaderLayout::from_wgsl(include_str!("shaders/gemm.wgsl"), &entries).unwrap();
+        let kinds = (0..4)
+            .map(|binding| layout.get(0, binding).and_then(|b| b.buffer_kind()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                Some(BindingKind::Uniform),
+                Some(BindingKind::StorageReadOnly),
+                Some(BindingKind::StorageReadOnly),
+                Some(BindingKind::Storage),
+            ]
+        );
+    }
+
+    #[test]
+    fn gpu_gemm_matches_the_cpu() {
+        let Some(runner) = ComputeRunner::for_tests() else {
+            return;
+        };
+        let gemm = Gemm::new(&runner).unwrap();
+        // Sizes that are and aren't multiples of the tiles.
+        let sizes = [(1, 1, 1), (64, 64, 64), (37, 70, 19), (65, 17, 130)];
+        for (m, n, k) in sizes {
+            let dims = GemmDims { m, n, k };
+            // Small integers keep every sum exact, whatever the order.
+            let matrix = |len: u32, seed: u32| {
+                (0..len)
+                    .map(|i| (i.wrapping_mul(seed) % 5) as f32 - 2.0)
+                    .collect::<Vec<_>>()
+            };
+            let a = matrix(m * k, 7919);
+            let b = matrix(k * n, 104729);
+            let c = vec![f32::NAN; (m * n) as usize];
+            let bufs = [runner.upload(&a), runner.upload(&b), runner.upload(&c)];
+            for variant in GemmVariant::ALL {
+                let buffers = [&bufs[0], &bufs[1], &bufs[2]];
+                pollster::block_on(gemm.multiply(&runner, buffers, dims, variant)).unwrap();
+                let result: Vec<f32> =
+                    pollster::block_on(runner.read_back(&bufs[2], c.len())).unwrap();
+                assert!(
+                    result == gemm_cpu(&a, &b, dims),
+                    "{:?} for {}x{}x{}",
+                    variant,
+                    m,
+                    n,
+                    k
+                );
+            }
+        }
+    }
+}
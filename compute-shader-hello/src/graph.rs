@@ -0,0 +1,743 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Multi-pass pipelines described as a graph of passes over named buffers.
+//!
+//! Algorithms like radix sort are sequences of passes sharing intermediate
+//! buffers. A [`Graph`] lists the buffers, and for each pass the kernel it
+//! dispatches and the buffers it reads and writes. Building it allocates the
+//! buffers the graph owns, checks each pass's bindings against its kernel,
+//! and orders the passes by their dependencies:
+//!
+//! * A pass that reads a buffer runs after the last pass declared before it
+//!   that writes the buffer. If there is none and the buffer has no initial
+//!   contents, it runs after the first pass that writes it, wherever that is
+//!   declared.
+//! * A pass that writes a buffer runs after the passes reading or writing
+//!   the contents it overwrites.
+//!
+//! So passes declared in the order they should run keep that order, as with
+//! ping-ponging between two buffers, while a pass consuming the output of one
+//! declared later waits for it. Passes that don't depend on each other keep
+//! their declaration order.
+//!
+//! All passes are recorded into one encoder. When the adapter supports
+//! timestamp queries, each pass is timed separately with a
+//! [`Profiler`], giving a per-stage breakdown in [`GraphTimings`].
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bytemuck::Pod;
+use wgpu::util::DeviceExt;
+
+use crate::buffer::padded_size;
+use crate::profile::Profiler;
+use crate::runner::encode_dispatch;
+use crate::{BindingKind, ComputeRunner, Error, Kernel};
+
+/// A description of a multi-pass pipeline; see the [module docs](self).
+#[derive(Default)]
+pub struct Graph<'a> {
+    buffers: Vec<BufferDecl<'a>>,
+    passes: Vec<PassDecl<'a>>,
+}
+
+struct BufferDecl<'a> {
+    name: String,
+    source: BufferSource<'a>,
+}
+
+enum BufferSource<'a> {
+    /// A buffer owned by the caller.
+    Import(&'a wgpu::Buffer),
+    /// A buffer of this size, with no contents until a pass writes it.
+    Transient(u64),
+    /// A buffer initialized with these bytes.
+    Init(Vec<u8>),
+}
+
+struct PassDecl<'a> {
+    name: String,
+    op: Op<'a>,
+    accesses: Vec<Access>,
+}
+
+enum Op<'a> {
+    Dispatch {
+        kernel: &'a Kernel,
+        workgroups: (u32, u32, u32),
+    },
+    Copy {
+        size: u64,
+    },
+}
+
+struct Access {
+    buffer: String,
+    /// Group and binding for dispatches; copies read their first access
+    /// and write their second.
+    binding: Option<(u32, u32)>,
+    write: bool,
+}
+
+impl<'a> Graph<'a> {
+    pub fn new() -> Graph<'a> {
+        Graph::default()
+    }
+
+    /// Add a buffer owned by the caller, such as the input and output of the
+    /// pipeline. Its contents count as initialized.
+    ///
+    /// Importing the same buffer under the same name again does nothing, so
+    /// that helpers like [`ScanPass::add_to_graph`] can be called repeatedly.
+    ///
+    /// [`ScanPass::add_to_graph`]: crate::scan::ScanPass::add_to_graph
+    pub fn import(&mut self, name: &str, buffer: &'a wgpu::Buffer) -> &mut Graph<'a> {
+        let imported = self.buffers.iter().any(|b| {
+            b.name == name && matches!(b.source, BufferSource::Import(i) if std::ptr::eq(i, buffer))
+        });
+        if imported {
+            return self;
+        }
+        self.add_buffer(name, BufferSource::Import(buffer))
+    }
+
+    /// Add an intermediate buffer of `size` bytes, allocated by the graph.
+    /// Its contents are undefined until a pass writes it.
+    pub fn buffer(&mut self, name: &str, size: u64) -> &mut Graph<'a> {
+        self.add_buffer(name, BufferSource::Transient(size))
+    }
+
+    /// Add a buffer allocated by the graph and initialized with `data`, such
+    /// as the parameters of a pass.
+    pub fn buffer_init<T: Pod>(&mut self, name: &str, data: &[T]) -> &mut Graph<'a> {
+        let bytes = bytemuck::cast_slice(data).to_vec();
+        self.add_buffer(name, BufferSource::Init(bytes))
+    }
+
+    fn add_buffer(&mut self, name: &str, source: BufferSource<'a>) -> &mut Graph<'a> {
+        self.buffers.push(BufferDecl {
+            name: name.into(),
+            source,
+        });
+        self
+    }
+
+    /// Add a pass dispatching `kernel`. The buffers it binds are declared
+    /// with the methods of the returned [`PassBuilder`].
+    pub fn dispatch(
+        &mut self,
+        name: &str,
+        kernel: &'a Kernel,
+        workgroups: (u32, u32, u32),
+    ) -> PassBuilder<'_, 'a> {
+        self.passes.push(PassDecl {
+            name: name.into(),
+            op: Op::Dispatch { kernel, workgroups },
+            accesses: Vec::new(),
+        });
+        PassBuilder {
+            pass: self.passes.last_mut().unwrap(),
+        }
+    }
+
+    /// Add a pass copying the first `size` bytes of `src` to `dst`.
+    pub fn copy(&mut self, name: &str, src: &str, dst: &str, size: u64) -> &mut Graph<'a> {
+        let access = |buffer: &str, write| Access {
+            buffer: buffer.into(),
+            binding: None,
+            write,
+        };
+        self.passes.push(PassDecl {
+            name: name.into(),
+            op: Op::Copy { size },
+            accesses: vec![access(src, false), access(dst, true)],
+        });
+        self
+    }
+
+    /// Allocate the graph's buffers, check the passes and put them in order.
+    pub fn build(self, runner: &ComputeRunner) -> Result<CompiledGraph<'a>, Error> {
+        let mut index = HashMap::new();
+        for (i, buffer) in self.buffers.iter().enumerate() {
+            if index.insert(buffer.name.as_str(), i).is_some() {
+                return Err(Error::Graph(format!(
+                    "buffer `{}` is declared twice",
+                    buffer.name
+                )));
+            }
+        }
+        let lookup = |pass: &PassDecl, name: &str| lookup(&index, pass, name);
+        let uses = self.uses(&index)?;
+
+        let buffers = self
+            .buffers
+            .iter()
+            .map(|decl| match &decl.source {
+                BufferSource::Import(buffer) => GraphBuffer::Import(buffer),
+                BufferSource::Transient(size) => GraphBuffer::Owned(
+                    runner.device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some(&decl.name),
+                        size: *size,
+                        usage: GRAPH_BUFFER_USAGE,
+                        mapped_at_creation: false,
+                    }),
+                    *size,
+                ),
+                BufferSource::Init(bytes) => GraphBuffer::Owned(
+                    runner
+                        .device
+                        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                            label: Some(&decl.name),
+                            contents: bytes,
+                            usage: GRAPH_BUFFER_USAGE,
+                        }),
+                    padded_size(bytes.len()),
+                ),
+            })
+            .collect::<Vec<_>>();
+        let sizes = buffers
+            .iter()
+            .filter_map(|buffer| match buffer {
+                GraphBuffer::Import(_) => None,
+                GraphBuffer::Owned(buffer, size) => Some((buffer, *size)),
+            })
+            .collect::<Vec<_>>();
+
+        let mut passes = Vec::with_capacity(self.passes.len());
+        for pass in &self.passes {
+            let op = match pass.op {
+                Op::Dispatch { kernel, workgroups } => {
+                    let mut bindings = Vec::with_capacity(pass.accesses.len());
+                    for access in &pass.accesses {
+                        let (group, binding) = access.binding.unwrap();
+                        check_access(pass, kernel, access, group, binding)?;
+                        let buffer = buffers[lookup(pass, &access.buffer)?].get();
+                        bindings.push((group, binding, buffer));
+                    }
+                    kernel
+                        .check_buffers(&bindings, &sizes)
+                        .map_err(|e| Error::Graph(format!("pass `{}`: {}", pass.name, e)))?;
+                    CompiledOp::Dispatch {
+                        kernel,
+                        bind_groups: runner.create_bind_groups(kernel, &bindings),
+                        workgroups,
+                    }
+                }
+                Op::Copy { size } => CompiledOp::Copy {
+                    src: lookup(pass, &pass.accesses[0].buffer)?,
+                    dst: lookup(pass, &pass.accesses[1].buffer)?,
+                    size,
+                },
+            };
+            passes.push(CompiledPass {
+                name: pass.name.clone(),
+                op,
+            });
+        }
+
+        let dependencies = dependencies(&self, &uses)?;
+        let order = order(&self, &dependencies)?;
+
+        // The passes are timed whenever the adapter supports it, and added
+        // to the runner's trace if it has one.
+        let profiler = Profiler::new(&runner.device).ok().map(|profiler| {
+            let profiler = match runner.trace() {
+                Some(trace) => profiler.with_trace(trace),
+                None => profiler,
+            };
+            Mutex::new(profiler)
+        });
+
+        Ok(CompiledGraph {
+            names: self.buffers.into_iter().map(|b| b.name).collect(),
+            buffers,
+            passes,
+            order,
+            profiler,
+        })
+    }
+}
+
+impl<'a> Graph<'a> {
+    /// The buffers each pass uses, and whether it writes them, with
+    /// repeated accesses to the same buffer merged.
+    fn uses(&self, index: &HashMap<&str, usize>) -> Result<Vec<Vec<(usize, bool)>>, Error> {
+        self.passes
+            .iter()
+            .map(|pass| {
+                let mut pass_uses: Vec<(usize, bool)> = Vec::new();
+                for access in &pass.accesses {
+                    let buffer = lookup(index, pass, &access.buffer)?;
+                    match pass_uses.iter_mut().find(|(b, _)| *b == buffer) {
+                        Some((_, write)) => *write |= access.write,
+                        None => pass_uses.push((buffer, access.write)),
+                    }
+                }
+                Ok(pass_uses)
+            })
+            .collect()
+    }
+}
+
+fn lookup(index: &HashMap<&str, usize>, pass: &PassDecl, name: &str) -> Result<usize, Error> {
+    index.get(name).copied().ok_or_else(|| {
+        Error::Graph(format!(
+            "pass `{}` uses buffer `{}`, which isn't declared",
+            pass.name, name
+        ))
+    })
+}
+
+/// Usage of the buffers a graph allocates.
+const GRAPH_BUFFER_USAGE: wgpu::BufferUsages = wgpu::BufferUsages::STORAGE
+    .union(wgpu::BufferUsages::UNIFORM)
+    .union(wgpu::BufferUsages::COPY_SRC)
+    .union(wgpu::BufferUsages::COPY_DST);
+
+/// Declares the buffers bound by a dispatch; returned by [`Graph::dispatch`].
+pub struct PassBuilder<'g, 'a> {
+    pass: &'g mut PassDecl<'a>,
+}
+
+impl<'g, 'a> PassBuilder<'g, 'a> {
+    /// Bind `buffer` at `group` and `binding`, for reading only.
+    pub fn read(self, group: u32, binding: u32, buffer: &str) -> PassBuilder<'g, 'a> {
+        self.access(group, binding, buffer, false)
+    }
+
+    /// Bind `buffer` at `group` and `binding`, for writing, and possibly
+    /// reading as well. The binding must be a read-write storage buffer.
+    pub fn write(self, group: u32, binding: u32, buffer: &str) -> PassBuilder<'g, 'a> {
+        self.access(group, binding, buffer, true)
+    }
+
+    fn access(self, group: u32, binding: u32, buffer: &str, write: bool) -> PassBuilder<'g, 'a> {
+        self.pass.accesses.push(Access {
+            buffer: buffer.into(),
+            binding: Some((group, binding)),
+            write,
+        });
+        self
+    }
+}
+
+/// Check that a pass declares writes only to bindings that allow them.
+fn check_access(
+    pass: &PassDecl,
+    kernel: &Kernel,
+    access: &Access,
+    group: u32,
+    binding: u32,
+) -> Result<(), Error> {
+    if pass
+        .accesses
+        .iter()
+        .filter(|a| a.binding == Some((group, binding)))
+        .count()
+        > 1
+    {
+        return Err(Error::Graph(format!(
+            "pass `{}` binds more than one buffer at group {}, binding {}",
+            pass.name, group, binding
+        )));
+    }
+    let kind = kernel
+        .layout
+        .get(group, binding)
+        .and_then(|b| b.buffer_kind());
+    match kind {
+        Some(kind) if access.write && kind != BindingKind::Storage => Err(Error::Graph(format!(
+            "pass `{}` writes `{}`, but group {}, binding {} is a {} binding",
+            pass.name, access.buffer, group, binding, kind
+        ))),
+        // Anything else is checked along with the rest of the bindings.
+        _ => Ok(()),
+    }
+}
+
+/// For each pass, the passes it must run after; see the [module docs](self).
+fn dependencies(graph: &Graph, uses: &[Vec<(usize, bool)>]) -> Result<Vec<BTreeSet<usize>>, Error> {
+    let n_buffers = graph.buffers.len();
+    let initialized = graph
+        .buffers
+        .iter()
+        .map(|b| !matches!(b.source, BufferSource::Transient(_)))
+        .collect::<Vec<_>>();
+    // The passes writing each buffer, in declaration order. Writer k
+    // produces version k + 1 of the contents; version 0 is the initial one.
+    let mut writers = vec![Vec::new(); n_buffers];
+    for (pass, pass_uses) in uses.iter().enumerate() {
+        for &(buffer, write) in pass_uses {
+            if write {
+                writers[buffer].push(pass);
+            }
+        }
+    }
+    let mut deps = vec![BTreeSet::new(); uses.len()];
+    // The passes reading each version of each buffer without writing it.
+    let mut readers: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+    for (pass, pass_uses) in uses.iter().enumerate() {
+        for &(buffer, write) in pass_uses {
+            let earlier = writers[buffer].iter().take_while(|&&w| w < pass).count();
+            let version = if write || earlier > 0 || initialized[buffer] {
+                earlier
+            } else if !writers[buffer].is_empty() {
+                1
+            } else {
+                return Err(Error::Graph(format!(
+                    "pass `{}` reads `{}`, but no pass writes it and it has no initial contents",
+                    graph.passes[pass].name, graph.buffers[buffer].name
+                )));
+            };
+            if version > 0 {
+                deps[pass].insert(writers[buffer][version - 1]);
+            }
+            if !write {
+                readers.entry((buffer, version)).or_default().push(pass);
+            }
+        }
+    }
+    for (buffer, buffer_writers) in writers.iter().enumerate() {
+        for (k, &writer) in buffer_writers.iter().enumerate() {
+            if let Some(r) = readers.get(&(buffer, k)) {
+                deps[writer].extend(r.iter().filter(|&&r| r != writer));
+            }
+        }
+    }
+    Ok(deps)
+}
+
+/// A topological order of the passes, keeping declaration order where the
+/// dependencies allow.
+fn order(graph: &Graph, dependencies: &[BTreeSet<usize>]) -> Result<Vec<usize>, Error> {
+    let mut remaining = dependencies.iter().map(|d| d.len()).collect::<Vec<_>>();
+    let mut ready = (0..dependencies.len())
+        .filter(|&p| remaining[p] == 0)
+        .collect::<BTreeSet<_>>();
+    let mut order = Vec::with_capacity(dependencies.len());
+    while let Some(pass) = ready.pop_first() {
+        order.push(pass);
+        for (other, deps) in dependencies.iter().enumerate() {
+            if deps.contains(&pass) {
+                remaining[other] -= 1;
+                if remaining[other] == 0 {
+                    ready.insert(other);
+                }
+            }
+        }
+    }
+    if order.len() < dependencies.len() {
+        let cycle = (0..dependencies.len())
+            .filter(|&p| remaining[p] > 0)
+            .map(|p| format!("`{}`", graph.passes[p].name))
+            .collect::<Vec<_>>();
+        return Err(Error::Graph(format!(
+            "passes {} depend on each other in a cycle",
+            cycle.join(", ")
+        )));
+    }
+    Ok(order)
+}
+
+enum GraphBuffer<'a> {
+    Import(&'a wgpu::Buffer),
+    /// A buffer the graph allocated, and its size.
+    Owned(wgpu::Buffer, u64),
+}
+
+impl<'a> GraphBuffer<'a> {
+    fn get(&self) -> &wgpu::Buffer {
+        match self {
+            GraphBuffer::Import(buffer) => buffer,
+            GraphBuffer::Owned(buffer, _) => buffer,
+        }
+    }
+}
+
+struct CompiledPass<'a> {
+    name: String,
+    op: CompiledOp<'a>,
+}
+
+enum CompiledOp<'a> {
+    Dispatch {
+        kernel: &'a Kernel,
+        bind_groups: Vec<wgpu::BindGroup>,
+        workgroups: (u32, u32, u32),
+    },
+    Copy {
+        src: usize,
+        dst: usize,
+        size: u64,
+    },
+}
+
+/// A graph with its buffers allocated and passes ordered, ready to be
+/// recorded.
+///
+/// Like [`ScanPass`](crate::scan::ScanPass), it can be run any number of
+/// times.
+pub struct CompiledGraph<'a> {
+    names: Vec<String>,
+    buffers: Vec<GraphBuffer<'a>>,
+    passes: Vec<CompiledPass<'a>>,
+    order: Vec<usize>,
+    profiler: Option<Mutex<Profiler>>,
+}
+
+impl<'a> CompiledGraph<'a> {
+    /// The buffer named `name`, for example to read back an intermediate
+    /// result.
+    pub fn buffer(&self, name: &str) -> Option<&wgpu::Buffer> {
+        let index = self.names.iter().position(|n| n == name)?;
+        Some(self.buffers[index].get())
+    }
+
+    /// The names of the passes, in the order they run.
+    pub fn pass_names(&self) -> impl Iterator<Item = &str> {
+        self.order.iter().map(|&p| self.passes[p].name.as_str())
+    }
+
+    /// Record the passes into `encoder`. If profiling is enabled on the
+    /// runner, each pass is timed in a scope of its own.
+    pub fn encode(&self, runner: &ComputeRunner, encoder: &mut wgpu::CommandEncoder) {
+        for &p in &self.order {
+            runner.profile_scope(encoder, &self.passes[p].name, |encoder| {
+                self.encode_pass(encoder, p)
+            });
+        }
+    }
+
+    fn encode_pass(&self, encoder: &mut wgpu::CommandEncoder, pass: usize) {
+        match &self.passes[pass].op {
+            CompiledOp::Dispatch {
+                kernel,
+                bind_groups,
+                workgroups,
+            } => encode_dispatch(encoder, kernel, bind_groups, *workgroups),
+            CompiledOp::Copy { src, dst, size } => encoder.copy_buffer_to_buffer(
+                self.buffers[*src].get(),
+                0,
+                self.buffers[*dst].get(),
+                0,
+                *size,
+            ),
+        }
+    }
+
+    /// Run the passes and wait for them to complete.
+    ///
+    /// Each pass is timed if the adapter supports timestamp queries, whether
+    /// or not profiling is enabled on the runner.
+    pub async fn run(&self, runner: &ComputeRunner) -> Result<GraphTimings, Error> {
+        let mut frame = None;
+        let total = runner
+            .submit(|encoder| match &self.profiler {
+                Some(profiler) => {
+                    let mut profiler = profiler.lock().unwrap();
+                    for &p in &self.order {
+                        let name = &self.passes[p].name;
+                        profiler.scope(&runner.device, encoder, name, |encoder| {
+                            self.encode_pass(encoder, p)
+                        });
+                    }
+                    frame = profiler.resolve(&runner.device, encoder);
+                }
+                None => self.encode(runner, encoder),
+            })
+            .await?;
+        let passes = match frame {
+            Some(frame) => frame
+                .read(&runner.device, &runner.queue)
+                .await?
+                .into_iter()
+                .map(|timing| PassTiming {
+                    duration: Some(timing.duration()),
+                    name: timing.label,
+                })
+                .collect(),
+            None => self
+                .order
+                .iter()
+                .map(|&p| PassTiming {
+                    name: self.passes[p].name.clone(),
+                    duration: None,
+                })
+                .collect(),
+        };
+        Ok(GraphTimings { passes, total })
+    }
+}
+
+/// The GPU time of one pass.
+#[derive(Clone, Debug)]
+pub struct PassTiming {
+    pub name: String,
+    /// `None` if timestamp queries aren't available.
+    pub duration: Option<Duration>,
+}
+
+/// GPU times from running a graph.
+#[derive(Clone, Debug)]
+pub struct GraphTimings {
+    /// Each pass, in the order they ran.
+    pub passes: Vec<PassTiming>,
+    /// The whole graph, if timestamp queries are available.
+    pub total: Option<Duration>,
+}
+
+impl GraphTimings {
+    /// The total time of the passes with each name, in the order the names
+    /// first ran. Passes repeated with the same name, like the stages of
+    /// each pass of a radix sort, are added up.
+    pub fn by_name(&self) -> Vec<PassTiming> {
+        let mut totals: Vec<PassTiming> = Vec::new();
+        for pass in &self.passes {
+            match totals.iter_mut().find(|t| t.name == pass.name) {
+                Some(total) => {
+                    total.duration = total.duration.zip(pass.duration).map(|(a, b)| a + b)
+                }
+                None => totals.push(pass.clone()),
+            }
+        }
+        totals
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The dependencies and order of a graph's passes, without allocating
+    /// anything.
+    fn schedule(graph: &Graph) -> Result<(Vec<Vec<usize>>, Vec<usize>), String> {
+        let index = graph
+            .buffers
+            .iter()
+            .enumerate()
+            .map(|(i, b)| (b.name.as_str(), i))
+            .collect();
+        let result = graph.uses(&index).and_then(|uses| {
+            let deps = dependencies(graph, &uses)?;
+            let order = order(graph, &deps)?;
+            Ok((
+                deps.into_iter().map(|d| d.into_iter().collect()).collect(),
+                order,
+            ))
+        });
+        result.map_err(|e| match e {
+            Error::Graph(msg) => msg,
+            e => panic!("expected a graph error, got {}", e),
+        })
+    }
+
+    /// A graph of copies between `buffers`, each given by its name and
+    /// whether it has initial contents.
+    fn copies<'a>(buffers: &[(&str, bool)], passes: &[(&str, &str, &str)]) -> Graph<'a> {
+        let mut graph = Graph::new();
+        for &(name, init) in buffers {
+            if init {
+                graph.buffer_init(name, &[0u32]);
+            } else {
+                graph.buffer(name, 4);
+            }
+        }
+        for &(name, src, dst) in passes {
+            graph.copy(name, src, dst, 4);
+        }
+        graph
+    }
+
+    #[test]
+    fn reads_follow_writes() {
+        let buffers = [("a", true), ("b", false), ("c", false)];
+        let in_order = copies(&buffers, &[("ab", "a", "b"), ("bc", "b", "c")]);
+        assert_eq!(
+            schedule(&in_order).unwrap(),
+            (vec![vec![], vec![0]], vec![0, 1])
+        );
+
+        // A pass reading a buffer with no contents yet waits for the first
+        // pass writing it, even one declared later.
+        let reversed = copies(&buffers, &[("bc", "b", "c"), ("ab", "a", "b")]);
+        assert_eq!(
+            schedule(&reversed).unwrap(),
+            (vec![vec![1], vec![]], vec![1, 0])
+        );
+    }
+
+    #[test]
+    fn writes_follow_reads_of_what_they_overwrite() {
+        let buffers = [("x", true), ("y", false)];
+        let ping_pong = copies(
+            &buffers,
+            &[("xy", "x", "y"), ("yx", "y", "x"), ("xy again", "x", "y")],
+        );
+        assert_eq!(
+            schedule(&ping_pong).unwrap(),
+            (vec![vec![], vec![0], vec![0, 1]], vec![0, 1, 2])
+        );
+
+        // Overwriting initial contents waits for the pass reading them.
+        let buffers = [("a", true), ("b", false), ("c", true)];
+        let overwrite = copies(&buffers, &[("ab", "a", "b"), ("ca", "c", "a")]);
+        assert_eq!(
+            schedule(&overwrite).unwrap(),
+            (vec![vec![], vec![0]], vec![0, 1])
+        );
+    }
+
+    #[test]
+    fn independent_passes_keep_their_order() {
+        let buffers = [("a", true), ("b", false), ("c", false)];
+        let graph = copies(&buffers, &[("ac", "a", "c"), ("ab", "a", "b")]);
+        assert_eq!(
+            schedule(&graph).unwrap(),
+            (vec![vec![], vec![]], vec![0, 1])
+        );
+    }
+
+    #[test]
+    fn cycles_are_errors() {
+        let buffers = [("a", false), ("b", false), ("c", true)];
+        let graph = copies(
+            &buffers,
+            &[("ab", "a", "b"), ("cc", "c", "c"), ("ba", "b", "a")],
+        );
+        assert_eq!(
+            schedule(&graph).unwrap_err(),
+            "passes `ab`, `ba` depend on each other in a cycle"
+        );
+    }
+
+    #[test]
+    fn reads_need_contents() {
+        let buffers = [("a", false), ("b", false)];
+        let graph = copies(&buffers, &[("ab", "a", "b")]);
+        assert_eq!(
+            schedule(&graph).unwrap_err(),
+            "pass `ab` reads `a`, but no pass writes it and it has no initial contents"
+        );
+        let graph = copies(&buffers, &[("ab", "a", "z")]);
+        assert_eq!(
+            schedule(&graph).unwrap_err(),
+            "pass `ab` uses buffer `z`, which isn't declared"
+        );
+    }
+}
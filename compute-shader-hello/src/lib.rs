@@ -0,0 +1,110 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! A small library for running compute shaders with wgpu.
+//!
+//! The [`ComputeRunner`] owns the device and queue, and takes care of the
+//! boilerplate of uploading data, binding buffers, dispatching a kernel and
+//! reading the results back.
+
+mod adapter;
+pub mod bench;
+mod buffer;
+mod dtype;
+mod error;
+pub mod gemm;
+pub mod graph;
+pub mod npy;
+pub mod preprocess;
+pub mod profile;
+pub mod radix_sort;
+mod readback;
+pub mod reduce;
+pub mod reflect;
+mod runner;
+pub mod scan;
+mod source;
+pub mod spec;
+pub mod staging;
+pub mod tune;
+
+pub use adapter::{request_adapter, AdapterOptions, FALLBACK_ENV};
+pub use buffer::GpuBuffer;
+pub use dtype::Dtype;
+pub use error::Error;
+pub use runner::{
+    check_grid, grid, grid_for, BindingKind, BindingLayout, ComputeRunner, Kernel, INDEX_WGSL,
+    MAX_WORKGROUPS_PER_DIMENSION,
+};
+pub use source::{bytes_to_u32, KernelSource};
+
+#[cfg(test)]
+mod tests {
+    use crate::reduce::{ReduceElement, ReduceOp, Reducer, LOAD_ELEMENTS, LOAD_PARTIALS};
+    use crate::scan::{ScanElement, Scanner};
+    use crate::{reflect, tune, INDEX_WGSL};
+
+    fn check(name: &str, source: &str) {
+        let module = reflect::parse(source).unwrap_or_else(|e| panic!("{}: {}", name, e));
+        if let Err(e) = reflect::validate(&module) {
+            panic!("{}: {}", name, e);
+        }
+    }
+
+    fn check_scan<T: ScanElement>(name: &str) {
+        for source in Scanner::<T>::sources() {
+            check(name, &source);
+        }
+    }
+
+    fn check_reduce<T: ReduceElement>(name: &str) {
+        for op in [
+            ReduceOp::Sum,
+            ReduceOp::Min,
+            ReduceOp::Max,
+            ReduceOp::ArgMin,
+        ] {
+            for load in [LOAD_ELEMENTS, LOAD_PARTIALS] {
+                for workgroup_size in [1, 64, 256] {
+                    check(
+                        &format!("{} {} with workgroup size {}", name, op, workgroup_size),
+                        &Reducer::<T>::source(op, load, workgroup_size),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Every shader the library builds passes naga's validation, as
+    /// creating its kernels would fail otherwise.
+    #[test]
+    fn shaders_validate() {
+        check_scan::<u32>("scan u32");
+        check_scan::<f32>("scan f32");
+        check_reduce::<u32>("reduce u32");
+        check_reduce::<f32>("reduce f32");
+        check("reduce_atomic", include_str!("shaders/reduce_atomic.wgsl"));
+        check("radix_sort", include_str!("shaders/radix_sort.wgsl"));
+        check("gemm", include_str!("shaders/gemm.wgsl"));
+        check(
+            "shader",
+            &tune::instantiate(
+                &format!("{}{}", INDEX_WGSL, include_str!("shader.wgsl")),
+                tune::DEFAULT_WORKGROUP_SIZE,
+            ),
+        );
+    }
+}
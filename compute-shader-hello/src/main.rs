@@ -16,141 +16,783 @@
 
 //! A simple application to run a compute shader.
 
-use std::time::Instant;
-
-use wgpu::util::DeviceExt;
-
-use bytemuck;
-
-async fn run() {
-    let instance = wgpu::Instance::new(wgpu::Backends::PRIMARY);
-    let adapter = instance.request_adapter(&Default::default()).await.unwrap();
-    let features = adapter.features();
-    let (device, queue) = adapter
-        .request_device(
-            &wgpu::DeviceDescriptor {
-                label: None,
-                features: features & wgpu::Features::TIMESTAMP_QUERY,
-                limits: Default::default(),
-            },
-            None,
-        )
-        .await
-        .unwrap();
-    let query_set = if features.contains(wgpu::Features::TIMESTAMP_QUERY) {
-        Some(device.create_query_set(&wgpu::QuerySetDescriptor {
-            count: 2,
-            ty: wgpu::QueryType::Timestamp,
-            label: None,
-        }))
-    } else {
-        None
-    };
+mod cli;
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+
+use compute_shader_hello::bench::{BenchOptions, BenchReport, Stats};
+use compute_shader_hello::gemm::{gemm_cpu, Gemm, GemmVariant};
+use compute_shader_hello::preprocess::Preprocessor;
+use compute_shader_hello::profile::Trace;
+use compute_shader_hello::radix_sort::RadixSorter;
+use compute_shader_hello::reduce::{reduce_cpu, AtomicReducer, ReduceElement, ReduceOp, Reducer};
+use compute_shader_hello::scan::{scan_cpu, ScanAlgorithm, ScanElement, ScanKind, Scanner};
+use compute_shader_hello::spec::KernelSpec;
+use compute_shader_hello::tune::{self, TuneCache, TunedSize, WorkgroupSize};
+use compute_shader_hello::{
+    check_grid, npy, reflect, AdapterOptions, ComputeRunner, Dtype, Error, GpuBuffer, Kernel,
+    KernelSource, INDEX_WGSL,
+};
+
+use cli::{
+    BenchArgs, Cli, Command, GemmArgs, GemmVariantArg, KernelArgs, ManifestArgs, ReduceArgs,
+    ReduceOpArg, RunArgs, ScanAlgorithmArg, ScanArgs, SortArgs, TuneArgs,
+};
+
+/// Options shared by all commands.
+struct Options {
+    adapter: AdapterOptions,
+    /// Collects the timing of every pass, when `--trace` is given.
+    trace: Option<Trace>,
+}
+
+impl Options {
+    /// Create a runner, profiling it if a trace was asked for.
+    async fn runner(&self) -> Result<ComputeRunner, Error> {
+        let mut runner = ComputeRunner::with_options(&self.adapter).await?;
+        if let Some(trace) = &self.trace {
+            runner.enable_profiling(trace)?;
+        }
+        Ok(runner)
+    }
+}
+
+/// Run the built-in example shader over a small fixed input.
+async fn run_example(options: &Options) -> Result<(), Error> {
+    let runner = options.runner().await?;
 
     let start_instant = Instant::now();
-    let cs_module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-        label: None,
-        //source: wgpu::ShaderSource::SpirV(bytes_to_u32(include_bytes!("alu.spv")).into()),
-        source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-    });
+    let source = format!("{}{}", INDEX_WGSL, include_str!("shader.wgsl"));
+    let (kernel, workgroup_size) =
+        runner.create_tuned_kernel(&source, "main", tune::DEFAULT_WORKGROUP_SIZE)?;
     println!("shader compilation {:?}", start_instant.elapsed());
     let input_f = &[1.0f32, 2.0f32];
-    let input : &[u8] = bytemuck::bytes_of(input_f);
-    let input_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: None,
-        contents: input,
-        usage: wgpu::BufferUsages::STORAGE
-            | wgpu::BufferUsages::COPY_DST
-            | wgpu::BufferUsages::COPY_SRC,
-    });
-    let output_buf = device.create_buffer(&wgpu::BufferDescriptor {
-        label: None,
-        size: input.len() as u64,
-        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
-        mapped_at_creation: false,
-    });
-    // This works if the buffer is initialized, otherwise reads all 0, for some reason.
-    let query_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: None,
-        contents: &[0; 16],
-        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
-    });
-
-    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        label: None,
-        entries: &[
-            wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: false },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
+    let input_buf = GpuBuffer::from_slice(&runner.device, input_f, wgpu::BufferUsages::STORAGE);
+    input_buf.check(&kernel.layout, 0, 0)?;
+
+    let workgroups = tune::workgroups_for((input_buf.len() as u32, 1, 1), workgroup_size);
+    let elapsed = runner
+        .run_kernel(&kernel, &[input_buf.buffer()], workgroups)
+        .await?;
+    let data = input_buf.read(&runner).await?;
+    println!("data: {:?}", data);
+    if let Some(elapsed) = elapsed {
+        println!(
+            "compute shader elapsed: {:?}ms",
+            elapsed.as_secs_f64() * 1e3
+        );
+    }
+    Ok(())
+}
+
+/// A kernel's source and input data, read from the files named on the
+/// command line.
+struct KernelFiles {
+    /// The kernel source. WGSL has the index helpers prepended so that
+    /// kernels can handle more elements than fit in one dimension of a
+    /// dispatch.
+    source: KernelSource,
+    /// The workgroup size the kernel declares, if it isn't tunable.
+    workgroup_size: WorkgroupSize,
+    input: npy::Array,
+    /// Name of the input array, used for `.npz` output.
+    input_name: String,
+}
+
+impl KernelFiles {
+    fn read(args: &KernelArgs) -> Result<KernelFiles, Error> {
+        let preprocessor = Preprocessor::new().defines(&args.defines);
+        let source = match preprocessor.load_kernel(&args.shader)? {
+            KernelSource::Wgsl(wgsl) => KernelSource::Wgsl(format!("{}{}", INDEX_WGSL, wgsl)),
+            other => other,
+        };
+        let path = &args.input;
+        let (input_name, input) = npy::load_any(
+            path,
+            args.array.as_deref(),
+            args.dtype.unwrap_or(Dtype::F32),
+        )?;
+        let mismatch = |msg| Error::InvalidInput(format!("{}: {}", path.display(), msg));
+        if let Some(dtype) = args.dtype.filter(|&d| d != input.dtype) {
+            return Err(mismatch(format!(
+                "data is {}, but --dtype {} was given",
+                input.dtype, dtype
+            )));
+        }
+        if input.is_empty() {
+            return Err(mismatch("no elements".to_string()));
+        }
+        // Check against the binding the shader declares, if it can be
+        // parsed; otherwise the error is reported when the shader is built.
+        let parsed = match source.as_wgsl().filter(|wgsl| tune::is_tunable(wgsl)) {
+            Some(wgsl) => reflect::parse(&tune::instantiate(wgsl, tune::DEFAULT_WORKGROUP_SIZE)),
+            None => source.parse(),
+        };
+        let mut workgroup_size = WorkgroupSize(1, 1, 1);
+        if let Ok(module) = parsed {
+            if let Some(element) = reflect::buffer_element(&module, 0, 0) {
+                element.check(input.dtype, input.len()).map_err(mismatch)?;
+            }
+            if let Some(size) = reflect::workgroup_size(&module, &args.entry) {
+                workgroup_size = size;
+            }
+        }
+        Ok(KernelFiles {
+            source,
+            workgroup_size,
+            input,
+            input_name,
+        })
+    }
+
+    /// The WGSL source, if it is tunable.
+    fn tunable_source(&self) -> Option<&str> {
+        self.source.as_wgsl().filter(|wgsl| tune::is_tunable(wgsl))
+    }
+}
+
+/// The extension of a path, if any.
+fn extension(path: &Path) -> Option<&str> {
+    path.extension().and_then(|e| e.to_str())
+}
+
+/// A kernel loaded from the files named on the command line.
+struct LoadedKernel {
+    runner: ComputeRunner,
+    kernel: Kernel,
+    input_buf: wgpu::Buffer,
+    files: KernelFiles,
+    workgroups: (u32, u32, u32),
+}
+
+impl LoadedKernel {
+    async fn load(options: &Options, args: &KernelArgs) -> Result<LoadedKernel, Error> {
+        let files = KernelFiles::read(args)?;
+        if let Some(wg) = args.workgroups {
+            check_grid((wg.0, wg.1, wg.2))?;
+        }
+        let n_elements = files.input.len();
+        let runner = options.runner().await?;
+        // Tunable kernels get the tuned workgroup size if there is one. Either
+        // way, one invocation is dispatched per element.
+        let (kernel, size) = match files.tunable_source() {
+            Some(wgsl) => {
+                runner.create_tuned_kernel(wgsl, &args.entry, tune::DEFAULT_WORKGROUP_SIZE)?
+            }
+            None => (
+                runner.create_kernel_from_source(&files.source, &args.entry)?,
+                files.workgroup_size,
+            ),
+        };
+        let default_workgroups = tune::workgroups_for((n_elements as u32, 1, 1), size);
+        let input_buf = runner.upload(&files.input.data);
+        let input_size =
+            (files.input.data.len() as u64).next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT);
+        kernel.check_buffers(&[(0, 0, &input_buf)], &[(&input_buf, input_size)])?;
+        let workgroups = match args.workgroups {
+            Some(wg) => (wg.0, wg.1, wg.2),
+            None => default_workgroups,
+        };
+        Ok(LoadedKernel {
+            runner,
+            kernel,
+            input_buf,
+            files,
+            workgroups,
+        })
+    }
+}
+
+/// Run a kernel from a file over an input file.
+async fn run_file(options: &Options, args: &RunArgs) -> Result<(), Error> {
+    let loaded = LoadedKernel::load(options, &args.kernel).await?;
+    let runner = &loaded.runner;
+    let elapsed = runner
+        .run_kernel(&loaded.kernel, &[&loaded.input_buf], loaded.workgroups)
+        .await?;
+    let input = &loaded.files.input;
+    let output = npy::Array {
+        data: runner
+            .read_back(&loaded.input_buf, input.data.len())
+            .await?,
+        ..input.clone()
+    };
+    match &args.output {
+        Some(path) => match extension(path) {
+            Some("npy") => npy::save(path, &output)?,
+            Some("npz") => npy::save_npz(path, &[(&loaded.files.input_name, &output)])?,
+            _ => std::fs::write(path, &output.data).map_err(|e| Error::Io(path.clone(), e))?,
+        },
+        None => println!("data: {}", output.dtype.format(&output.data)),
+    }
+    if let Some(elapsed) = elapsed {
+        println!(
+            "compute shader elapsed: {:?}ms",
+            elapsed.as_secs_f64() * 1e3
+        );
+    }
+    Ok(())
+}
+
+/// Run a kernel described by a manifest.
+async fn run_manifest(options: &Options, args: &ManifestArgs) -> Result<(), Error> {
+    let preprocessor = Preprocessor::new().defines(&args.defines);
+    let spec = KernelSpec::load(&args.manifest, &preprocessor)?;
+    let runner = options.runner().await?;
+    let kernel = spec.build(&runner)?;
+    let elapsed = kernel.run(&runner).await?;
+    let outputs = kernel.read_back(&runner).await?;
+    match &args.output {
+        Some(path) => match (extension(path), outputs.as_slice()) {
+            (Some("npy"), [(_, output)]) => npy::save(path, output)?,
+            (Some("npy"), _) => {
+                return Err(Error::InvalidInput(format!(
+                    "{} buffers are read back, but .npy holds one; use .npz",
+                    outputs.len()
+                )))
+            }
+            _ => {
+                let arrays = outputs
+                    .iter()
+                    .map(|(name, array)| (name.as_str(), array))
+                    .collect::<Vec<_>>();
+                npy::save_npz(path, &arrays)?
+            }
+        },
+        None => {
+            for (name, output) in &outputs {
+                println!("{}: {}", name, output.dtype.format(&output.data));
+            }
+        }
+    }
+    if let Some(elapsed) = elapsed {
+        println!(
+            "compute shader elapsed: {:?}ms",
+            elapsed.as_secs_f64() * 1e3
+        );
+    }
+    Ok(())
+}
+
+/// Benchmark a kernel from a file and emit a JSON report.
+async fn bench_file(options: &Options, args: &BenchArgs) -> Result<(), Error> {
+    let loaded = LoadedKernel::load(options, &args.kernel).await?;
+    let bench_options = BenchOptions {
+        warmup: args.warmup,
+        iterations: args.iterations as usize,
+    };
+    let runner = &loaded.runner;
+    let samples = if args.read_back {
+        let record = |encoder: &mut wgpu::CommandEncoder| {
+            runner.encode_kernel(
+                encoder,
+                &loaded.kernel,
+                &[&loaded.input_buf],
+                loaded.workgroups,
+            )
+        };
+        let size = loaded.files.input.data.len() as u64;
+        runner
+            .bench_with_read_back(&bench_options, record, &loaded.input_buf, size)
+            .await?
+    } else {
+        runner
+            .bench(
+                &loaded.kernel,
+                &[&loaded.input_buf],
+                loaded.workgroups,
+                &bench_options,
+            )
+            .await?
+    };
+    if args.read_back {
+        eprintln!(
+            "staging buffers: {} needed, {} created",
+            samples.staging.requests, samples.staging.allocations
+        );
+    }
+    // The buffer is bound read-write, so count it as read once and written once.
+    let report = BenchReport::new(
+        &loaded.runner,
+        &args.kernel.shader.display().to_string(),
+        &bench_options,
+        &samples,
+        loaded.files.input.len() as u64,
+        2 * loaded.files.input.data.len() as u64,
+    );
+    let json = serde_json::to_string_pretty(&report).unwrap();
+    match &args.json {
+        Some(path) => std::fs::write(path, json).map_err(|e| Error::Io(path.clone(), e)),
+        None => {
+            println!("{}", json);
+            Ok(())
+        }
+    }
+}
+
+/// Tune the workgroup size of a kernel from a file, and record the fastest in
+/// the tuning cache.
+async fn tune_file(options: &Options, args: &TuneArgs) -> Result<(), Error> {
+    if args.kernel.workgroups.is_some() {
+        return Err(Error::InvalidInput(
+            "tune derives the number of workgroups from each candidate size; use --invocations"
+                .into(),
+        ));
+    }
+    let files = KernelFiles::read(&args.kernel)?;
+    let source = files.tunable_source().ok_or_else(|| {
+        Error::InvalidInput(format!(
+            "{} isn't WGSL with `{}` to tune",
+            args.kernel.shader.display(),
+            tune::PLACEHOLDER
+        ))
+    })?;
+    let runner = options.runner().await?;
+    if runner.query_set.is_none() {
+        println!("timestamp queries unavailable, timing with the CPU clock");
+    }
+    let input_buf = runner.upload(&files.input.data);
+    let invocations = match args.invocations {
+        Some(inv) => (inv.0, inv.1, inv.2),
+        None => (files.input.len() as u32, 1, 1),
+    };
+    let bench_options = BenchOptions {
+        warmup: args.warmup,
+        iterations: args.iterations as usize,
+    };
+    let candidates = tune::tune(
+        &runner,
+        source,
+        &args.kernel.entry,
+        &[&input_buf],
+        invocations,
+        &args.candidates,
+        &bench_options,
+    )
+    .await?;
+    for candidate in &candidates {
+        println!(
+            "{:>9}: median {:.3}ms, p95 {:.3}ms",
+            candidate.workgroup_size.to_string(),
+            candidate.stats_ms.median,
+            candidate.stats_ms.p95
+        );
+    }
+    let best = tune::fastest(&candidates).unwrap();
+    println!("fastest: {}", best.workgroup_size);
+
+    let cache_path = args.cache.clone().unwrap_or_else(TuneCache::default_path);
+    let mut cache = TuneCache::load(&cache_path)?;
+    cache.insert(
+        &runner.adapter_info,
+        source,
+        &args.kernel.entry,
+        TunedSize {
+            workgroup_size: best.workgroup_size,
+            median_ms: best.stats_ms.median,
+            timer: best.timer,
+        },
+    );
+    cache.save(&cache_path)?;
+    println!("saved to {}", cache_path.display());
+    Ok(())
+}
+
+/// Deterministic pseudo-random test data (xorshift32).
+fn random_u32s(n: usize, seed: u32) -> Vec<u32> {
+    let mut x = seed.max(1);
+    (0..n)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x
+        })
+        .collect()
+}
+
+/// Check the GPU scan against the CPU reference on random data.
+async fn check_scan(options: &Options, args: &ScanArgs) -> Result<(), Error> {
+    let runner = options.runner().await?;
+    match args.dtype {
+        Dtype::U32 => check_scan_typed(&runner, args, |x| x).await,
+        // Small integers keep the f32 sums exact, so they compare equal.
+        Dtype::F32 => check_scan_typed(&runner, args, |x| (x % 4) as f32).await,
+        dtype => Err(Error::InvalidInput(format!(
+            "scan supports u32 and f32, not {}",
+            dtype
+        ))),
+    }
+}
+
+async fn check_scan_typed<T: ScanElement + PartialEq + std::fmt::Debug>(
+    runner: &ComputeRunner,
+    args: &ScanArgs,
+    from_u32: impl Fn(u32) -> T,
+) -> Result<(), Error> {
+    let scanner = Scanner::<T>::new(runner)?;
+    let kind = if args.exclusive {
+        ScanKind::Exclusive
+    } else {
+        ScanKind::Inclusive
+    };
+    let algorithms = match args.algorithm {
+        Some(ScanAlgorithmArg::ReduceThenScan) => vec![ScanAlgorithm::ReduceThenScan],
+        Some(ScanAlgorithmArg::DecoupledLookback) => vec![ScanAlgorithm::DecoupledLookback],
+        None => vec![
+            ScanAlgorithm::ReduceThenScan,
+            ScanAlgorithm::DecoupledLookback,
         ],
-    });
-    let compute_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: None,
-        bind_group_layouts: &[&bind_group_layout],
-        push_constant_ranges: &[],
-    });
-    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-        label: None,
-        layout: Some(&compute_pipeline_layout),
-        module: &cs_module,
-        entry_point: "main",
-    });
-
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        layout: &bind_group_layout,
-        entries: &[wgpu::BindGroupEntry {
-            binding: 0,
-            resource: input_buf.as_entire_binding(),
-        }],
-    });
-
-    let mut encoder = device.create_command_encoder(&Default::default());
-    if let Some(query_set) = &query_set {
-        encoder.write_timestamp(query_set, 0);
-    }
-    {
-        let mut cpass = encoder.begin_compute_pass(&Default::default());
-        cpass.set_pipeline(&pipeline);
-        cpass.set_bind_group(0, &bind_group, &[]);
-        cpass.dispatch(input_f.len() as u32, 1, 1);
-    }
-    if let Some(query_set) = &query_set {
-        encoder.write_timestamp(query_set, 1);
-    }
-    encoder.copy_buffer_to_buffer(&input_buf, 0, &output_buf, 0, input.len() as u64);
-    if let Some(query_set) = &query_set {
-        encoder.resolve_query_set(query_set, 0..2, &query_buf, 0);
-    }
-    queue.submit(Some(encoder.finish()));
-
-    let buf_slice = output_buf.slice(..);
-    let buf_future = buf_slice.map_async(wgpu::MapMode::Read);
-    let query_slice = query_buf.slice(..);
-    let _query_future = query_slice.map_async(wgpu::MapMode::Read);
-    println!("pre-poll {:?}", std::time::Instant::now());
-    device.poll(wgpu::Maintain::Wait);
-    println!("post-poll {:?}", std::time::Instant::now());
-    if buf_future.await.is_ok() {
-        let data_raw = &*buf_slice.get_mapped_range();
-        let data : &[f32] = bytemuck::cast_slice(data_raw);
-        println!("data: {:?}", &*data);
-    }
-    if features.contains(wgpu::Features::TIMESTAMP_QUERY) {
-        let ts_period = queue.get_timestamp_period();
-        let ts_data_raw = &*query_slice.get_mapped_range();
-        let ts_data : &[u64] = bytemuck::cast_slice(ts_data_raw);
-        println!("compute shader elapsed: {:?}ms", (ts_data[1] - ts_data[0]) as f64 * ts_period as f64 * 1e-6);
+    };
+    let mut failures = Vec::new();
+    for &len in &args.len {
+        let input: Vec<T> = random_u32s(len, args.seed)
+            .into_iter()
+            .map(&from_u32)
+            .collect();
+        let expected = scan_cpu(&input, kind);
+        for &algorithm in &algorithms {
+            let buf = runner.upload(&input);
+            let elapsed = scanner.scan(runner, &buf, len, kind, algorithm).await?;
+            let result: Vec<T> = runner.read_back(&buf, len).await?;
+            let timing = elapsed.map_or(String::new(), |d| format!(" in {:?}", d));
+            match result.iter().zip(&expected).position(|(a, b)| a != b) {
+                None => println!("{:?} {:?} len {}: ok{}", algorithm, kind, len, timing),
+                Some(i) => {
+                    let msg = format!(
+                        "{:?} {:?} len {}: index {}: gpu {:?}, cpu {:?}",
+                        algorithm, kind, len, i, result[i], expected[i]
+                    );
+                    println!("{}", msg);
+                    failures.push(msg);
+                }
+            }
+        }
+    }
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Mismatch(failures.join("; ")))
+    }
+}
+
+/// Check the GPU radix sort against `slice::sort` on random data.
+async fn check_sort(options: &Options, args: &SortArgs) -> Result<(), Error> {
+    let runner = options.runner().await?;
+    let sorter = RadixSorter::new(&runner)?;
+    let mut failures = Vec::new();
+    for &len in &args.len {
+        let keys = random_u32s(len, args.seed);
+        // The original index as the value shows whether equal keys kept
+        // their order.
+        let values: Vec<u32> = (0..len as u32).collect();
+        let mut expected: Vec<(u32, u32)> = keys.iter().copied().zip(values.clone()).collect();
+        if args.values {
+            expected.sort_by_key(|&(k, _)| k);
+        } else {
+            expected.sort();
+        }
+
+        let keys_buf = runner.upload(&keys);
+        let values_buf = runner.upload(&values);
+        let values_arg = args.values.then_some(&values_buf);
+        let mut durations = Vec::new();
+        // The time of each stage in each iteration, when the GPU reports it.
+        let mut stages: Vec<(String, Vec<Duration>)> = Vec::new();
+        for _ in 0..args.iterations {
+            runner
+                .queue
+                .write_buffer(&keys_buf, 0, bytemuck::cast_slice(&keys));
+            runner
+                .queue
+                .write_buffer(&values_buf, 0, bytemuck::cast_slice(&values));
+            let start = Instant::now();
+            let timings = sorter
+                .sort_timed(&runner, &keys_buf, values_arg, len, args.bits)
+                .await?;
+            durations.push(timings.total.unwrap_or_else(|| start.elapsed()));
+            for stage in timings.by_name() {
+                let Some(duration) = stage.duration else {
+                    continue;
+                };
+                match stages.iter_mut().find(|(name, _)| *name == stage.name) {
+                    Some((_, samples)) => samples.push(duration),
+                    None => stages.push((stage.name, vec![duration])),
+                }
+            }
+        }
+        let median = Stats::from_durations(&durations).median;
+        let mut rate = format!("{:.3}ms, {:.1} Mkeys/s", median, len as f64 / median / 1e3);
+        if !stages.is_empty() {
+            let breakdown = stages
+                .iter()
+                .map(|(name, samples)| {
+                    format!("{} {:.3}ms", name, Stats::from_durations(samples).median)
+                })
+                .collect::<Vec<_>>();
+            rate = format!("{} ({})", rate, breakdown.join(", "));
+        }
+
+        let result_keys: Vec<u32> = runner.read_back(&keys_buf, len).await?;
+        let result_values: Vec<u32> = runner.read_back(&values_buf, len).await?;
+        let mismatch = (0..len).find(|&i| {
+            result_keys[i] != expected[i].0 || (args.values && result_values[i] != expected[i].1)
+        });
+        match mismatch {
+            None => println!("len {}: ok, {}", len, rate),
+            Some(i) => {
+                let msg = format!(
+                    "len {}: index {}: gpu ({}, {}), cpu ({}, {})",
+                    len, i, result_keys[i], result_values[i], expected[i].0, expected[i].1
+                );
+                println!("{}", msg);
+                failures.push(msg);
+            }
+        }
+    }
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Mismatch(failures.join("; ")))
+    }
+}
+
+/// Check the GPU reductions against the CPU reference on random data, and
+/// benchmark them against the atomic baseline.
+async fn check_reduce(options: &Options, args: &ReduceArgs) -> Result<(), Error> {
+    let runner = options.runner().await?;
+    match args.dtype {
+        Dtype::U32 => check_reduce_typed(&runner, args, |x| x).await,
+        // Small integers keep the f32 sums exact, so they compare equal.
+        Dtype::F32 => check_reduce_typed(&runner, args, |x| (x % 4) as f32).await,
+        dtype => Err(Error::InvalidInput(format!(
+            "reduce supports u32 and f32, not {}",
+            dtype
+        ))),
+    }
+}
+
+async fn check_reduce_typed<T: ReduceElement + std::fmt::Debug>(
+    runner: &ComputeRunner,
+    args: &ReduceArgs,
+    from_u32: impl Fn(u32) -> T,
+) -> Result<(), Error> {
+    let ops = match args.op {
+        Some(ReduceOpArg::Sum) => vec![ReduceOp::Sum],
+        Some(ReduceOpArg::Min) => vec![ReduceOp::Min],
+        Some(ReduceOpArg::Max) => vec![ReduceOp::Max],
+        Some(ReduceOpArg::Argmin) => vec![ReduceOp::ArgMin],
+        None => vec![
+            ReduceOp::Sum,
+            ReduceOp::Min,
+            ReduceOp::Max,
+            ReduceOp::ArgMin,
+        ],
+    };
+    let atomic = match args.dtype {
+        Dtype::U32 => Some(AtomicReducer::new(runner)?),
+        _ => None,
+    };
+    let bench_options = BenchOptions {
+        warmup: args.warmup,
+        iterations: args.iterations as usize,
+    };
+    let mut reports = Vec::new();
+    let mut failures = Vec::new();
+    for &op in &ops {
+        let reducer = Reducer::<T>::new(runner, op, args.workgroup_size)?;
+        for &len in &args.len {
+            let name = format!("{} {} len {}", op, args.dtype, len);
+            let input: Vec<T> = random_u32s(len, args.seed)
+                .into_iter()
+                .map(&from_u32)
+                .collect();
+            let expected = reduce_cpu(&input, op);
+            let buf = runner.upload(&input);
+            let result = reducer.reduce(runner, &buf, len).await?;
+            if result != expected {
+                let msg = format!("{}: gpu {:?}, cpu {:?}", name, result, expected);
+                println!("{}", msg);
+                failures.push(msg);
+                continue;
+            }
+
+            // Each element is read once.
+            let bytes = (len * std::mem::size_of::<T>()) as u64;
+            let pass = reducer.prepare(runner, &buf, len);
+            let samples = runner
+                .bench_with(&bench_options, |encoder| pass.encode(runner, encoder))
+                .await?;
+            let report = BenchReport::new(
+                runner,
+                &format!("reduce {} tree", name),
+                &bench_options,
+                &samples,
+                len as u64,
+                bytes,
+            );
+            let mut line = format!(
+                "{}: ok, tree {:.3}ms {:.2} GB/s",
+                name, report.stats_ms.median, report.throughput.gb_per_sec
+            );
+            reports.push(report);
+
+            if let Some(atomic) = atomic.as_ref().filter(|_| op != ReduceOp::ArgMin) {
+                let pass = atomic.prepare(runner, &buf, len, op)?;
+                let samples = runner
+                    .bench_with(&bench_options, |encoder| pass.encode(runner, encoder))
+                    .await?;
+                let result: Vec<T> = runner.read_back(pass.output(), 1).await?;
+                if result[0] != expected.value {
+                    let msg = format!("{}: atomic {:?}, cpu {:?}", name, result[0], expected.value);
+                    println!("{}", msg);
+                    failures.push(msg);
+                    continue;
+                }
+                let report = BenchReport::new(
+                    runner,
+                    &format!("reduce {} atomic", name),
+                    &bench_options,
+                    &samples,
+                    len as u64,
+                    bytes,
+                );
+                line.push_str(&format!(
+                    ", atomic {:.3}ms {:.2} GB/s",
+                    report.stats_ms.median, report.throughput.gb_per_sec
+                ));
+                reports.push(report);
+            }
+            println!("{}", line);
+        }
+    }
+    if let Some(path) = &args.json {
+        let json = serde_json::to_string_pretty(&reports).unwrap();
+        std::fs::write(path, json).map_err(|e| Error::Io(path.clone(), e))?;
+    }
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Mismatch(failures.join("; ")))
+    }
+}
+
+/// Check the matrix multiplication kernels against the CPU reference on
+/// random data, and benchmark them.
+async fn check_gemm(options: &Options, args: &GemmArgs) -> Result<(), Error> {
+    let runner = options.runner().await?;
+    let gemm = Gemm::new(&runner)?;
+    let variants = match args.variant {
+        Some(GemmVariantArg::Naive) => vec![GemmVariant::Naive],
+        Some(GemmVariantArg::Tiled) => vec![GemmVariant::Tiled],
+        Some(GemmVariantArg::RegisterBlocked) => vec![GemmVariant::RegisterBlocked],
+        None => GemmVariant::ALL.to_vec(),
+    };
+    let bench_options = BenchOptions {
+        warmup: args.warmup,
+        iterations: args.iterations as usize,
+    };
+    let mut reports = Vec::new();
+    let mut failures = Vec::new();
+    for &dims in &args.dims {
+        let (m, n, k) = (dims.m as usize, dims.n as usize, dims.k as usize);
+        // Small integers keep the f32 sums exact, so they compare equal.
+        let small = |x: u32| (x % 8) as f32 - 4.0;
+        let a: Vec<f32> = random_u32s(m * k, args.seed)
+            .into_iter()
+            .map(small)
+            .collect();
+        let b: Vec<f32> = random_u32s(k * n, args.seed.wrapping_add(1))
+            .into_iter()
+            .map(small)
+            .collect();
+        let expected = gemm_cpu(&a, &b, dims);
+        let a_buf = runner.upload(&a);
+        let b_buf = runner.upload(&b);
+        for &variant in &variants {
+            let name = format!("{:?} {}x{}x{}", variant, m, n, k);
+            let c_buf = runner.upload(&vec![0.0f32; m * n]);
+            let pass = gemm.prepare(&runner, [&a_buf, &b_buf, &c_buf], dims, variant)?;
+            runner
+                .submit(|encoder| pass.encode(&runner, encoder))
+                .await?;
+            let result: Vec<f32> = runner.read_back(&c_buf, m * n).await?;
+            if let Some(i) = result.iter().zip(&expected).position(|(x, y)| x != y) {
+                let msg = format!(
+                    "{}: row {}, col {}: gpu {}, cpu {}",
+                    name,
+                    i / n,
+                    i % n,
+                    result[i],
+                    expected[i]
+                );
+                println!("{}", msg);
+                failures.push(msg);
+                continue;
+            }
+
+            let samples = runner
+                .bench_with(&bench_options, |encoder| pass.encode(&runner, encoder))
+                .await?;
+            // Count each input and the output once, the least any kernel
+            // could touch.
+            let bytes = 4 * (m * k + k * n + m * n) as u64;
+            let report = BenchReport::new(
+                &runner,
+                &format!("gemm {}", name),
+                &bench_options,
+                &samples,
+                (m * n) as u64,
+                bytes,
+            )
+            .with_flops(dims.flops());
+            println!(
+                "{}: ok, {:.3}ms {:.1} GFLOP/s",
+                name,
+                report.stats_ms.median,
+                report.throughput.gflop_per_sec.unwrap()
+            );
+            reports.push(report);
+        }
+    }
+    if let Some(path) = &args.json {
+        let json = serde_json::to_string_pretty(&reports).unwrap();
+        std::fs::write(path, json).map_err(|e| Error::Io(path.clone(), e))?;
+    }
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Mismatch(failures.join("; ")))
     }
 }
 
 fn main() {
-    pollster::block_on(run());
+    let cli = Cli::parse();
+    let mut adapter = AdapterOptions::from_env();
+    adapter.force_fallback |= cli.fallback;
+    let options = Options {
+        adapter,
+        trace: cli.trace.as_ref().map(|_| Trace::new()),
+    };
+    let mut result = match &cli.command {
+        None => pollster::block_on(run_example(&options)),
+        Some(Command::Run(args)) => pollster::block_on(run_file(&options, args)),
+        Some(Command::RunManifest(args)) => pollster::block_on(run_manifest(&options, args)),
+        Some(Command::Bench(args)) => pollster::block_on(bench_file(&options, args)),
+        Some(Command::Tune(args)) => pollster::block_on(tune_file(&options, args)),
+        Some(Command::Scan(args)) => pollster::block_on(check_scan(&options, args)),
+        Some(Command::Sort(args)) => pollster::block_on(check_sort(&options, args)),
+        Some(Command::Reduce(args)) => pollster::block_on(check_reduce(&options, args)),
+        Some(Command::Gemm(args)) => pollster::block_on(check_gemm(&options, args)),
+    };
+    if let (Some(path), Some(trace)) = (&cli.trace, &options.trace) {
+        result = result.and(trace.write(path));
+    }
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
 }
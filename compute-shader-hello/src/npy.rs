@@ -0,0 +1,564 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Reading and writing NumPy `.npy` and `.npz` files.
+//!
+//! Only little-endian, C-ordered arrays of the [`Dtype`]s are supported.
+//! `.npz` archives must be uncompressed, as written by `numpy.savez`;
+//! `numpy.savez_compressed` would need a deflate implementation.
+
+use std::path::Path;
+
+use crate::{Dtype, Error};
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// An array loaded from or to be saved to a `.npy` file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Array {
+    pub dtype: Dtype,
+    pub shape: Vec<usize>,
+    /// The elements in C order, as little-endian bytes.
+    pub data: Vec<u8>,
+}
+
+impl Array {
+    /// A one-dimensional array of raw little-endian bytes.
+    pub fn from_bytes(dtype: Dtype, data: Vec<u8>) -> Result<Array, String> {
+        if data.len() % dtype.size() != 0 {
+            return Err(format!(
+                "{} bytes is not a multiple of the {}-byte {} element size",
+                data.len(),
+                dtype.size(),
+                dtype
+            ));
+        }
+        Ok(Array {
+            dtype,
+            shape: vec![data.len() / dtype.size()],
+            data,
+        })
+    }
+
+    /// Number of elements, the product of the shape.
+    pub fn len(&self) -> usize {
+        self.shape.iter().product()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Parse the contents of a `.npy` file.
+    pub fn parse(bytes: &[u8]) -> Result<Array, String> {
+        if !bytes.starts_with(MAGIC) || bytes.len() < 10 {
+            return Err("not a .npy file".into());
+        }
+        let (header_len, header_start) = match bytes[6] {
+            1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+            2 | 3 if bytes.len() >= 12 => (
+                u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize,
+                12,
+            ),
+            v => return Err(format!("unsupported .npy version {}", v)),
+        };
+        let data_start = header_start + header_len;
+        let header = bytes
+            .get(header_start..data_start)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .ok_or("truncated or invalid .npy header")?;
+
+        let descr = dict_value(header, "descr")?;
+        let descr = descr.trim_matches(|c| c == '\'' || c == '"');
+        let dtype = dtype_from_descr(descr)?;
+        if dict_value(header, "fortran_order")? != "False" {
+            return Err("Fortran-ordered arrays are not supported".into());
+        }
+        let shape = dict_value(header, "shape")?;
+        let shape = shape
+            .trim_start_matches('(')
+            .trim_end_matches(')')
+            .split(',')
+            .map(str::trim)
+            .filter(|d| !d.is_empty())
+            .map(|d| d.parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("invalid shape {}", shape))?;
+
+        let array = Array {
+            dtype,
+            shape,
+            data: bytes[data_start..].to_vec(),
+        };
+        let expected = array.len() * dtype.size();
+        if array.data.len() != expected {
+            return Err(format!(
+                "shape {:?} of {} needs {} bytes of data, found {}",
+                array.shape,
+                dtype,
+                expected,
+                array.data.len()
+            ));
+        }
+        Ok(array)
+    }
+
+    /// Encode as the contents of a `.npy` file, in format version 1.0.
+    pub fn to_npy(&self) -> Vec<u8> {
+        let shape = match &self.shape[..] {
+            [n] => format!("({},)", n),
+            dims => format!(
+                "({})",
+                dims.iter()
+                    .map(|d| d.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        let mut header = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+            descr(self.dtype),
+            shape
+        );
+        // The header is padded with spaces so that the data is 64-byte
+        // aligned, and ends with a newline.
+        let unpadded = MAGIC.len() + 4 + header.len() + 1;
+        header.push_str(&" ".repeat(unpadded.next_multiple_of(64) - unpadded));
+        header.push('\n');
+
+        let mut out = Vec::with_capacity(MAGIC.len() + 4 + header.len() + self.data.len());
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&[1, 0]);
+        out.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        out.extend_from_slice(header.as_bytes());
+        out.extend_from_slice(&self.data);
+        out
+    }
+}
+
+/// Load a `.npy` file.
+pub fn load(path: &Path) -> Result<Array, Error> {
+    let bytes = std::fs::read(path).map_err(|e| Error::Io(path.into(), e))?;
+    Array::parse(&bytes).map_err(|e| Error::Parse(path.into(), e))
+}
+
+/// Save an array as a `.npy` file.
+pub fn save(path: &Path, array: &Array) -> Result<(), Error> {
+    std::fs::write(path, array.to_npy()).map_err(|e| Error::Io(path.into(), e))
+}
+
+/// Load all the arrays in a `.npz` archive, with their names.
+pub fn load_npz(path: &Path) -> Result<Vec<(String, Array)>, Error> {
+    let bytes = std::fs::read(path).map_err(|e| Error::Io(path.into(), e))?;
+    let parse_error = |e| Error::Parse(path.into(), e);
+    zip::read_stored(&bytes)
+        .map_err(parse_error)?
+        .into_iter()
+        .map(|(name, data)| {
+            let array = Array::parse(data).map_err(|e| format!("{}: {}", name, e));
+            let name = name.strip_suffix(".npy").unwrap_or(&name).to_string();
+            Ok((name, array.map_err(parse_error)?))
+        })
+        .collect()
+}
+
+/// Save named arrays as an uncompressed `.npz` archive.
+pub fn save_npz(path: &Path, arrays: &[(&str, &Array)]) -> Result<(), Error> {
+    let files: Vec<(String, Vec<u8>)> = arrays
+        .iter()
+        .map(|(name, array)| (format!("{}.npy", name), array.to_npy()))
+        .collect();
+    std::fs::write(path, zip::write_stored(&files)).map_err(|e| Error::Io(path.into(), e))
+}
+
+/// Load an array from a `.npy` or `.npz` file, or raw little-endian
+/// `raw_dtype` elements from a file with any other extension.
+///
+/// Returns the name of the array along with it; `arr_0` unless it came from
+/// an archive.
+pub fn load_any(
+    path: &Path,
+    name: Option<&str>,
+    raw_dtype: Dtype,
+) -> Result<(String, Array), Error> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("npy") => Ok(("arr_0".to_string(), load(path)?)),
+        Some("npz") => select(path, load_npz(path)?, name),
+        _ => {
+            let data = std::fs::read(path).map_err(|e| Error::Io(path.into(), e))?;
+            let array = Array::from_bytes(raw_dtype, data)
+                .map_err(|e| Error::InvalidInput(format!("{}: {}", path.display(), e)))?;
+            Ok(("arr_0".to_string(), array))
+        }
+    }
+}
+
+/// Pick the array named `name` from those in a `.npz` archive, or the only
+/// one if no name is given.
+pub fn select(
+    path: &Path,
+    arrays: Vec<(String, Array)>,
+    name: Option<&str>,
+) -> Result<(String, Array), Error> {
+    let names = || {
+        arrays
+            .iter()
+            .map(|(n, _)| n.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let error = |msg: String| Err(Error::InvalidInput(format!("{}: {}", path.display(), msg)));
+    match name {
+        Some(name) => match arrays.iter().position(|(n, _)| n == name) {
+            Some(i) => Ok(arrays.into_iter().nth(i).unwrap()),
+            None => error(format!("no array '{}'; it has {}", name, names())),
+        },
+        None if arrays.len() == 1 => Ok(arrays.into_iter().next().unwrap()),
+        None if arrays.is_empty() => error("archive is empty".into()),
+        None => error(format!("name one of {}", names())),
+    }
+}
+
+fn descr(dtype: Dtype) -> &'static str {
+    match dtype {
+        Dtype::F32 => "<f4",
+        Dtype::F16 => "<f2",
+        Dtype::U32 => "<u4",
+        Dtype::I32 => "<i4",
+        Dtype::U8 => "|u1",
+    }
+}
+
+fn dtype_from_descr(descr: &str) -> Result<Dtype, String> {
+    match descr {
+        "<f4" => Ok(Dtype::F32),
+        "<f2" => Ok(Dtype::F16),
+        "<u4" => Ok(Dtype::U32),
+        "<i4" => Ok(Dtype::I32),
+        "|u1" | "<u1" => Ok(Dtype::U8),
+        _ if descr.starts_with('>') => Err(format!("big-endian dtype {} is not supported", descr)),
+        _ => Err(format!(
+            "unsupported dtype {} (expected f32, f16, u32, i32 or u8)",
+            descr
+        )),
+    }
+}
+
+/// The text of the value for `key` in the Python dict literal of a header.
+fn dict_value<'a>(header: &'a str, key: &str) -> Result<&'a str, String> {
+    let missing = || format!("header has no '{}'", key);
+    let start = header.find(&format!("'{}'", key)).ok_or_else(missing)? + key.len() + 2;
+    let rest = header[start..]
+        .trim_start()
+        .strip_prefix(':')
+        .ok_or_else(missing)?
+        .trim_start();
+    // The value ends at the first comma or closing brace outside a tuple.
+    let end = if rest.starts_with('(') {
+        rest.find(')').map(|i| i + 1)
+    } else {
+        rest.find([',', '}'])
+    };
+    Ok(rest[..end.ok_or_else(missing)?].trim())
+}
+
+/// Just enough of the zip format for `.npz` files: stored (uncompressed)
+/// entries, without zip64 extensions.
+mod zip {
+    const LOCAL_HEADER: u32 = 0x0403_4b50;
+    const CENTRAL_HEADER: u32 = 0x0201_4b50;
+    const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+
+    fn u16_at(bytes: &[u8], offset: usize) -> Result<u16, String> {
+        bytes
+            .get(offset..offset + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .ok_or_else(|| "truncated zip archive".into())
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> Result<u32, String> {
+        bytes
+            .get(offset..offset + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or_else(|| "truncated zip archive".into())
+    }
+
+    /// The names and contents of the entries in an archive.
+    pub fn read_stored(bytes: &[u8]) -> Result<Vec<(String, &[u8])>, String> {
+        // The end of central directory record is at the end, followed only by
+        // a comment of up to 64KiB.
+        let eocd = (0..bytes.len().saturating_sub(21))
+            .rev()
+            .take(65536 + 22)
+            .find(|&i| u32_at(bytes, i) == Ok(END_OF_CENTRAL_DIRECTORY))
+            .ok_or("not a zip archive")?;
+        let n_entries = u16_at(bytes, eocd + 10)? as usize;
+        let mut offset = u32_at(bytes, eocd + 16)? as usize;
+
+        let mut entries = Vec::with_capacity(n_entries);
+        for _ in 0..n_entries {
+            if u32_at(bytes, offset)? != CENTRAL_HEADER {
+                return Err("corrupt zip central directory".into());
+            }
+            let method = u16_at(bytes, offset + 10)?;
+            let size = u32_at(bytes, offset + 20)?;
+            let name_len = u16_at(bytes, offset + 28)? as usize;
+            let extra_len = u16_at(bytes, offset + 30)? as usize;
+            let comment_len = u16_at(bytes, offset + 32)? as usize;
+            let local = u32_at(bytes, offset + 42)? as usize;
+            let name = bytes
+                .get(offset + 46..offset + 46 + name_len)
+                .ok_or("truncated zip archive")?;
+            let name = String::from_utf8_lossy(name).into_owned();
+            if method != 0 {
+                return Err(format!(
+                    "{} is compressed; only uncompressed archives (numpy.savez) are supported",
+                    name
+                ));
+            }
+            if size == u32::MAX || local == u32::MAX as usize {
+                return Err(format!("{} needs zip64, which is not supported", name));
+            }
+
+            if u32_at(bytes, local)? != LOCAL_HEADER {
+                return Err(format!("corrupt zip entry for {}", name));
+            }
+            let data_start = local
+                + 30
+                + u16_at(bytes, local + 26)? as usize
+                + u16_at(bytes, local + 28)? as usize;
+            let data = bytes
+                .get(data_start..data_start + size as usize)
+                .ok_or("truncated zip archive")?;
+            entries.push((name, data));
+            offset += 46 + name_len + extra_len + comment_len;
+        }
+        Ok(entries)
+    }
+
+    /// An archive with the given files, stored uncompressed.
+    pub fn write_stored(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut central = Vec::new();
+        for (name, data) in files {
+            let offset = out.len() as u32;
+            let crc = crc32(data);
+            // Version needed, flags, method, time and date.
+            let common = |buf: &mut Vec<u8>| {
+                for v in [20u16, 0, 0, 0, 0x21] {
+                    buf.extend_from_slice(&v.to_le_bytes());
+                }
+                for v in [crc, data.len() as u32, data.len() as u32] {
+                    buf.extend_from_slice(&v.to_le_bytes());
+                }
+                buf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+                buf.extend_from_slice(&0u16.to_le_bytes());
+            };
+            out.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+            common(&mut out);
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(data);
+
+            central.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+            // Version made by.
+            central.extend_from_slice(&20u16.to_le_bytes());
+            common(&mut central);
+            // Comment length, disk number, internal and external attributes.
+            central.extend_from_slice(&[0; 10]);
+            central.extend_from_slice(&offset.to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+        }
+        let central_offset = out.len() as u32;
+        out.extend_from_slice(&central);
+        out.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        out.extend_from_slice(&central_offset.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out
+    }
+
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in data {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+            }
+        }
+        !crc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `.npy` file with `header` as written, for headers `to_npy` doesn't
+    /// produce itself.
+    fn npy(version: u8, header: &str, data: &[u8]) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&[version, 0]);
+        if version == 1 {
+            out.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        } else {
+            out.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        }
+        out.extend_from_slice(header.as_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    fn array(dtype: Dtype, shape: &[usize]) -> Array {
+        let len = shape.iter().product::<usize>() * dtype.size();
+        Array {
+            dtype,
+            shape: shape.to_vec(),
+            data: (0..len).map(|i| i as u8).collect(),
+        }
+    }
+
+    #[test]
+    fn npy_round_trips() {
+        for dtype in [Dtype::F32, Dtype::F16, Dtype::U32, Dtype::I32, Dtype::U8] {
+            for shape in [&[][..], &[0], &[5], &[2, 3], &[2, 1, 4]] {
+                let array = array(dtype, shape);
+                let bytes = array.to_npy();
+                let data_start = bytes.len() - array.data.len();
+                assert_eq!(data_start % 64, 0, "{:?}", shape);
+                assert_eq!(bytes[data_start - 1], b'\n');
+                assert_eq!(Array::parse(&bytes).unwrap(), array);
+            }
+        }
+    }
+
+    #[test]
+    fn npy_writes_numpy_shapes() {
+        let header = |array: &Array| {
+            let bytes = array.to_npy();
+            String::from_utf8(bytes[10..bytes.len() - array.data.len()].to_vec()).unwrap()
+        };
+        let one_d = header(&array(Dtype::F32, &[3]));
+        assert!(one_d.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (3,), }"));
+        let scalar = header(&array(Dtype::U8, &[]));
+        assert!(scalar.starts_with("{'descr': '|u1', 'fortran_order': False, 'shape': (), }"));
+    }
+
+    #[test]
+    fn npy_parses_numpy_headers() {
+        let data: Vec<u8> = (0..24).collect();
+        let header = "{'descr': '<f4', 'fortran_order': False, 'shape': (2, 3), }          \n";
+        let expected = Array {
+            dtype: Dtype::F32,
+            shape: vec![2, 3],
+            data: data.clone(),
+        };
+        assert_eq!(Array::parse(&npy(1, header, &data)).unwrap(), expected);
+        assert_eq!(Array::parse(&npy(3, header, &data)).unwrap(), expected);
+
+        // Keys in another order, and a one-element tuple.
+        let header = "{'shape': (6,), 'fortran_order': False, 'descr': '<u4'}\n";
+        let parsed = Array::parse(&npy(1, header, &data)).unwrap();
+        assert_eq!((parsed.dtype, parsed.shape), (Dtype::U32, vec![6]));
+    }
+
+    #[test]
+    fn npy_rejects_unsupported_files() {
+        let error = |version, header: &str, data: &[u8]| {
+            Array::parse(&npy(version, header, data)).unwrap_err()
+        };
+        let f4 = |shape: &str| {
+            format!(
+                "{{'descr': '<f4', 'fortran_order': False, 'shape': {}, }}\n",
+                shape
+            )
+        };
+        assert_eq!(
+            Array::parse(b"PK\x03\x04 not npy").unwrap_err(),
+            "not a .npy file"
+        );
+        assert!(error(4, &f4("(1,)"), &[0; 4]).contains("version 4"));
+        assert!(error(1, &f4("(2,)"), &[0; 4]).contains("needs 8 bytes of data, found 4"));
+        assert!(error(1, &f4("(x,)"), &[0; 4]).contains("invalid shape"));
+        let fortran = "{'descr': '<f4', 'fortran_order': True, 'shape': (1,), }\n";
+        assert!(error(1, fortran, &[0; 4]).contains("Fortran"));
+        let big_endian = "{'descr': '>f4', 'fortran_order': False, 'shape': (1,), }\n";
+        assert!(error(1, big_endian, &[0; 4]).contains("big-endian"));
+        let f8 = "{'descr': '<f8', 'fortran_order': False, 'shape': (1,), }\n";
+        assert!(error(1, f8, &[0; 8]).contains("unsupported dtype <f8"));
+        let no_shape = "{'descr': '<f4', 'fortran_order': False, }\n";
+        assert_eq!(error(1, no_shape, &[0; 4]), "header has no 'shape'");
+    }
+
+    #[test]
+    fn raw_bytes_must_fill_whole_elements() {
+        let array = Array::from_bytes(Dtype::U32, vec![0; 12]).unwrap();
+        assert_eq!(array.shape, [3]);
+        assert!(Array::from_bytes(Dtype::U32, vec![0; 10]).is_err());
+        assert!(Array::from_bytes(Dtype::U8, vec![]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn zip_round_trips() {
+        let files = vec![
+            ("a.npy".to_string(), array(Dtype::F32, &[4]).to_npy()),
+            ("b.npy".to_string(), vec![]),
+        ];
+        let bytes = zip::write_stored(&files);
+        let read = zip::read_stored(&bytes).unwrap();
+        assert_eq!(read.len(), 2);
+        for ((name, data), (read_name, read_data)) in files.iter().zip(read) {
+            assert_eq!((name.as_str(), &data[..]), (read_name.as_str(), read_data));
+        }
+        assert!(zip::read_stored(&bytes[..bytes.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn npz_round_trips() {
+        let path = std::env::temp_dir().join(format!("npy-test-{}.npz", std::process::id()));
+        let a = array(Dtype::F32, &[2, 2]);
+        let b = array(Dtype::U8, &[7]);
+        save_npz(&path, &[("a", &a), ("b", &b)]).unwrap();
+        let arrays = load_npz(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(arrays, [("a".to_string(), a), ("b".to_string(), b)]);
+    }
+
+    #[test]
+    fn select_picks_by_name_or_the_only_array() {
+        let path = Path::new("x.npz");
+        let named = |names: &[&str]| {
+            names
+                .iter()
+                .map(|n| (n.to_string(), array(Dtype::U8, &[1])))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(select(path, named(&["a", "b"]), Some("b")).unwrap().0, "b");
+        assert_eq!(select(path, named(&["only"]), None).unwrap().0, "only");
+        let error = |arrays, name| match select(path, arrays, name) {
+            Err(Error::InvalidInput(msg)) => msg,
+            other => panic!("expected an error, got {:?}", other),
+        };
+        assert_eq!(
+            error(named(&["a", "b"]), Some("c")),
+            "x.npz: no array 'c'; it has a, b"
+        );
+        assert_eq!(error(named(&["a", "b"]), None), "x.npz: name one of a, b");
+        assert_eq!(error(named(&[]), None), "x.npz: archive is empty");
+    }
+}
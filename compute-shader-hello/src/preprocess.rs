@@ -0,0 +1,488 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! A small preprocessor for WGSL, which has no way to share code between
+//! files.
+//!
+//! Directives start a line, possibly after whitespace:
+//!
+//! * `#include "noise.wgsl"` pastes in another file, found relative to the
+//!   file including it. Each file is included at most once, as WGSL has no
+//!   use for repeated definitions.
+//! * `#define NAME value` and `#undef NAME`. Defined names are replaced by
+//!   their values wherever they appear as whole identifiers.
+//! * `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif`.
+//!
+//! The output remembers which file and line each of its lines came from, so
+//! that errors from naga can be reported against the original source.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::tune;
+use crate::{Error, KernelSource, INDEX_WGSL};
+
+/// Preprocessor settings: names defined before the first line is read.
+#[derive(Clone, Debug, Default)]
+pub struct Preprocessor {
+    defines: BTreeMap<String, String>,
+}
+
+impl Preprocessor {
+    pub fn new() -> Preprocessor {
+        Preprocessor::default()
+    }
+
+    /// Define `name` as `value`, as if by `#define`.
+    pub fn define(mut self, name: impl Into<String>, value: impl Into<String>) -> Preprocessor {
+        self.defines.insert(name.into(), value.into());
+        self
+    }
+
+    /// Define each of `defines`, given as `NAME` or `NAME=VALUE` as on the
+    /// command line. A name without a value is defined as `1`.
+    pub fn defines<S: AsRef<str>>(mut self, defines: &[S]) -> Preprocessor {
+        for define in defines {
+            let (name, value) = define
+                .as_ref()
+                .split_once('=')
+                .unwrap_or((define.as_ref(), "1"));
+            self = self.define(name, value);
+        }
+        self
+    }
+
+    /// Whether `name` is defined.
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.defines.contains_key(name)
+    }
+
+    /// Preprocess the file at `path`.
+    pub fn process_file(&self, path: &Path) -> Result<Preprocessed, Error> {
+        let source = std::fs::read_to_string(path).map_err(|e| Error::Io(path.into(), e))?;
+        self.process(&source, path)
+    }
+
+    /// Preprocess `source`, which was read from `path`; includes are found
+    /// relative to it.
+    pub fn process(&self, source: &str, path: &Path) -> Result<Preprocessed, Error> {
+        let mut state = State {
+            defines: self.defines.clone(),
+            out: Preprocessed::default(),
+            stack: Vec::new(),
+        };
+        state.process(source, path)?;
+        Ok(state.out)
+    }
+
+    /// Load a kernel, preprocessing it if it is WGSL.
+    ///
+    /// WGSL is also parsed the way the command line and
+    /// [`KernelSpec`](crate::spec::KernelSpec) build it, with the index
+    /// helpers prepended and any tunable workgroup size filled in, so that
+    /// syntax errors are reported at their file and line.
+    pub fn load_kernel(&self, path: &Path) -> Result<KernelSource, Error> {
+        if path.extension().and_then(|e| e.to_str()) != Some("wgsl") {
+            return KernelSource::load(path);
+        }
+        let processed = self.process_file(path)?;
+        let kernel = processed.prepend("index.wgsl", INDEX_WGSL);
+        if tune::is_tunable(&kernel.text) {
+            kernel
+                .transform("<workgroup size>", |text| {
+                    tune::instantiate(text, tune::DEFAULT_WORKGROUP_SIZE)
+                })
+                .parse()?;
+        } else {
+            kernel.parse()?;
+        }
+        Ok(KernelSource::Wgsl(processed.text))
+    }
+}
+
+/// Preprocessed source, with the origin of each line.
+#[derive(Clone, Debug, Default)]
+pub struct Preprocessed {
+    pub text: String,
+    files: Vec<PathBuf>,
+    /// For each line of `text`, an index into `files` and a 1-based line.
+    lines: Vec<(usize, usize)>,
+}
+
+impl Preprocessed {
+    /// The files the text came from: the one processed, then the files it
+    /// includes.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// The file and 1-based line that a 1-based line of the output came from.
+    pub fn map_line(&self, line: usize) -> Option<(&Path, usize)> {
+        let &(file, line) = self.lines.get(line.checked_sub(1)?)?;
+        Some((&self.files[file], line))
+    }
+
+    /// Apply a transformation to the text that only adds lines at the
+    /// start, like [`tune::instantiate`]. The added lines are attributed to
+    /// `name`.
+    pub fn transform(&self, name: &str, f: impl FnOnce(&str) -> String) -> Preprocessed {
+        let text = f(&self.text);
+        let added = text.lines().count().saturating_sub(self.lines.len());
+        let mut files = self.files.clone();
+        files.push(PathBuf::from(name));
+        let mut lines = (1..=added)
+            .map(|line| (files.len() - 1, line))
+            .collect::<Vec<_>>();
+        lines.extend_from_slice(&self.lines);
+        Preprocessed { text, files, lines }
+    }
+
+    /// Prepend `text`, attributing its lines to `name`.
+    pub fn prepend(&self, name: &str, text: &str) -> Preprocessed {
+        self.transform(name, |rest| format!("{}{}", text, rest))
+    }
+
+    /// Parse the text as WGSL, reporting syntax errors at the file and line
+    /// they came from.
+    pub fn parse(&self) -> Result<naga::Module, Error> {
+        naga::front::wgsl::parse_str(&self.text).map_err(|e| {
+            let (line, col) = e.location(&self.text);
+            let snippet = self.text.lines().nth(line - 1).unwrap_or("");
+            let at = match self.map_line(line) {
+                Some((file, line)) => format!("{}:{}:{}", file.display(), line, col),
+                None => format!("{}:{}", line, col),
+            };
+            Error::Shader(format!(
+                "{}: {}\n    {}\n    {:>col$}",
+                at,
+                e,
+                snippet,
+                "^",
+                col = col
+            ))
+        })
+    }
+
+    fn push_line(&mut self, text: &str, file: usize, line: usize) {
+        self.text.push_str(text);
+        self.text.push('\n');
+        self.lines.push((file, line));
+    }
+}
+
+struct State {
+    defines: BTreeMap<String, String>,
+    out: Preprocessed,
+    /// Files currently being processed, to detect cycles.
+    stack: Vec<PathBuf>,
+}
+
+impl State {
+    fn process(&mut self, source: &str, path: &Path) -> Result<(), Error> {
+        let file = self.out.files.len();
+        self.out.files.push(path.to_owned());
+        self.stack.push(path.to_owned());
+        // For each enclosing conditional: whether its current branch is
+        // taken, and whether an #else has been seen.
+        let mut conditions: Vec<(bool, bool)> = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let line_number = i + 1;
+            let error =
+                |msg: String| Error::Parse(path.into(), format!("line {}: {}", line_number, msg));
+            let active = conditions.iter().all(|&(taken, _)| taken);
+            let directive = match line.trim_start().strip_prefix('#') {
+                Some(directive) => directive,
+                None => {
+                    if active {
+                        let line = substitute(line, &self.defines);
+                        self.out.push_line(&line, file, line_number);
+                    }
+                    continue;
+                }
+            };
+            let (name, rest) = directive
+                .split_once(char::is_whitespace)
+                .unwrap_or((directive, ""));
+            let rest = rest.trim();
+            match name {
+                "ifdef" | "ifndef" => {
+                    let defined = self.defines.contains_key(identifier(rest).map_err(error)?);
+                    conditions.push((defined == (name == "ifdef"), false));
+                }
+                "else" => match conditions.last_mut() {
+                    Some((taken, seen_else @ false)) => {
+                        *taken = !*taken;
+                        *seen_else = true;
+                    }
+                    Some(_) => return Err(error("#else after #else".into())),
+                    None => return Err(error("#else without #ifdef".into())),
+                },
+                "endif" => {
+                    conditions
+                        .pop()
+                        .ok_or_else(|| error("#endif without #ifdef".into()))?;
+                }
+                _ if !active => (),
+                "define" => {
+                    let (name, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                    let name = identifier(name).map_err(error)?;
+                    self.defines.insert(name.into(), value.trim().into());
+                }
+                "undef" => {
+                    self.defines.remove(identifier(rest).map_err(error)?);
+                }
+                "include" => {
+                    let name = rest
+                        .strip_prefix('"')
+                        .and_then(|r| r.strip_suffix('"'))
+                        .ok_or_else(|| {
+                            error(format!("expected #include \"file\", got '{}'", line.trim()))
+                        })?;
+                    let include = path.parent().unwrap_or_else(|| Path::new("")).join(name);
+                    if self.stack.contains(&include) {
+                        return Err(error(format!("{} includes itself", include.display())));
+                    }
+                    if self.out.files.contains(&include) {
+                        continue;
+                    }
+                    let source = std::fs::read_to_string(&include)
+                        .map_err(|e| Error::Io(include.clone(), e))?;
+                    self.process(&source, &include)?;
+                }
+                _ => return Err(error(format!("unknown directive #{}", name))),
+            }
+        }
+        if !conditions.is_empty() {
+            return Err(Error::Parse(path.into(), "#ifdef without #endif".into()));
+        }
+        self.stack.pop();
+        Ok(())
+    }
+}
+
+/// Check that `s` is a single identifier.
+fn identifier(s: &str) -> Result<&str, String> {
+    let valid = s
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if valid {
+        Ok(s)
+    } else {
+        Err(format!("expected a name, got '{}'", s))
+    }
+}
+
+/// Replace defined names with their values, where they appear as whole
+/// identifiers.
+fn substitute(line: &str, defines: &BTreeMap<String, String>) -> String {
+    if defines.is_empty() {
+        return line.to_string();
+    }
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find(|c: char| c.is_alphabetic() || c == '_') {
+        // Skip the tails of numbers like `1u` or `0x1f`.
+        let (before, from) = rest.split_at(start);
+        let in_number = before.ends_with(|c: char| c.is_ascii_digit());
+        let len = from
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(from.len());
+        let (word, after) = from.split_at(len);
+        out.push_str(before);
+        match defines.get(word) {
+            Some(value) if !in_number => out.push_str(value),
+            _ => out.push_str(word),
+        }
+        rest = after;
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory holding `files`, for includes to be read from.
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("preprocess-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, source) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        }
+        dir
+    }
+
+    fn process(preprocessor: &Preprocessor, source: &str) -> Result<String, Error> {
+        Ok(preprocessor.process(source, Path::new("main.wgsl"))?.text)
+    }
+
+    fn parse_error(source: &str) -> String {
+        match process(&Preprocessor::new(), source) {
+            Err(Error::Parse(_, msg)) => msg,
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn defines_replace_whole_identifiers() {
+        let source = "#define N 64\nlet a = N + N_2 + M + 1N;\n#undef N\nlet b = N;\n";
+        let preprocessor = Preprocessor::new().defines(&["M=3", "FLAG"]);
+        assert_eq!(
+            process(&preprocessor, source).unwrap(),
+            "let a = 64 + N_2 + 3 + 1N;\nlet b = N;\n"
+        );
+        assert!(preprocessor.is_defined("FLAG"));
+        assert_eq!(
+            process(&preprocessor, "let c = FLAG + 0xM;").unwrap(),
+            "let c = 1 + 0xM;\n"
+        );
+    }
+
+    #[test]
+    fn conditionals_select_lines() {
+        let source = "\
+#ifdef A
+a
+#ifndef B
+a_not_b
+#else
+a_b
+#endif
+#else
+not_a
+#define B
+#endif
+#ifdef B
+b
+#endif
+";
+        let lines = |preprocessor: Preprocessor| process(&preprocessor, source).unwrap();
+        assert_eq!(lines(Preprocessor::new()), "not_a\nb\n");
+        assert_eq!(lines(Preprocessor::new().define("A", "")), "a\na_not_b\n");
+        let both = Preprocessor::new().defines(&["A", "B"]);
+        assert_eq!(lines(both), "a\na_b\nb\n");
+    }
+
+    #[test]
+    fn malformed_directives_are_errors() {
+        assert_eq!(parse_error("#else"), "line 1: #else without #ifdef");
+        assert_eq!(
+            parse_error("#ifdef A\n#else\n#else\n#endif"),
+            "line 3: #else after #else"
+        );
+        assert_eq!(parse_error("x\n#endif"), "line 2: #endif without #ifdef");
+        assert_eq!(parse_error("#ifdef A"), "#ifdef without #endif");
+        assert_eq!(
+            parse_error("#ifdef 1A\n#endif"),
+            "line 1: expected a name, got '1A'"
+        );
+        assert_eq!(
+            parse_error("#pragma once"),
+            "line 1: unknown directive #pragma"
+        );
+        assert_eq!(
+            parse_error("#include <noise.wgsl>"),
+            "line 1: expected #include \"file\", got '#include <noise.wgsl>'"
+        );
+    }
+
+    #[test]
+    fn includes_are_pasted_once() {
+        let dir = write_files(
+            "includes",
+            &[
+                (
+                    "main.wgsl",
+                    "#include \"a.wgsl\"\nmain\n#include \"b.wgsl\"\n",
+                ),
+                ("a.wgsl", "#define X 1\na X\n"),
+                (
+                    "b.wgsl",
+                    "#include \"lib/c.wgsl\"\n#include \"a.wgsl\"\nb X\n",
+                ),
+                ("lib/c.wgsl", "#include \"d.wgsl\"\nc\n"),
+                ("lib/d.wgsl", "d\n"),
+            ],
+        );
+
+        let main = dir.join("main.wgsl");
+        let out = Preprocessor::new().process_file(&main).unwrap();
+        assert_eq!(out.text, "a 1\nmain\nd\nc\nb 1\n");
+        let files = [
+            main.clone(),
+            dir.join("a.wgsl"),
+            dir.join("b.wgsl"),
+            dir.join("lib/c.wgsl"),
+            dir.join("lib/d.wgsl"),
+        ];
+        assert_eq!(out.files(), files);
+
+        let lines = (0..=6).map(|line| out.map_line(line)).collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                None,
+                Some((files[1].as_path(), 2)),
+                Some((main.as_path(), 2)),
+                Some((files[4].as_path(), 1)),
+                Some((files[3].as_path(), 2)),
+                Some((files[2].as_path(), 3)),
+                None,
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include_cycles_and_missing_files_are_errors() {
+        let dir = write_files(
+            "cycle",
+            &[
+                ("a.wgsl", "#include \"b.wgsl\"\n"),
+                ("b.wgsl", "\n#include \"a.wgsl\"\n"),
+            ],
+        );
+        let result = Preprocessor::new().process_file(&dir.join("a.wgsl"));
+        match result {
+            Err(Error::Parse(path, msg)) => {
+                assert_eq!(path, dir.join("b.wgsl"));
+                assert!(msg.starts_with("line 2: ") && msg.ends_with("a.wgsl includes itself"));
+            }
+            other => panic!("expected a cycle error, got {:?}", other),
+        }
+        let missing =
+            Preprocessor::new().process("#include \"missing.wgsl\"", &dir.join("main.wgsl"));
+        assert!(matches!(missing, Err(Error::Io(path, _)) if path == dir.join("missing.wgsl")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prepended_lines_map_to_their_name() {
+        let out = Preprocessor::new()
+            .process("x\ny\n", Path::new("main.wgsl"))
+            .unwrap()
+            .prepend("index.wgsl", "i\n");
+        assert_eq!(out.text, "i\nx\ny\n");
+        assert_eq!(out.map_line(1), Some((Path::new("index.wgsl"), 1)));
+        assert_eq!(out.map_line(3), Some((Path::new("main.wgsl"), 2)));
+    }
+}
@@ -0,0 +1,385 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! GPU profiling with timestamp queries, and export to Chrome traces.
+//!
+//! A [`Profiler`] times labelled scopes in command encoders, allocating
+//! query slots as scopes are opened, so a frame or run can have any number
+//! of them. [`Profiler::resolve`] records the copy of the timestamps out of
+//! the query sets; once the commands have been submitted,
+//! [`ResolvedFrame::read`] waits for them and converts the timestamps to
+//! nanoseconds.
+//!
+//! Timings can be collected into a [`Trace`] and written as JSON in the
+//! Chrome trace event format, which `chrome://tracing` and
+//! [Perfetto](https://ui.perfetto.dev) open.
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::json;
+
+use crate::readback::map_read;
+use crate::Error;
+
+/// Number of queries in each query set the profiler allocates.
+const QUERIES_PER_SET: u32 = 256;
+
+/// Times labelled scopes with timestamp queries.
+pub struct Profiler {
+    query_sets: Vec<wgpu::QuerySet>,
+    /// The scopes opened since the last resolve: label, and start and end
+    /// query. The end is `None` while the scope is open.
+    scopes: Vec<(String, u32, Option<u32>)>,
+    next_query: u32,
+    trace: Option<Trace>,
+}
+
+/// An open scope, returned by [`Profiler::begin_scope`].
+#[must_use]
+pub struct Scope(usize);
+
+impl Profiler {
+    /// Create a profiler for `device`, which must have been created with
+    /// the `TIMESTAMP_QUERY` feature.
+    pub fn new(device: &wgpu::Device) -> Result<Profiler, Error> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return Err(Error::InvalidInput(
+                "profiling needs timestamp queries, which the adapter doesn't support".into(),
+            ));
+        }
+        Ok(Profiler {
+            query_sets: Vec::new(),
+            scopes: Vec::new(),
+            next_query: 0,
+            trace: None,
+        })
+    }
+
+    /// Add the timings of each frame to `trace`.
+    pub fn with_trace(mut self, trace: Trace) -> Profiler {
+        self.trace = Some(trace);
+        self
+    }
+
+    /// The trace the profiler adds to, if any.
+    pub fn trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
+    }
+
+    /// Write a timestamp starting a scope named `label`.
+    pub fn begin_scope(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+    ) -> Scope {
+        let query = self.write_timestamp(device, encoder);
+        self.scopes.push((label.into(), query, None));
+        Scope(self.scopes.len() - 1)
+    }
+
+    /// Write a timestamp ending a scope.
+    pub fn end_scope(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        scope: Scope,
+    ) {
+        let query = self.write_timestamp(device, encoder);
+        self.scopes[scope.0].2 = Some(query);
+    }
+
+    /// Time the commands `record` records in a scope named `label`.
+    pub fn scope<R>(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        record: impl FnOnce(&mut wgpu::CommandEncoder) -> R,
+    ) -> R {
+        let scope = self.begin_scope(device, encoder, label);
+        let result = record(encoder);
+        self.end_scope(device, encoder, scope);
+        result
+    }
+
+    fn write_timestamp(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> u32 {
+        let query = self.next_query;
+        let set = (query / QUERIES_PER_SET) as usize;
+        if set == self.query_sets.len() {
+            self.query_sets
+                .push(device.create_query_set(&wgpu::QuerySetDescriptor {
+                    label: Some("profiler"),
+                    ty: wgpu::QueryType::Timestamp,
+                    count: QUERIES_PER_SET,
+                }));
+        }
+        encoder.write_timestamp(&self.query_sets[set], query % QUERIES_PER_SET);
+        self.next_query += 1;
+        query
+    }
+
+    /// Record the copy of the timestamps of the scopes opened since the last
+    /// resolve, and start a new frame. Scopes must be closed first.
+    ///
+    /// Returns `None` if no scopes were opened.
+    pub fn resolve(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Option<ResolvedFrame> {
+        if self.scopes.is_empty() {
+            return None;
+        }
+        let n_queries = self.next_query;
+        let size = n_queries as u64 * 8;
+        let resolve = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("profiler resolve"),
+            size,
+            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        for (i, set) in self.query_sets.iter().enumerate() {
+            let first = i as u32 * QUERIES_PER_SET;
+            if first >= n_queries {
+                break;
+            }
+            let count = (n_queries - first).min(QUERIES_PER_SET);
+            // Each set is a multiple of QUERY_RESOLVE_BUFFER_ALIGNMENT into
+            // the buffer.
+            encoder.resolve_query_set(set, 0..count, &resolve, first as u64 * 8);
+        }
+        // Queries can't be resolved straight into a mappable buffer.
+        let buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("profiler readback"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_buffer_to_buffer(&resolve, 0, &buf, 0, size);
+        let scopes = std::mem::take(&mut self.scopes)
+            .into_iter()
+            .map(|(label, start, end)| (label, start, end.expect("scope wasn't ended")))
+            .collect();
+        self.next_query = 0;
+        Some(ResolvedFrame {
+            buf,
+            scopes,
+            trace: self.trace.clone(),
+        })
+    }
+}
+
+/// The timestamps of one frame, resolved into a buffer.
+pub struct ResolvedFrame {
+    buf: wgpu::Buffer,
+    scopes: Vec<(String, u32, u32)>,
+    trace: Option<Trace>,
+}
+
+impl ResolvedFrame {
+    /// Wait for the frame's commands to complete, and read the time of each
+    /// scope, in the order they were opened.
+    ///
+    /// The commands the frame was resolved in must have been submitted.
+    pub async fn read(
+        self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<Vec<ScopeTiming>, Error> {
+        let period = queue.get_timestamp_period() as f64;
+        let mut timings = Vec::new();
+        map_read(device, &[("profiler timestamps", &self.buf)], |_, data| {
+            timings = scope_timings(&self.scopes, bytemuck::cast_slice(data), period);
+        })
+        .await?;
+        if let Some(trace) = &self.trace {
+            trace.add(&timings);
+        }
+        Ok(timings)
+    }
+}
+
+/// The times of `scopes`, given as label and start and end query, from the
+/// timestamps of the queries in ticks of `period` nanoseconds.
+fn scope_timings(scopes: &[(String, u32, u32)], ticks: &[u64], period: f64) -> Vec<ScopeTiming> {
+    scopes
+        .iter()
+        .map(|(label, start, end)| ScopeTiming {
+            label: label.clone(),
+            start_ns: ticks[*start as usize] as f64 * period,
+            end_ns: ticks[*end as usize] as f64 * period,
+        })
+        .collect()
+}
+
+/// The GPU time of one scope.
+///
+/// Times are in nanoseconds on the GPU's clock, whose origin is arbitrary
+/// but the same for all scopes timed on one queue.
+#[derive(Clone, Debug)]
+pub struct ScopeTiming {
+    pub label: String,
+    pub start_ns: f64,
+    pub end_ns: f64,
+}
+
+impl ScopeTiming {
+    pub fn duration(&self) -> Duration {
+        Duration::from_nanos((self.end_ns - self.start_ns).max(0.0) as u64)
+    }
+}
+
+/// Scope timings collected over a run, to be written as a Chrome trace.
+///
+/// Clones share the same events, so one trace can collect the timings of
+/// several profilers.
+#[derive(Clone, Default)]
+pub struct Trace {
+    frames: Arc<Mutex<Vec<Vec<ScopeTiming>>>>,
+}
+
+impl Trace {
+    pub fn new() -> Trace {
+        Trace::default()
+    }
+
+    /// Add the timings of one frame or run.
+    pub fn add(&self, timings: &[ScopeTiming]) {
+        self.frames.lock().unwrap().push(timings.to_vec());
+    }
+
+    /// Write the trace as JSON in the Chrome trace event format.
+    ///
+    /// Each scope is a complete event, with times in microseconds from the
+    /// start of the first scope, and the frame it was in as an argument.
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let json = serde_json::to_string_pretty(&self.to_json()).unwrap();
+        std::fs::write(path, json).map_err(|e| Error::Io(path.into(), e))
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let frames = self.frames.lock().unwrap();
+        let origin = frames
+            .iter()
+            .flatten()
+            .map(|t| t.start_ns)
+            .fold(f64::INFINITY, f64::min);
+        let mut events = vec![json!({
+            "name": "process_name",
+            "ph": "M",
+            "pid": 1,
+            "args": { "name": "GPU" },
+        })];
+        for (frame, timings) in frames.iter().enumerate() {
+            for timing in timings {
+                events.push(json!({
+                    "name": timing.label,
+                    "cat": "gpu",
+                    "ph": "X",
+                    "pid": 1,
+                    "tid": 1,
+                    "ts": (timing.start_ns - origin) * 1e-3,
+                    "dur": (timing.end_ns - timing.start_ns).max(0.0) * 1e-3,
+                    "args": { "frame": frame },
+                }));
+            }
+        }
+        json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scopes(scopes: &[(&str, u32, u32)]) -> Vec<(String, u32, u32)> {
+        scopes
+            .iter()
+            .map(|&(label, start, end)| (label.into(), start, end))
+            .collect()
+    }
+
+    #[test]
+    fn timestamps_are_scaled_by_the_period() {
+        // Nested scopes: the outer one opened first and closed last.
+        let scopes = scopes(&[("outer", 0, 3), ("inner", 1, 2)]);
+        let timings = scope_timings(&scopes, &[1000, 1100, 1500, 1600], 2.5);
+        assert_eq!(timings.len(), 2);
+        assert_eq!(timings[0].label, "outer");
+        assert_eq!((timings[0].start_ns, timings[0].end_ns), (2500.0, 4000.0));
+        assert_eq!(timings[1].label, "inner");
+        assert_eq!((timings[1].start_ns, timings[1].end_ns), (2750.0, 3750.0));
+        assert_eq!(timings[1].duration(), Duration::from_nanos(1000));
+    }
+
+    #[test]
+    fn chrome_trace_events() {
+        let trace = Trace::new();
+        let frame = |ticks: &[u64]| scope_timings(&scopes(&[("a", 0, 1), ("b", 2, 3)]), ticks, 4.0);
+        trace.add(&frame(&[500, 750, 750, 1000]));
+        // A clone shares the events, and a second frame comes later.
+        trace.clone().add(&frame(&[2500, 3000, 3000, 2900]));
+
+        let json = trace.to_json();
+        assert_eq!(json["displayTimeUnit"], "ms");
+        let events = json["traceEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 5);
+        assert_eq!(events[0]["ph"], "M");
+        assert_eq!(events[0]["args"]["name"], "GPU");
+
+        // Times are in microseconds from the start of the first scope, at
+        // 2000 ns.
+        let expected = [
+            ("a", 0.0, 1.0, 0),
+            ("b", 1.0, 1.0, 0),
+            ("a", 8.0, 2.0, 1),
+            // A scope that seems to end before it starts lasts no time.
+            ("b", 10.0, 0.0, 1),
+        ];
+        for (event, (name, ts, dur, frame)) in events[1..].iter().zip(expected) {
+            assert_eq!(event["ph"], "X");
+            assert_eq!(event["name"], name);
+            assert_eq!(event["ts"].as_f64().unwrap(), ts, "{}", event);
+            assert_eq!(event["dur"].as_f64().unwrap(), dur, "{}", event);
+            assert_eq!(event["args"]["frame"], frame);
+            assert_eq!((&event["pid"], &event["tid"]), (&json!(1), &json!(1)));
+        }
+    }
+
+    #[test]
+    fn trace_is_written_as_json() {
+        let trace = Trace::new();
+        trace.add(&[ScopeTiming {
+            label: "kernel".into(),
+            start_ns: 1e6,
+            end_ns: 3e6,
+        }]);
+        let path = std::env::temp_dir().join(format!("trace-{}.json", std::process::id()));
+        trace.write(&path).unwrap();
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, trace.to_json());
+        assert_eq!(written["traceEvents"][1]["dur"].as_f64(), Some(2000.0));
+    }
+}
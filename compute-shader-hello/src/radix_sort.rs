@@ -0,0 +1,217 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! GPU radix sort of `u32` keys, optionally carrying `u32` values along.
+//!
+//! This is a least significant digit sort. Each pass counts the digits in
+//! every block of keys, scans the counts with [`crate::scan`] to find where
+//! each block's keys go, then scatters them. The sort is stable.
+
+use std::time::Duration;
+
+use bytemuck::{Pod, Zeroable};
+
+use crate::graph::{Graph, GraphTimings};
+use crate::runner::grid;
+use crate::scan::{ScanAlgorithm, ScanKind, Scanner};
+use crate::{ComputeRunner, Error, Kernel};
+
+/// Number of keys handled by one workgroup.
+///
+/// This must match `WG_SIZE` in `radix_sort.wgsl`.
+pub const BLOCK_SIZE: usize = 256;
+
+/// Parameters for one sort pass; matches `Params` in the shader.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct Params {
+    n: u32,
+    shift: u32,
+    bits: u32,
+    n_blocks: u32,
+    grid_width: u32,
+    has_values: u32,
+}
+
+/// Compiled radix sort kernels.
+pub struct RadixSorter {
+    histogram: Kernel,
+    scatter: Kernel,
+    scanner: Scanner<u32>,
+}
+
+impl RadixSorter {
+    pub fn new(runner: &ComputeRunner) -> Result<RadixSorter, Error> {
+        // The layouts are reflected, so that each pass binds only the
+        // buffers it uses.
+        let source = include_str!("shaders/radix_sort.wgsl");
+        Ok(RadixSorter {
+            histogram: runner.create_reflected_kernel(source, "histogram")?,
+            scatter: runner.create_reflected_kernel(source, "scatter")?,
+            scanner: Scanner::new(runner)?,
+        })
+    }
+
+    /// Sort the first `n` keys in `keys`, in place.
+    ///
+    /// If `values` is given, its first `n` elements are permuted along with
+    /// the keys. Each pass sorts on `bits_per_pass` bits, which must be
+    /// between 1 and 8.
+    ///
+    /// Returns the GPU time taken, if timestamp queries are available.
+    pub async fn sort(
+        &self,
+        runner: &ComputeRunner,
+        keys: &wgpu::Buffer,
+        values: Option<&wgpu::Buffer>,
+        n: usize,
+        bits_per_pass: u32,
+    ) -> Result<Option<Duration>, Error> {
+        let timings = self
+            .sort_timed(runner, keys, values, n, bits_per_pass)
+            .await?;
+        Ok(timings.total)
+    }
+
+    /// Sort as [`sort`](RadixSorter::sort) does, and return the GPU time of
+    /// each stage: `histogram`, the stages of the scan, `scatter`, and
+    /// `copy` if the result ends up in the temporary buffers.
+    pub async fn sort_timed(
+        &self,
+        runner: &ComputeRunner,
+        keys: &wgpu::Buffer,
+        values: Option<&wgpu::Buffer>,
+        n: usize,
+        bits_per_pass: u32,
+    ) -> Result<GraphTimings, Error> {
+        if !(1..=8).contains(&bits_per_pass) {
+            return Err(Error::InvalidInput(format!(
+                "bits per pass must be between 1 and 8, got {}",
+                bits_per_pass
+            )));
+        }
+        if n == 0 {
+            return Ok(GraphTimings {
+                passes: Vec::new(),
+                total: None,
+            });
+        }
+        let n_blocks = n.div_ceil(BLOCK_SIZE) as u32;
+        let n_passes = 32u32.div_ceil(bits_per_pass);
+        let size = (n * 4) as u64;
+        let hist_len = (n_blocks as usize) << bits_per_pass;
+        let block_hist = runner.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (hist_len * 4) as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        // Without values, the value bindings still need some buffers.
+        let values_dummy = values.is_none().then(|| {
+            runner.device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size: 4,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })
+        });
+        let scan = self.scanner.prepare(
+            runner,
+            &block_hist,
+            hist_len,
+            ScanKind::Exclusive,
+            ScanAlgorithm::ReduceThenScan,
+        );
+
+        let mut graph = Graph::new();
+        graph
+            .import("keys", keys)
+            .import("values", values.or(values_dummy.as_ref()).unwrap())
+            .import("block_hist", &block_hist)
+            .buffer("keys_alt", size)
+            .buffer("values_alt", if values.is_some() { size } else { 4 });
+        let mut src = ("keys", "values");
+        let mut dst = ("keys_alt", "values_alt");
+        for pass in 0..n_passes {
+            let params = format!("params {}", pass);
+            graph.buffer_init(
+                &params,
+                &[Params {
+                    n: n as u32,
+                    shift: pass * bits_per_pass,
+                    bits: bits_per_pass,
+                    n_blocks,
+                    grid_width: grid(n_blocks).0,
+                    has_values: values.is_some() as u32,
+                }],
+            );
+            graph
+                .dispatch("histogram", &self.histogram, grid(n_blocks))
+                .read(0, 0, &params)
+                .read(0, 1, src.0)
+                .write(0, 5, "block_hist");
+            scan.add_to_graph(&mut graph, "block_hist");
+            graph
+                .dispatch("scatter", &self.scatter, grid(n_blocks))
+                .read(0, 0, &params)
+                .read(0, 1, src.0)
+                .read(0, 2, src.1)
+                .write(0, 3, dst.0)
+                .write(0, 4, dst.1)
+                .read(0, 5, "block_hist");
+            std::mem::swap(&mut src, &mut dst);
+        }
+        // After an odd number of passes the result is in the temporary
+        // buffers.
+        if n_passes % 2 == 1 {
+            graph.copy("copy", "keys_alt", "keys", size);
+            if values.is_some() {
+                graph.copy("copy", "values_alt", "values", size);
+            }
+        }
+        graph.build(runner)?.run(runner).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reflect::ShaderLayout;
+
+    #[test]
+    fn sort_matches_slice_sort() {
+        let source = include_str!("shaders/radix_sort.wgsl");
+        for entry in ["histogram", "scatter"] {
+            if let Err(e) = ShaderLayout::from_wgsl(source, &[entry]) {
+                panic!("{}: {}", entry, e);
+            }
+        }
+
+        let Some(runner) = ComputeRunner::for_tests() else {
+            return;
+        };
+        let sorter = RadixSorter::new(&runner).unwrap();
+        // Enough keys for several blocks, with duplicates.
+        let mut keys = (0..3000u32)
+            .map(|i| i.wrapping_mul(2654435761) % 5000)
+            .collect::<Vec<_>>();
+        let buf = runner.upload(&keys);
+        pollster::block_on(sorter.sort(&runner, &buf, None, keys.len(), 4)).unwrap();
+        let sorted: Vec<u32> = pollster::block_on(runner.read_back(&buf, keys.len())).unwrap();
+        keys.sort();
+        assert_eq!(sorted, keys);
+    }
+}
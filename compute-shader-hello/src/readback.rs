@@ -0,0 +1,287 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Reading buffers back to the host.
+//!
+//! Buffers can't be mapped while the GPU may use them, so their contents
+//! are copied into staging buffers with `MAP_READ` usage, which are mapped
+//! instead. All the copies for one read are submitted together, and every
+//! mapping is awaited and unmapped, even when one of them fails. Staging
+//! buffers come from the runner's [`StagingPool`](crate::staging::StagingPool)
+//! and go back to it afterwards.
+
+use std::ops::Range;
+
+use bytemuck::Pod;
+
+use crate::staging::StagingStats;
+use crate::{ComputeRunner, Error};
+
+/// Map each of `buffers` for reading, wait for all of them, and pass the
+/// contents of each mapped buffer to `read`, along with its index.
+///
+/// The buffers are unmapped afterwards. If a mapping fails, the others are
+/// still read and unmapped, and the first failure is returned, naming the
+/// buffer by the label given with it.
+pub(crate) async fn map_read(
+    device: &wgpu::Device,
+    buffers: &[(&str, &wgpu::Buffer)],
+    mut read: impl FnMut(usize, &[u8]),
+) -> Result<(), Error> {
+    let futures = buffers
+        .iter()
+        .map(|(_, buf)| buf.slice(..).map_async(wgpu::MapMode::Read))
+        .collect::<Vec<_>>();
+    device.poll(wgpu::Maintain::Wait);
+    let mut results = Vec::with_capacity(futures.len());
+    for future in futures {
+        results.push(future.await);
+    }
+    let mut error = None;
+    for (i, ((label, buf), result)) in buffers.iter().zip(results).enumerate() {
+        match result {
+            Ok(()) => {
+                read(i, &buf.slice(..).get_mapped_range());
+                buf.unmap();
+            }
+            Err(e) => {
+                error.get_or_insert(Error::Map(label.to_string(), e));
+            }
+        }
+    }
+    error.map_or(Ok(()), Err)
+}
+
+/// Round a byte range out to the alignment copies need.
+fn align_range(range: &Range<u64>) -> Range<u64> {
+    let align = wgpu::COPY_BUFFER_ALIGNMENT;
+    range.start / align * align..range.end.div_ceil(align) * align
+}
+
+impl ComputeRunner {
+    /// Read the first `len` elements of a buffer back to the host.
+    ///
+    /// The buffer must have been created with `COPY_SRC` usage, as is the
+    /// case for buffers created by [`upload`](ComputeRunner::upload).
+    pub async fn read_back<T: Pod>(&self, buf: &wgpu::Buffer, len: usize) -> Result<Vec<T>, Error> {
+        self.read_back_range(buf, 0..len).await
+    }
+
+    /// Read the elements in `range` of a buffer back to the host.
+    pub async fn read_back_range<T: Pod>(
+        &self,
+        buf: &wgpu::Buffer,
+        range: Range<usize>,
+    ) -> Result<Vec<T>, Error> {
+        let size = std::mem::size_of::<T>() as u64;
+        let bytes = range.start as u64 * size..range.end as u64 * size;
+        let mut result = Vec::new();
+        self.read_ranges(&[(buf, bytes)], |_, data| {
+            result = bytemuck::cast_slice(data).to_vec();
+        })
+        .await?;
+        Ok(result)
+    }
+
+    /// Read byte ranges of any number of buffers back to the host, with one
+    /// submission.
+    ///
+    /// Ranges needn't be aligned, but a buffer's size is rounded up to a
+    /// multiple of 4 bytes when reading the end of it, so it must be padded
+    /// to that, as buffers created by wgpu's `create_buffer_init` are.
+    pub async fn read_back_ranges(
+        &self,
+        ranges: &[(&wgpu::Buffer, Range<u64>)],
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let mut result = vec![Vec::new(); ranges.len()];
+        self.read_ranges(ranges, |i, data| result[i] = data.to_vec())
+            .await?;
+        Ok(result)
+    }
+
+    /// Read byte ranges back, passing the contents of each to `read`.
+    async fn read_ranges(
+        &self,
+        ranges: &[(&wgpu::Buffer, Range<u64>)],
+        mut read: impl FnMut(usize, &[u8]),
+    ) -> Result<(), Error> {
+        if let Some((_, range)) = ranges.iter().find(|(_, r)| r.start > r.end) {
+            return Err(Error::InvalidInput(format!(
+                "can't read back bytes {}..{}",
+                range.start, range.end
+            )));
+        }
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        // Empty ranges don't get a staging buffer.
+        let mut staged = Vec::new();
+        for (i, (buf, range)) in ranges.iter().enumerate() {
+            if range.is_empty() {
+                continue;
+            }
+            let aligned = align_range(range);
+            let staging = self
+                .staging
+                .lock()
+                .unwrap()
+                .acquire(&self.device, aligned.end - aligned.start);
+            encoder.copy_buffer_to_buffer(
+                buf,
+                aligned.start,
+                &staging.buffer,
+                0,
+                aligned.end - aligned.start,
+            );
+            staged.push((i, staging));
+        }
+        self.queue.submit(Some(encoder.finish()));
+
+        for (i, (_, range)) in ranges.iter().enumerate() {
+            if range.is_empty() {
+                read(i, &[]);
+            }
+        }
+        let labels = staged
+            .iter()
+            .map(|(i, _)| {
+                let range = &ranges[*i].1;
+                format!("bytes {}..{} of buffer {}", range.start, range.end, i)
+            })
+            .collect::<Vec<_>>();
+        let buffers = staged
+            .iter()
+            .zip(&labels)
+            .map(|((_, staging), label)| (label.as_str(), &staging.buffer))
+            .collect::<Vec<_>>();
+        let result = map_read(&self.device, &buffers, |j, data| {
+            let (i, _) = staged[j];
+            let range = &ranges[i].1;
+            let offset = (range.start - align_range(range).start) as usize;
+            read(
+                i,
+                &data[offset..offset + (range.end - range.start) as usize],
+            );
+        })
+        .await;
+        // Buffers that failed to map may still be in use, so only reuse them
+        // if all went well.
+        if result.is_ok() {
+            let mut pool = self.staging.lock().unwrap();
+            for (_, staging) in staged {
+                pool.release(staging);
+            }
+        }
+        result
+    }
+
+    /// How many staging buffers readbacks have asked for and created.
+    pub fn staging_stats(&self) -> StagingStats {
+        self.staging.lock().unwrap().stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tune, GpuBuffer, INDEX_WGSL};
+
+    #[test]
+    fn ranges_align_outwards() {
+        assert_eq!(align_range(&(0..0)), 0..0);
+        assert_eq!(align_range(&(1..3)), 0..4);
+        assert_eq!(align_range(&(4..9)), 4..12);
+        assert_eq!(align_range(&(7..8)), 4..8);
+    }
+
+    /// Several buffers and unaligned sub-ranges of them, read back together
+    /// and one at a time.
+    #[test]
+    fn read_back_ranges_of_buffers() {
+        let Some(runner) = ComputeRunner::for_tests() else {
+            return;
+        };
+        // Odd lengths check the unaligned tail of byte buffers.
+        for len in [1037, 1] {
+            let words = (0..len as u32)
+                .map(|i| i.wrapping_mul(2654435761))
+                .collect::<Vec<_>>();
+            let bytes = words.iter().map(|&x| x as u8).collect::<Vec<_>>();
+            let words_buf = runner.upload(&words);
+            let bytes_buf = runner.upload(&bytes);
+
+            let len64 = len as u64;
+            let ranges = [
+                (0, len64),
+                (1, len64),
+                (len64 / 3, len64 / 3),
+                (len64 / 3, len64.saturating_sub(1)),
+                (len64.saturating_sub(3), len64),
+            ];
+            for (start, end) in ranges {
+                let start = start.min(end);
+                let data = pollster::block_on(runner.read_back_ranges(&[
+                    (&words_buf, 4 * start..4 * end),
+                    (&bytes_buf, start..end),
+                ]))
+                .unwrap();
+                let (start, end) = (start as usize, end as usize);
+                assert_eq!(data[0], bytemuck::cast_slice::<u32, u8>(&words[start..end]));
+                assert_eq!(data[1], bytes[start..end]);
+                let result: Vec<u32> =
+                    pollster::block_on(runner.read_back_range(&words_buf, start..end)).unwrap();
+                assert_eq!(result, words[start..end]);
+            }
+
+            // Byte buffers of odd length are padded on the GPU, but not on
+            // the host.
+            let typed = GpuBuffer::<u8>::new(&runner.device, len, wgpu::BufferUsages::STORAGE);
+            typed.write(&runner.queue, &bytes).unwrap();
+            assert_eq!(pollster::block_on(typed.read(&runner)).unwrap(), bytes);
+        }
+
+        let words_buf = runner.upload(&[0u32; 4]);
+        let (start, end) = (8, 4);
+        let backwards = pollster::block_on(runner.read_back_ranges(&[(&words_buf, start..end)]));
+        assert!(matches!(backwards, Err(Error::InvalidInput(_))));
+    }
+
+    /// Each submission's timestamps are read back rather than left as
+    /// zeros, and results read after each run see that run.
+    #[test]
+    fn timestamps_are_read_back() {
+        let Some(runner) = ComputeRunner::for_tests() else {
+            return;
+        };
+        let len = 1037;
+        // Small integers keep the f32 sums exact, so they compare equal.
+        let floats = (0..len).map(|i| (i % 1024) as f32).collect::<Vec<_>>();
+        let floats_buf = runner.upload(&floats);
+        let source = format!("{}{}", INDEX_WGSL, include_str!("shader.wgsl"));
+        let (kernel, workgroup_size) = runner
+            .create_tuned_kernel(&source, "main", tune::DEFAULT_WORKGROUP_SIZE)
+            .unwrap();
+        let workgroups = tune::workgroups_for((len as u32, 1, 1), workgroup_size);
+        for i in 1..=5 {
+            let elapsed =
+                pollster::block_on(runner.run_kernel(&kernel, &[&floats_buf], workgroups)).unwrap();
+            let result: Vec<f32> = pollster::block_on(runner.read_back(&floats_buf, len)).unwrap();
+            let added = 42.0 * i as f32;
+            assert!(result.iter().zip(&floats).all(|(&r, &x)| r == x + added));
+            if let Some(elapsed) = elapsed {
+                assert!(elapsed > std::time::Duration::ZERO, "run {}", i);
+            }
+        }
+    }
+}
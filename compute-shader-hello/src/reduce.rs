@@ -0,0 +1,534 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! GPU reductions: sum, min, max and argmin.
+//!
+//! Each workgroup reduces a partition of the input to a single value with a
+//! tree in workgroup memory. If that leaves more than one value, the partial
+//! results are reduced again, until one is left. The workgroup size is chosen
+//! when the kernels are built.
+//!
+//! [`AtomicReducer`] is a naive reduction of `u32` elements using atomics,
+//! provided as a baseline for benchmarking.
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use crate::runner::grid;
+use crate::scan::ScanElement;
+use crate::tune::{instantiate, WorkgroupSize};
+use crate::{ComputeRunner, Error, Kernel};
+
+/// Number of elements each invocation loads before the tree reduction.
+///
+/// This must match `N_SEQ` in `reduce.wgsl`.
+pub const N_SEQ: usize = 4;
+
+/// Largest supported workgroup size; the minimum limit that WebGPU
+/// guarantees.
+pub const MAX_WORKGROUP_SIZE: u32 = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReduceOp {
+    Sum,
+    Min,
+    Max,
+    /// The minimum along with the index of its first occurrence.
+    ArgMin,
+}
+
+impl ReduceOp {
+    /// WGSL defining `identity` and `combine` for the operation.
+    fn prelude(self) -> &'static str {
+        match self {
+            ReduceOp::Sum => {
+                "fn identity() -> Acc { return Acc(ZERO, 0u); }
+fn combine(a: Acc, b: Acc) -> Acc { return Acc(a.value + b.value, 0u); }
+"
+            }
+            ReduceOp::Min | ReduceOp::ArgMin => {
+                "fn identity() -> Acc { return Acc(highest(), 0xffffffffu); }
+fn combine(a: Acc, b: Acc) -> Acc {
+    if (b.value < a.value || (b.value == a.value && b.index < a.index)) {
+        return b;
+    }
+    return a;
+}
+"
+            }
+            ReduceOp::Max => {
+                "fn identity() -> Acc { return Acc(lowest(), 0xffffffffu); }
+fn combine(a: Acc, b: Acc) -> Acc {
+    if (b.value > a.value || (b.value == a.value && b.index < a.index)) {
+        return b;
+    }
+    return a;
+}
+"
+            }
+        }
+    }
+}
+
+impl fmt::Display for ReduceOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ReduceOp::Sum => "sum",
+            ReduceOp::Min => "min",
+            ReduceOp::Max => "max",
+            ReduceOp::ArgMin => "argmin",
+        };
+        f.write_str(name)
+    }
+}
+
+/// An element type that can be reduced on the GPU.
+pub trait ReduceElement: ScanElement + PartialOrd {
+    /// WGSL defining `lowest` and `highest`, the identities for max and min.
+    const BOUNDS: &'static str;
+}
+
+impl ReduceElement for u32 {
+    const BOUNDS: &'static str = "fn lowest() -> Elem { return 0u; }
+fn highest() -> Elem { return 0xffffffffu; }
+";
+}
+
+impl ReduceElement for f32 {
+    const BOUNDS: &'static str = "fn lowest() -> Elem { return bitcast<f32>(0xff800000u); }
+fn highest() -> Elem { return bitcast<f32>(0x7f800000u); }
+";
+}
+
+/// Input definitions for the first pass, which reads the elements.
+pub(crate) const LOAD_ELEMENTS: &str = "[[block]]
+struct InputBuf {
+    data: [[stride(4)]] array<Elem>;
+};
+[[group(0), binding(1)]]
+var<storage, read_write> input: InputBuf;
+fn load(ix: u32) -> Acc { return Acc(input.data[ix], ix); }
+";
+
+/// Input definitions for later passes, which read partial results.
+pub(crate) const LOAD_PARTIALS: &str = "[[group(0), binding(1)]]
+var<storage, read_write> input: AccBuf;
+fn load(ix: u32) -> Acc { return input.data[ix]; }
+";
+
+/// The result of a reduction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reduced<T> {
+    pub value: T,
+    /// Index of the element `value` came from; zero for sums.
+    pub index: u32,
+}
+
+/// Parameters for one pass; matches `Params` in the shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct Params {
+    n: u32,
+    grid_width: u32,
+}
+
+/// Compiled reduction kernels for one element type and operation.
+pub struct Reducer<T> {
+    op: ReduceOp,
+    workgroup_size: u32,
+    first: Kernel,
+    rest: Kernel,
+    _phantom: PhantomData<T>,
+}
+
+/// One pass of a reduction, with the buffers it needs.
+struct Level {
+    params: wgpu::Buffer,
+    output: wgpu::Buffer,
+    n_partitions: u32,
+}
+
+impl<T: ReduceElement> Reducer<T> {
+    /// Build the kernels for `op`, with `workgroup_size` invocations per
+    /// workgroup.
+    ///
+    /// The workgroup size must be a power of two no larger than
+    /// [`MAX_WORKGROUP_SIZE`].
+    pub fn new(
+        runner: &ComputeRunner,
+        op: ReduceOp,
+        workgroup_size: u32,
+    ) -> Result<Reducer<T>, Error> {
+        if !workgroup_size.is_power_of_two() || workgroup_size > MAX_WORKGROUP_SIZE {
+            return Err(Error::InvalidInput(format!(
+                "workgroup size must be a power of two up to {}, got {}",
+                MAX_WORKGROUP_SIZE, workgroup_size
+            )));
+        }
+        let kernel = |load: &str| -> Result<Kernel, Error> {
+            let module = runner.create_shader_module(&Self::source(op, load, workgroup_size))?;
+            Ok(runner.create_kernel(&module, "main", 3))
+        };
+        Ok(Reducer {
+            op,
+            workgroup_size,
+            first: kernel(LOAD_ELEMENTS)?,
+            rest: kernel(LOAD_PARTIALS)?,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// The WGSL of the kernel loading its input with `load`, which is
+    /// [`LOAD_ELEMENTS`] or [`LOAD_PARTIALS`].
+    pub(crate) fn source(op: ReduceOp, load: &str, workgroup_size: u32) -> String {
+        let source = format!(
+            "{}{}{}{}{}{}",
+            T::PRELUDE,
+            T::BOUNDS,
+            include_str!("shaders/reduce_common.wgsl"),
+            op.prelude(),
+            load,
+            include_str!("shaders/reduce.wgsl"),
+        );
+        instantiate(&source, WorkgroupSize::linear(workgroup_size))
+    }
+
+    pub fn op(&self) -> ReduceOp {
+        self.op
+    }
+
+    /// Reduce the first `n` elements of `buf`.
+    pub async fn reduce(
+        &self,
+        runner: &ComputeRunner,
+        buf: &wgpu::Buffer,
+        n: usize,
+    ) -> Result<Reduced<T>, Error> {
+        if n == 0 {
+            return Err(Error::InvalidInput("cannot reduce zero elements".into()));
+        }
+        let pass = self.prepare(runner, buf, n);
+        runner
+            .submit(|encoder| pass.encode(runner, encoder))
+            .await?;
+        let result: Vec<T> = runner.read_back(pass.output(), 2).await?;
+        Ok(Reduced {
+            value: result[0],
+            index: bytemuck::cast(result[1]),
+        })
+    }
+
+    /// Allocate the buffers for reducing the first `n` elements of `buf`,
+    /// so that the reduction can be recorded along with other work.
+    ///
+    /// `n` must not be zero.
+    pub fn prepare<'a>(
+        &'a self,
+        runner: &ComputeRunner,
+        buf: &'a wgpu::Buffer,
+        n: usize,
+    ) -> ReducePass<'a, T> {
+        let partition_size = self.workgroup_size as usize * N_SEQ;
+        let mut levels = Vec::new();
+        let mut level_n = n;
+        loop {
+            let n_partitions = level_n.div_ceil(partition_size) as u32;
+            let params = Params {
+                n: level_n as u32,
+                grid_width: grid(n_partitions).0,
+            };
+            levels.push(Level {
+                params: runner
+                    .device
+                    .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: None,
+                        contents: bytemuck::bytes_of(&params),
+                        usage: wgpu::BufferUsages::STORAGE,
+                    }),
+                output: runner.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: None,
+                    size: n_partitions as u64 * 8,
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                    mapped_at_creation: false,
+                }),
+                n_partitions,
+            });
+            if n_partitions == 1 {
+                break;
+            }
+            level_n = n_partitions as usize;
+        }
+        ReducePass {
+            reducer: self,
+            buf,
+            levels,
+        }
+    }
+}
+
+/// A reduction with its buffers allocated, ready to be recorded.
+pub struct ReducePass<'a, T> {
+    reducer: &'a Reducer<T>,
+    buf: &'a wgpu::Buffer,
+    levels: Vec<Level>,
+}
+
+impl<'a, T> ReducePass<'a, T> {
+    /// Record the reduction into `encoder`.
+    pub fn encode(&self, runner: &ComputeRunner, encoder: &mut wgpu::CommandEncoder) {
+        let mut input = self.buf;
+        for (i, level) in self.levels.iter().enumerate() {
+            let kernel = if i == 0 {
+                &self.reducer.first
+            } else {
+                &self.reducer.rest
+            };
+            let bindings = [&level.params, input, &level.output];
+            runner.encode_kernel(encoder, kernel, &bindings, grid(level.n_partitions));
+            input = &level.output;
+        }
+    }
+
+    /// The buffer holding the result once the pass has run: the value
+    /// followed by its index, as a `u32`.
+    pub fn output(&self) -> &wgpu::Buffer {
+        &self.levels.last().unwrap().output
+    }
+}
+
+/// Naive reductions of `u32` elements, with one atomic operation per
+/// element.
+pub struct AtomicReducer {
+    sum: Kernel,
+    min: Kernel,
+    max: Kernel,
+}
+
+impl AtomicReducer {
+    pub fn new(runner: &ComputeRunner) -> Result<AtomicReducer, Error> {
+        let module = runner.create_shader_module(include_str!("shaders/reduce_atomic.wgsl"))?;
+        Ok(AtomicReducer {
+            sum: runner.create_kernel(&module, "atomic_sum", 3),
+            min: runner.create_kernel(&module, "atomic_min", 3),
+            max: runner.create_kernel(&module, "atomic_max", 3),
+        })
+    }
+
+    /// Allocate the buffers for reducing the first `n` elements of `buf`.
+    ///
+    /// Only sum, min and max are supported.
+    pub fn prepare<'a>(
+        &'a self,
+        runner: &ComputeRunner,
+        buf: &'a wgpu::Buffer,
+        n: usize,
+        op: ReduceOp,
+    ) -> Result<AtomicPass<'a>, Error> {
+        let (kernel, identity) = match op {
+            ReduceOp::Sum => (&self.sum, 0),
+            ReduceOp::Min => (&self.min, u32::MAX),
+            ReduceOp::Max => (&self.max, 0),
+            ReduceOp::ArgMin => {
+                return Err(Error::InvalidInput(
+                    "the atomic reduction does not support argmin".into(),
+                ))
+            }
+        };
+        let n_workgroups = n.div_ceil(256) as u32;
+        let params = Params {
+            n: n as u32,
+            grid_width: grid(n_workgroups).0,
+        };
+        let init = |contents: &[u8], usage| {
+            runner
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: None,
+                    contents,
+                    usage,
+                })
+        };
+        Ok(AtomicPass {
+            kernel,
+            buf,
+            params: init(bytemuck::bytes_of(&params), wgpu::BufferUsages::STORAGE),
+            result: init(
+                &[0; 4],
+                wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::COPY_SRC,
+            ),
+            identity: init(bytemuck::bytes_of(&identity), wgpu::BufferUsages::COPY_SRC),
+            n_workgroups,
+        })
+    }
+}
+
+/// An atomic reduction with its buffers allocated, ready to be recorded.
+pub struct AtomicPass<'a> {
+    kernel: &'a Kernel,
+    buf: &'a wgpu::Buffer,
+    params: wgpu::Buffer,
+    result: wgpu::Buffer,
+    /// Source for resetting `result` before each run.
+    identity: wgpu::Buffer,
+    n_workgroups: u32,
+}
+
+impl<'a> AtomicPass<'a> {
+    /// Record the reduction into `encoder`.
+    pub fn encode(&self, runner: &ComputeRunner, encoder: &mut wgpu::CommandEncoder) {
+        encoder.copy_buffer_to_buffer(&self.identity, 0, &self.result, 0, 4);
+        let bindings = [&self.params, self.buf, &self.result];
+        runner.encode_kernel(encoder, self.kernel, &bindings, grid(self.n_workgroups));
+    }
+
+    /// The buffer holding the result, a single `u32`, once the pass has run.
+    pub fn output(&self) -> &wgpu::Buffer {
+        &self.result
+    }
+}
+
+/// Reduce on the CPU, as a reference for checking the GPU results.
+///
+/// `input` must not be empty.
+pub fn reduce_cpu<T: ReduceElement>(input: &[T], op: ReduceOp) -> Reduced<T> {
+    let mut acc = Reduced {
+        value: input[0],
+        index: 0,
+    };
+    for (i, &x) in input.iter().enumerate().skip(1) {
+        let better = match op {
+            ReduceOp::Sum => {
+                acc.value = acc.value.combine(x);
+                false
+            }
+            ReduceOp::Min | ReduceOp::ArgMin => x < acc.value,
+            ReduceOp::Max => x > acc.value,
+        };
+        if better {
+            acc = Reduced {
+                value: x,
+                index: i as u32,
+            };
+        }
+    }
+    acc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPS: [ReduceOp; 4] = [
+        ReduceOp::Sum,
+        ReduceOp::Min,
+        ReduceOp::Max,
+        ReduceOp::ArgMin,
+    ];
+
+    /// Values from 10 to 59, each occurring many times.
+    fn input(n: usize) -> Vec<u32> {
+        (0..n as u32)
+            .map(|i| i.wrapping_mul(7919) % 50 + 10)
+            .collect()
+    }
+
+    #[test]
+    fn cpu_reduction_keeps_the_first_of_ties() {
+        let input = [5u32, 2, 7, 2, 7];
+        let reduced = |op| reduce_cpu(&input, op);
+        assert_eq!(reduced(ReduceOp::Sum).value, 23);
+        assert_eq!(reduced(ReduceOp::Min), Reduced { value: 2, index: 1 });
+        assert_eq!(reduced(ReduceOp::ArgMin), Reduced { value: 2, index: 1 });
+        assert_eq!(reduced(ReduceOp::Max), Reduced { value: 7, index: 2 });
+    }
+
+    #[test]
+    fn gpu_reductions_match_the_cpu() {
+        let Some(runner) = ComputeRunner::for_tests() else {
+            return;
+        };
+        // With 64 invocations a partition is 256 elements, so the larger
+        // sizes take two and three passes; with one invocation, 1000
+        // elements take five.
+        for (workgroup_size, lengths) in [(64, &[1, 255, 257, 70_000, 300_000][..]), (1, &[1000])] {
+            for op in OPS {
+                let reducer = Reducer::<u32>::new(&runner, op, workgroup_size).unwrap();
+                for &n in lengths {
+                    let input = input(n);
+                    let buf = runner.upload(&input);
+                    let result = pollster::block_on(reducer.reduce(&runner, &buf, n)).unwrap();
+                    let mut expected = reduce_cpu(&input, op);
+                    if op == ReduceOp::Sum {
+                        expected.index = 0;
+                    }
+                    assert_eq!(
+                        result, expected,
+                        "{} of {} elements, workgroup size {}",
+                        op, n, workgroup_size
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn gpu_ties_resolve_to_the_smallest_index() {
+        let Some(runner) = ComputeRunner::for_tests() else {
+            return;
+        };
+        // The extremes occur twice in each partition but the first, so ties
+        // are resolved both within and between workgroups.
+        let n = 5000;
+        let mut input = vec![100.0f32; n];
+        for i in (300..n - 256).step_by(256) {
+            for j in [i, i + 100] {
+                input[j] = 1.0;
+                input[j + 1] = 200.0;
+            }
+        }
+        let buf = runner.upload(&input);
+        for op in [ReduceOp::Min, ReduceOp::Max, ReduceOp::ArgMin] {
+            let reducer = Reducer::<f32>::new(&runner, op, 64).unwrap();
+            let result = pollster::block_on(reducer.reduce(&runner, &buf, n)).unwrap();
+            let expected = if op == ReduceOp::Max { 301 } else { 300 };
+            assert_eq!(result.index, expected, "{}", op);
+        }
+    }
+
+    #[test]
+    fn atomic_reduction_agrees_with_the_tree() {
+        let Some(runner) = ComputeRunner::for_tests() else {
+            return;
+        };
+        let atomic = AtomicReducer::new(&runner).unwrap();
+        let n = 70_000;
+        let buf = runner.upload(&input(n));
+        for op in [ReduceOp::Sum, ReduceOp::Min, ReduceOp::Max] {
+            let pass = atomic.prepare(&runner, &buf, n, op).unwrap();
+            pollster::block_on(runner.submit(|encoder| pass.encode(&runner, encoder))).unwrap();
+            let result: Vec<u32> = pollster::block_on(runner.read_back(pass.output(), 1)).unwrap();
+            let tree = Reducer::<u32>::new(&runner, op, 256).unwrap();
+            let expected = pollster::block_on(tree.reduce(&runner, &buf, n)).unwrap();
+            assert_eq!(result[0], expected.value, "{}", op);
+        }
+        assert!(atomic.prepare(&runner, &buf, n, ReduceOp::ArgMin).is_err());
+    }
+}
@@ -0,0 +1,912 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Reflection of shader interfaces with naga.
+//!
+//! [`ShaderLayout`] derives the bind group layouts of a shader from the
+//! resources it declares, so that the host doesn't have to keep a copy of
+//! them in sync with the source, and checks the resources the host binds
+//! against them.
+
+use std::fmt;
+
+use crate::tune::WorkgroupSize;
+use crate::{BindingKind, Dtype, Error};
+
+/// The element type of a storage buffer binding, as declared in the shader.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferElement {
+    pub dtype: Dtype,
+    /// The number of elements, if the array has a fixed size.
+    pub len: Option<usize>,
+}
+
+/// How the contents of a buffer binding are laid out, as declared in the
+/// shader, for checking the host's element type against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ElementLayout {
+    /// Bytes of other members before the array the struct ends in, if any.
+    pub offset: u64,
+    /// The stride of that array, or the size of the whole struct if it
+    /// doesn't end in one.
+    pub stride: u64,
+    /// The number of elements, if fixed; 1 for a struct without an array.
+    pub len: Option<usize>,
+}
+
+/// Parse WGSL source into a naga module.
+pub fn parse(source: &str) -> Result<naga::Module, Error> {
+    naga::front::wgsl::parse_str(source).map_err(|e| Error::Shader(e.emit_to_string(source)))
+}
+
+/// Validate a parsed module, returning the analysis needed for reflection.
+pub fn validate(module: &naga::Module) -> Result<naga::valid::ModuleInfo, Error> {
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::empty(),
+    )
+    .validate(module)
+    .map_err(|e| Error::Shader(error_chain(&e)))
+}
+
+/// Format an error along with all of its sources on one line.
+fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut msg = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        msg.push_str(": ");
+        msg.push_str(&e.to_string());
+        source = e.source();
+    }
+    msg
+}
+
+/// The workgroup size of the named entry point, if there is one.
+pub fn workgroup_size(module: &naga::Module, entry: &str) -> Option<WorkgroupSize> {
+    let [x, y, z] = module
+        .entry_points
+        .iter()
+        .find(|ep| ep.name == entry)?
+        .workgroup_size;
+    Some(WorkgroupSize(x, y, z))
+}
+
+/// The element type of the buffer bound at `group` and `binding`.
+///
+/// Returns `None` if nothing is bound there, or if the binding isn't an
+/// array of `f32`, `u32` or `i32`, possibly wrapped in a struct as its last
+/// member.
+pub fn buffer_element(module: &naga::Module, group: u32, binding: u32) -> Option<BufferElement> {
+    let var = module.global_variables.iter().find_map(|(_, var)| {
+        let rb = var.binding.as_ref()?;
+        (rb.group == group && rb.binding == binding).then_some(var)
+    })?;
+    let mut ty = &module.types[var.ty].inner;
+    if let naga::TypeInner::Struct { members, .. } = ty {
+        ty = &module.types[members.last()?.ty].inner;
+    }
+    let (base, size) = match ty {
+        naga::TypeInner::Array { base, size, .. } => (*base, size),
+        _ => return None,
+    };
+    let dtype = match module.types[base].inner {
+        naga::TypeInner::Scalar { kind, width: 4 } | naga::TypeInner::Atomic { kind, width: 4 } => {
+            match kind {
+                naga::ScalarKind::Float => Dtype::F32,
+                naga::ScalarKind::Uint => Dtype::U32,
+                naga::ScalarKind::Sint => Dtype::I32,
+                naga::ScalarKind::Bool => return None,
+            }
+        }
+        _ => return None,
+    };
+    let len = array_len(module, size);
+    Some(BufferElement { dtype, len })
+}
+
+/// The number of elements of an array, if it has a fixed size.
+fn array_len(module: &naga::Module, size: &naga::ArraySize) -> Option<usize> {
+    match size {
+        naga::ArraySize::Constant(c) => match module.constants[*c].inner {
+            naga::ConstantInner::Scalar {
+                value: naga::ScalarValue::Uint(n),
+                ..
+            } => Some(n as usize),
+            naga::ConstantInner::Scalar {
+                value: naga::ScalarValue::Sint(n),
+                ..
+            } => Some(n as usize),
+            _ => None,
+        },
+        naga::ArraySize::Dynamic => None,
+    }
+}
+
+/// The layout of a buffer binding of type `ty`, or `None` if it isn't a
+/// struct.
+fn element_layout(module: &naga::Module, ty: naga::Handle<naga::Type>) -> Option<ElementLayout> {
+    let (members, span) = match &module.types[ty].inner {
+        naga::TypeInner::Struct { members, span, .. } => (members, *span),
+        _ => return None,
+    };
+    if let Some(last) = members.last() {
+        if let naga::TypeInner::Array { size, stride, .. } = &module.types[last.ty].inner {
+            return Some(ElementLayout {
+                offset: last.offset as u64,
+                stride: *stride as u64,
+                len: array_len(module, size),
+            });
+        }
+    }
+    Some(ElementLayout {
+        offset: 0,
+        stride: span as u64,
+        len: Some(1),
+    })
+}
+
+impl BufferElement {
+    /// Check that data of type `dtype` with `len` elements can be bound as
+    /// this buffer, returning a description of the problem if not.
+    ///
+    /// Packed types must be bound as `u32` words.
+    pub fn check(&self, dtype: Dtype, len: usize) -> Result<(), String> {
+        let declared = if dtype.is_packed() { Dtype::U32 } else { dtype };
+        if declared != self.dtype {
+            return Err(format!(
+                "data is {}, but the shader declares array<{}>{}",
+                dtype,
+                self.dtype,
+                if dtype.is_packed() {
+                    " (packed types are bound as u32)"
+                } else {
+                    ""
+                }
+            ));
+        }
+        if let Some(declared_len) = self.len {
+            let words = (len * dtype.size()).div_ceil(4);
+            if words != declared_len {
+                return Err(format!(
+                    "data has {} {} elements, but the shader declares array<{}, {}>",
+                    len, dtype, self.dtype, declared_len
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A resource bound by a shader.
+#[derive(Clone, Debug)]
+pub struct Binding {
+    pub group: u32,
+    pub binding: u32,
+    /// The name of the global variable, if it has one.
+    pub name: Option<String>,
+    /// The stages of the entry points that use the resource.
+    pub visibility: wgpu::ShaderStages,
+    pub ty: wgpu::BindingType,
+    /// The layout of a buffer's contents, if reflected from the shader.
+    pub element: Option<ElementLayout>,
+}
+
+impl Binding {
+    /// How a buffer binding is bound, or `None` for textures and samplers.
+    pub fn buffer_kind(&self) -> Option<BindingKind> {
+        match self.ty {
+            wgpu::BindingType::Buffer { ty, .. } => Some(match ty {
+                wgpu::BufferBindingType::Uniform => BindingKind::Uniform,
+                wgpu::BufferBindingType::Storage { read_only: false } => BindingKind::Storage,
+                wgpu::BufferBindingType::Storage { read_only: true } => {
+                    BindingKind::StorageReadOnly
+                }
+            }),
+            _ => None,
+        }
+    }
+
+    fn layout_entry(&self) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding: self.binding,
+            visibility: self.visibility,
+            ty: self.ty,
+            count: None,
+        }
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "group {}, binding {}", self.group, self.binding)?;
+        if let Some(name) = &self.name {
+            write!(f, " (`{}`)", name)?;
+        }
+        Ok(())
+    }
+}
+
+/// The resources bound by some entry points of a shader, sorted by group
+/// and binding.
+#[derive(Clone, Debug)]
+pub struct ShaderLayout {
+    pub bindings: Vec<Binding>,
+}
+
+impl ShaderLayout {
+    /// Reflect the resources used by the named entry points of a validated
+    /// module.
+    ///
+    /// Resources the entry points don't use are left out, as wgpu doesn't
+    /// require them to be bound. Float textures are assumed filterable, and
+    /// samplers filtering, as the shader doesn't say.
+    pub fn new(
+        module: &naga::Module,
+        info: &naga::valid::ModuleInfo,
+        entry_points: &[&str],
+    ) -> Result<ShaderLayout, Error> {
+        let mut bindings: Vec<Binding> = Vec::new();
+        for &name in entry_points {
+            let (index, entry_point) = module
+                .entry_points
+                .iter()
+                .enumerate()
+                .find(|(_, ep)| ep.name == name)
+                .ok_or_else(|| Error::Shader(format!("no entry point named '{}'", name)))?;
+            let stage = match entry_point.stage {
+                naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+                naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+                naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+            };
+            let function_info = info.get_entry_point(index);
+            for (handle, var) in module.global_variables.iter() {
+                let rb = match &var.binding {
+                    Some(rb) if !function_info[handle].is_empty() => rb,
+                    _ => continue,
+                };
+                if let Some(b) = bindings
+                    .iter_mut()
+                    .find(|b| (b.group, b.binding) == (rb.group, rb.binding))
+                {
+                    b.visibility |= stage;
+                    continue;
+                }
+                bindings.push(Binding {
+                    group: rb.group,
+                    binding: rb.binding,
+                    name: var.name.clone().filter(|n| !n.is_empty()),
+                    visibility: stage,
+                    ty: binding_type(module, var).ok_or_else(|| {
+                        Error::Shader(format!(
+                            "can't bind `{}` at group {}, binding {}",
+                            var.name.as_deref().unwrap_or("_"),
+                            rb.group,
+                            rb.binding
+                        ))
+                    })?,
+                    element: element_layout(module, var.ty),
+                });
+            }
+        }
+        bindings.sort_by_key(|b| (b.group, b.binding));
+        Ok(ShaderLayout { bindings })
+    }
+
+    /// Parse and validate WGSL source, and reflect the resources used by the
+    /// named entry points.
+    pub fn from_wgsl(source: &str, entry_points: &[&str]) -> Result<ShaderLayout, Error> {
+        let module = parse(source)?;
+        let info = validate(&module)?;
+        ShaderLayout::new(&module, &info, entry_points)
+    }
+
+    /// The number of bind groups, including any empty ones below the last.
+    pub fn n_groups(&self) -> u32 {
+        self.bindings.last().map_or(0, |b| b.group + 1)
+    }
+
+    /// The binding at `group` and `binding`, if the shader uses one.
+    pub fn get(&self, group: u32, binding: u32) -> Option<&Binding> {
+        self.bindings
+            .iter()
+            .find(|b| (b.group, b.binding) == (group, binding))
+    }
+
+    /// Create a layout for each bind group, indexed by group number.
+    pub fn create_bind_group_layouts(&self, device: &wgpu::Device) -> Vec<wgpu::BindGroupLayout> {
+        (0..self.n_groups())
+            .map(|group| {
+                let entries = self
+                    .bindings
+                    .iter()
+                    .filter(|b| b.group == group)
+                    .map(Binding::layout_entry)
+                    .collect::<Vec<_>>();
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: None,
+                    entries: &entries,
+                })
+            })
+            .collect()
+    }
+
+    /// Check that `entries` supply exactly the resources the shader binds in
+    /// `group`, each of the right type and large enough.
+    ///
+    /// wgpu doesn't report the size of a buffer, so for buffers bound whole
+    /// it is looked up in `sizes`, and not checked if it isn't there.
+    pub fn check_group(
+        &self,
+        group: u32,
+        entries: &[wgpu::BindGroupEntry],
+        sizes: &[(&wgpu::Buffer, u64)],
+    ) -> Result<(), Error> {
+        let bound = entries
+            .iter()
+            .map(|entry| (entry.binding, Bound::new(&entry.resource, sizes)))
+            .collect::<Vec<_>>();
+        self.check_bound(group, &bound)
+    }
+
+    fn check_bound(&self, group: u32, bound: &[(u32, Bound)]) -> Result<(), Error> {
+        let mut problems = Vec::new();
+        for b in self.bindings.iter().filter(|b| b.group == group) {
+            match bound.iter().find(|(binding, _)| *binding == b.binding) {
+                Some((_, resource)) => {
+                    if let Err(e) = check_resource(b, resource) {
+                        problems.push(format!("{}: {}", b, e));
+                    }
+                }
+                None => problems.push(format!(
+                    "{}: shader uses {}, but nothing is bound",
+                    b,
+                    describe(&b.ty)
+                )),
+            }
+        }
+        for (binding, _) in bound {
+            if self.get(group, *binding).is_none() {
+                problems.push(format!(
+                    "group {}, binding {}: a resource is bound, but the shader doesn't use one",
+                    group, binding
+                ));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Bindings(problems.join("; ")))
+        }
+    }
+
+    /// Check `entries` against the shader, then create the bind group for
+    /// `group` from them, with its layout from `layouts`.
+    ///
+    /// Buffers need to be bound with a size for their size to be checked.
+    pub fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        layouts: &[wgpu::BindGroupLayout],
+        group: u32,
+        entries: &[wgpu::BindGroupEntry],
+    ) -> Result<wgpu::BindGroup, Error> {
+        self.check_group(group, entries, &[])?;
+        Ok(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &layouts[group as usize],
+            entries,
+        }))
+    }
+}
+
+/// The binding type of a global variable, as wgpu derives it.
+fn binding_type(module: &naga::Module, var: &naga::GlobalVariable) -> Option<wgpu::BindingType> {
+    Some(match &module.types[var.ty].inner {
+        naga::TypeInner::Struct {
+            top_level: true,
+            span,
+            ..
+        } => wgpu::BindingType::Buffer {
+            ty: match var.class {
+                naga::StorageClass::Uniform => wgpu::BufferBindingType::Uniform,
+                naga::StorageClass::Storage { access } => wgpu::BufferBindingType::Storage {
+                    read_only: !access.contains(naga::StorageAccess::STORE),
+                },
+                _ => return None,
+            },
+            has_dynamic_offset: false,
+            min_binding_size: wgpu::BufferSize::new(*span as u64),
+        },
+        naga::TypeInner::Image {
+            dim,
+            arrayed,
+            class,
+        } => {
+            let view_dimension = match (dim, arrayed) {
+                (naga::ImageDimension::D1, _) => wgpu::TextureViewDimension::D1,
+                (naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+                (naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+                (naga::ImageDimension::D3, _) => wgpu::TextureViewDimension::D3,
+                (naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+                (naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+            };
+            match *class {
+                naga::ImageClass::Sampled { kind, multi } => wgpu::BindingType::Texture {
+                    sample_type: match kind {
+                        naga::ScalarKind::Float => {
+                            wgpu::TextureSampleType::Float { filterable: true }
+                        }
+                        naga::ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                        naga::ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                        naga::ScalarKind::Bool => return None,
+                    },
+                    view_dimension,
+                    multisampled: multi,
+                },
+                naga::ImageClass::Depth { multi } => wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension,
+                    multisampled: multi,
+                },
+                naga::ImageClass::Storage { format, access } => wgpu::BindingType::StorageTexture {
+                    access: if !access.contains(naga::StorageAccess::LOAD) {
+                        wgpu::StorageTextureAccess::WriteOnly
+                    } else if !access.contains(naga::StorageAccess::STORE) {
+                        wgpu::StorageTextureAccess::ReadOnly
+                    } else {
+                        wgpu::StorageTextureAccess::ReadWrite
+                    },
+                    format: texture_format(format),
+                    view_dimension,
+                },
+            }
+        }
+        naga::TypeInner::Sampler { comparison } => wgpu::BindingType::Sampler {
+            filtering: true,
+            comparison: *comparison,
+        },
+        _ => return None,
+    })
+}
+
+fn texture_format(format: naga::StorageFormat) -> wgpu::TextureFormat {
+    use naga::StorageFormat as Sf;
+    use wgpu::TextureFormat as Tf;
+    match format {
+        Sf::R8Unorm => Tf::R8Unorm,
+        Sf::R8Snorm => Tf::R8Snorm,
+        Sf::R8Uint => Tf::R8Uint,
+        Sf::R8Sint => Tf::R8Sint,
+        Sf::R16Uint => Tf::R16Uint,
+        Sf::R16Sint => Tf::R16Sint,
+        Sf::R16Float => Tf::R16Float,
+        Sf::Rg8Unorm => Tf::Rg8Unorm,
+        Sf::Rg8Snorm => Tf::Rg8Snorm,
+        Sf::Rg8Uint => Tf::Rg8Uint,
+        Sf::Rg8Sint => Tf::Rg8Sint,
+        Sf::R32Uint => Tf::R32Uint,
+        Sf::R32Sint => Tf::R32Sint,
+        Sf::R32Float => Tf::R32Float,
+        Sf::Rg16Uint => Tf::Rg16Uint,
+        Sf::Rg16Sint => Tf::Rg16Sint,
+        Sf::Rg16Float => Tf::Rg16Float,
+        Sf::Rgba8Unorm => Tf::Rgba8Unorm,
+        Sf::Rgba8Snorm => Tf::Rgba8Snorm,
+        Sf::Rgba8Uint => Tf::Rgba8Uint,
+        Sf::Rgba8Sint => Tf::Rgba8Sint,
+        Sf::Rgb10a2Unorm => Tf::Rgb10a2Unorm,
+        Sf::Rg11b10Float => Tf::Rg11b10Float,
+        Sf::Rg32Uint => Tf::Rg32Uint,
+        Sf::Rg32Sint => Tf::Rg32Sint,
+        Sf::Rg32Float => Tf::Rg32Float,
+        Sf::Rgba16Uint => Tf::Rgba16Uint,
+        Sf::Rgba16Sint => Tf::Rgba16Sint,
+        Sf::Rgba16Float => Tf::Rgba16Float,
+        Sf::Rgba32Uint => Tf::Rgba32Uint,
+        Sf::Rgba32Sint => Tf::Rgba32Sint,
+        Sf::Rgba32Float => Tf::Rgba32Float,
+    }
+}
+
+/// A short description of a binding type, for error messages.
+fn describe(ty: &wgpu::BindingType) -> String {
+    match ty {
+        wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            ..
+        } => "a uniform buffer".into(),
+        wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            ..
+        } => format!(
+            "a {} storage buffer",
+            if *read_only {
+                "read-only"
+            } else {
+                "read-write"
+            }
+        ),
+        wgpu::BindingType::Texture { .. } => "a sampled texture".into(),
+        wgpu::BindingType::StorageTexture { format, .. } => {
+            format!("a {:?} storage texture", format)
+        }
+        wgpu::BindingType::Sampler { .. } => "a sampler".into(),
+    }
+}
+
+/// What is bound at a binding, as far as checking it against the shader
+/// goes.
+#[derive(Clone, Copy, Debug)]
+enum Bound {
+    /// A buffer, with the number of bytes bound if known.
+    Buffer(Option<u64>),
+    Sampler,
+    TextureView,
+    Array,
+}
+
+impl Bound {
+    fn new(resource: &wgpu::BindingResource, sizes: &[(&wgpu::Buffer, u64)]) -> Bound {
+        match resource {
+            wgpu::BindingResource::Buffer(buf) => Bound::Buffer(match buf.size {
+                Some(size) => Some(size.get()),
+                None => sizes
+                    .iter()
+                    .find(|(b, _)| std::ptr::eq(*b, buf.buffer))
+                    .map(|(_, size)| size.saturating_sub(buf.offset)),
+            }),
+            wgpu::BindingResource::Sampler(_) => Bound::Sampler,
+            wgpu::BindingResource::TextureView(_) => Bound::TextureView,
+            _ => Bound::Array,
+        }
+    }
+}
+
+/// Check that a resource can be bound where the shader declares `b`.
+fn check_resource(b: &Binding, resource: &Bound) -> Result<(), String> {
+    match (&b.ty, resource) {
+        (
+            wgpu::BindingType::Buffer {
+                min_binding_size, ..
+            },
+            Bound::Buffer(size),
+        ) => match (min_binding_size, size) {
+            (Some(min), Some(size)) if *size < min.get() => Err(format!(
+                "shader needs at least {} bytes, but {} are bound",
+                min, size
+            )),
+            _ => Ok(()),
+        },
+        (wgpu::BindingType::Sampler { .. }, Bound::Sampler)
+        | (
+            wgpu::BindingType::Texture { .. } | wgpu::BindingType::StorageTexture { .. },
+            Bound::TextureView,
+        ) => Ok(()),
+        _ => Err(format!(
+            "shader uses {}, but {} is bound",
+            describe(&b.ty),
+            match resource {
+                Bound::Buffer(_) => "a buffer",
+                Bound::Sampler => "a sampler",
+                Bound::TextureView => "a texture view",
+                Bound::Array => "an array of resources",
+            }
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHADER: &str = r#"
+[[block]]
+struct Params {
+    a: f32;
+    b: vec4<f32>;
+};
+
+[[block]]
+struct DataBuf {
+    n: u32;
+    data: [[stride(4)]] array<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: Params;
+
+[[group(0), binding(1)]]
+var<storage, read> input: DataBuf;
+
+[[group(0), binding(2)]]
+var<storage, read_write> output: DataBuf;
+
+[[group(1), binding(0)]]
+var image: texture_2d<f32>;
+
+[[group(1), binding(1)]]
+var image_sampler: sampler;
+
+[[group(1), binding(2)]]
+var target: texture_storage_2d<rgba8unorm, write>;
+
+[[stage(compute), workgroup_size(1)]]
+fn main() {
+    let c = textureSampleLevel(image, image_sampler, vec2<f32>(0.5, 0.5), 0.0);
+    textureStore(target, vec2<i32>(0, 0), c * params.b);
+    output.data[0] = input.data[0] * params.a + f32(input.n);
+}
+"#;
+
+    fn layout() -> ShaderLayout {
+        ShaderLayout::from_wgsl(SHADER, &["main"]).unwrap()
+    }
+
+    fn ty(layout: &ShaderLayout, group: u32, binding: u32) -> wgpu::BindingType {
+        layout.get(group, binding).unwrap().ty
+    }
+
+    fn problems(layout: &ShaderLayout, group: u32, bound: &[(u32, Bound)]) -> String {
+        match layout.check_bound(group, bound) {
+            Err(Error::Bindings(msg)) => msg,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn buffer_kinds() {
+        let layout = layout();
+        let kinds = (0..3)
+            .map(|binding| layout.get(0, binding).unwrap().buffer_kind())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                Some(BindingKind::Uniform),
+                Some(BindingKind::StorageReadOnly),
+                Some(BindingKind::Storage)
+            ]
+        );
+        assert_eq!(layout.get(1, 0).unwrap().buffer_kind(), None);
+        assert_eq!(layout.get(0, 1).unwrap().name.as_deref(), Some("input"));
+        assert_eq!(layout.n_groups(), 2);
+    }
+
+    #[test]
+    fn texture_types() {
+        let layout = layout();
+        assert_eq!(
+            ty(&layout, 1, 0),
+            wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            }
+        );
+        assert_eq!(
+            ty(&layout, 1, 1),
+            wgpu::BindingType::Sampler {
+                filtering: true,
+                comparison: false,
+            }
+        );
+        assert_eq!(
+            ty(&layout, 1, 2),
+            wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            }
+        );
+
+        let layout = ShaderLayout::from_wgsl(
+            r#"
+[[group(0), binding(0)]]
+var counts: texture_2d<u32>;
+
+[[group(0), binding(1)]]
+var heights: texture_storage_2d<r32float, read>;
+
+[[group(0), binding(2)]]
+var normals: texture_storage_2d<rgba16float, write>;
+
+[[stage(compute), workgroup_size(1)]]
+fn main() {
+    let h = textureLoad(heights, vec2<i32>(0, 0)).x;
+    let n = f32(textureLoad(counts, vec2<i32>(0, 0), 0).x);
+    textureStore(normals, vec2<i32>(0, 0), vec4<f32>(h, n, 0.0, 1.0));
+}
+"#,
+            &["main"],
+        )
+        .unwrap();
+        assert!(matches!(
+            ty(&layout, 0, 0),
+            wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Uint,
+                ..
+            }
+        ));
+        assert_eq!(
+            ty(&layout, 0, 1),
+            wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::ReadOnly,
+                format: wgpu::TextureFormat::R32Float,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            }
+        );
+        assert!(matches!(
+            ty(&layout, 0, 2),
+            wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: wgpu::TextureFormat::Rgba16Float,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn min_binding_sizes() {
+        let layout = layout();
+        let min = |binding| match ty(&layout, 0, binding) {
+            wgpu::BindingType::Buffer {
+                min_binding_size, ..
+            } => min_binding_size.map(|size| size.get()),
+            other => panic!("{:?}", other),
+        };
+        // `b` is aligned to 16 bytes, so the struct is 32.
+        assert_eq!(min(0), Some(32));
+        // A runtime-sized array needs room for at least one element.
+        assert_eq!(min(1), Some(8));
+        assert_eq!(min(2), Some(8));
+    }
+
+    #[test]
+    fn unused_resources_are_left_out() {
+        let source = format!(
+            "{}\n[[stage(compute), workgroup_size(1)]]\nfn other() {{}}\n",
+            SHADER
+        );
+        let layout = ShaderLayout::from_wgsl(&source, &["other"]).unwrap();
+        assert!(layout.bindings.is_empty());
+        let layout = ShaderLayout::from_wgsl(&source, &["main", "other"]).unwrap();
+        assert_eq!(layout.bindings.len(), 6);
+    }
+
+    #[test]
+    fn check_group_problems() {
+        let layout = layout();
+        let buffers = [
+            (0, Bound::Buffer(Some(32))),
+            (1, Bound::Buffer(None)),
+            (2, Bound::Buffer(Some(1024))),
+        ];
+        assert!(layout.check_bound(0, &buffers).is_ok());
+        let textures = [
+            (0, Bound::TextureView),
+            (1, Bound::Sampler),
+            (2, Bound::TextureView),
+        ];
+        assert!(layout.check_bound(1, &textures).is_ok());
+
+        assert_eq!(
+            problems(&layout, 0, &buffers[1..]),
+            "group 0, binding 0 (`params`): shader uses a uniform buffer, but nothing is bound"
+        );
+        assert_eq!(
+            problems(&layout, 1, &[(0, Bound::TextureView), (1, Bound::Sampler)]),
+            "group 1, binding 2 (`target`): shader uses a Rgba8Unorm storage texture, \
+             but nothing is bound"
+        );
+        let mut extra = buffers.to_vec();
+        extra.push((3, Bound::Buffer(Some(4))));
+        assert_eq!(
+            problems(&layout, 0, &extra),
+            "group 0, binding 3: a resource is bound, but the shader doesn't use one"
+        );
+        assert_eq!(
+            problems(&layout, 2, &[(0, Bound::Sampler)]),
+            "group 2, binding 0: a resource is bound, but the shader doesn't use one"
+        );
+
+        let wrong = [
+            (0, Bound::Sampler),
+            (1, Bound::TextureView),
+            (2, Bound::Buffer(Some(8))),
+        ];
+        assert_eq!(
+            problems(&layout, 0, &wrong),
+            "group 0, binding 0 (`params`): shader uses a uniform buffer, but a sampler is bound; \
+             group 0, binding 1 (`input`): shader uses a read-only storage buffer, \
+             but a texture view is bound"
+        );
+        assert_eq!(
+            problems(
+                &layout,
+                1,
+                &[
+                    (0, Bound::Buffer(None)),
+                    (1, Bound::Sampler),
+                    (2, Bound::Array)
+                ]
+            ),
+            "group 1, binding 0 (`image`): shader uses a sampled texture, but a buffer is bound; \
+             group 1, binding 2 (`target`): shader uses a Rgba8Unorm storage texture, \
+             but an array of resources is bound"
+        );
+    }
+
+    #[test]
+    fn buffers_must_be_large_enough() {
+        let layout = layout();
+        let bound = [
+            (0, Bound::Buffer(Some(16))),
+            (1, Bound::Buffer(Some(8))),
+            (2, Bound::Buffer(Some(4))),
+        ];
+        assert_eq!(
+            problems(&layout, 0, &bound),
+            "group 0, binding 0 (`params`): shader needs at least 32 bytes, but 16 are bound; \
+             group 0, binding 2 (`output`): shader needs at least 8 bytes, but 4 are bound"
+        );
+    }
+
+    /// Buffers bound whole are checked with the sizes the caller gives.
+    #[test]
+    fn whole_buffer_sizes() {
+        let Some(runner) = crate::ComputeRunner::for_tests() else {
+            return;
+        };
+        let layout = layout();
+        let buffer = |size| {
+            runner.device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })
+        };
+        let (params, input, output) = (buffer(16), buffer(64), buffer(64));
+        let entries = [&params, &input, &output]
+            .iter()
+            .zip(0..)
+            .map(|(buf, binding)| wgpu::BindGroupEntry {
+                binding,
+                resource: buf.as_entire_binding(),
+            })
+            .collect::<Vec<_>>();
+        assert!(layout.check_group(0, &entries, &[]).is_ok());
+        match layout.check_group(0, &entries, &[(&params, 16), (&input, 64)]) {
+            Err(Error::Bindings(msg)) => assert_eq!(
+                msg,
+                "group 0, binding 0 (`params`): shader needs at least 32 bytes, but 16 are bound"
+            ),
+            other => panic!("{:?}", other),
+        }
+        let offset = [wgpu::BindGroupEntry {
+            binding: 1,
+            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer: &input,
+                offset: 60,
+                size: None,
+            }),
+        }];
+        assert!(matches!(
+            Bound::new(&offset[0].resource, &[(&input, 64)]),
+            Bound::Buffer(Some(4))
+        ));
+    }
+}
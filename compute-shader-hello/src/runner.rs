@@ -0,0 +1,672 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! The compute runner: device setup, dispatch and readback.
+
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bytemuck::Pod;
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+use crate::profile::{Profiler, Trace};
+use crate::readback::map_read;
+use crate::reflect::{self, ShaderLayout};
+use crate::staging::StagingPool;
+use crate::tune::TuneCache;
+use crate::{AdapterOptions, Error, KernelSource};
+
+/// A compiled compute pipeline, along with the layout of its bindings.
+pub struct Kernel {
+    pub pipeline: wgpu::ComputePipeline,
+    /// One layout per bind group, indexed by group number.
+    pub bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+    /// The bindings the layouts were built from.
+    pub layout: ShaderLayout,
+    /// The entry point, which labels the kernel's passes when profiling.
+    pub entry: String,
+}
+
+impl Kernel {
+    /// Check that `bindings` supply exactly the buffers the kernel binds,
+    /// given as group, binding and buffer.
+    ///
+    /// Buffers are checked to be large enough for the shader if their size
+    /// is in `sizes`; see [`ShaderLayout::check_group`].
+    pub fn check_buffers(
+        &self,
+        bindings: &[(u32, u32, &wgpu::Buffer)],
+        sizes: &[(&wgpu::Buffer, u64)],
+    ) -> Result<(), Error> {
+        for group in 0..self.layout.n_groups() {
+            let entries = group_entries(bindings, group);
+            self.layout.check_group(group, &entries, sizes)?;
+        }
+        match bindings.iter().find(|b| b.0 >= self.layout.n_groups()) {
+            Some((group, binding, _)) => Err(Error::Bindings(format!(
+                "group {}, binding {}: a buffer is bound, but the shader doesn't use one",
+                group, binding
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The bind group entries for the buffers bound in `group`.
+fn group_entries<'a>(
+    bindings: &[(u32, u32, &'a wgpu::Buffer)],
+    group: u32,
+) -> Vec<wgpu::BindGroupEntry<'a>> {
+    bindings
+        .iter()
+        .filter(|(g, _, _)| *g == group)
+        .map(|(_, binding, buf)| wgpu::BindGroupEntry {
+            binding: *binding,
+            resource: buf.as_entire_binding(),
+        })
+        .collect()
+}
+
+/// How a buffer is bound to a kernel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BindingKind {
+    /// `var<storage, read_write>`
+    Storage,
+    /// `var<storage, read>`
+    StorageReadOnly,
+    /// `var<uniform>`
+    Uniform,
+}
+
+impl fmt::Display for BindingKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BindingKind::Storage => "storage",
+            BindingKind::StorageReadOnly => "storage_read_only",
+            BindingKind::Uniform => "uniform",
+        })
+    }
+}
+
+impl BindingKind {
+    fn binding_type(self) -> wgpu::BufferBindingType {
+        match self {
+            BindingKind::Storage => wgpu::BufferBindingType::Storage { read_only: false },
+            BindingKind::StorageReadOnly => wgpu::BufferBindingType::Storage { read_only: true },
+            BindingKind::Uniform => wgpu::BufferBindingType::Uniform,
+        }
+    }
+
+    /// The usage a buffer needs to be bound this way and filled by the host.
+    pub fn usage(self) -> wgpu::BufferUsages {
+        match self {
+            BindingKind::Storage | BindingKind::StorageReadOnly => {
+                wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST
+            }
+            BindingKind::Uniform => wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        }
+    }
+}
+
+/// Where and how one buffer is bound.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BindingLayout {
+    pub group: u32,
+    pub binding: u32,
+    pub kind: BindingKind,
+}
+
+/// A GPU device and queue, ready to run compute kernels.
+///
+/// If the adapter supports timestamp queries, a query set is created as well,
+/// and [`dispatch`](ComputeRunner::dispatch) reports the GPU time spent in
+/// the compute pass. With [`enable_profiling`](ComputeRunner::enable_profiling),
+/// each pass is timed separately as well.
+pub struct ComputeRunner {
+    pub adapter_info: wgpu::AdapterInfo,
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    pub query_set: Option<wgpu::QuerySet>,
+    /// Tuned workgroup sizes, loaded from [`TuneCache::default_path`], or
+    /// empty if that can't be read.
+    pub tune_cache: TuneCache,
+    profiler: Option<Mutex<Profiler>>,
+    timestamp_bufs: Mutex<Option<(wgpu::Buffer, wgpu::Buffer)>>,
+    pub(crate) staging: Mutex<StagingPool>,
+}
+
+impl ComputeRunner {
+    /// Create a runner on the default adapter, honoring the environment
+    /// variables described in [`AdapterOptions::from_env`].
+    pub async fn new() -> Result<ComputeRunner, Error> {
+        Self::with_options(&AdapterOptions::from_env()).await
+    }
+
+    /// Create a runner on an adapter chosen by `options`.
+    pub async fn with_options(options: &AdapterOptions) -> Result<ComputeRunner, Error> {
+        let instance = wgpu::Instance::new(options.backends());
+        let adapter = crate::request_adapter(&instance, options, None).await?;
+        let features = adapter.features();
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    features: features & wgpu::Features::TIMESTAMP_QUERY,
+                    limits: Default::default(),
+                },
+                None,
+            )
+            .await?;
+        let query_set = if features.contains(wgpu::Features::TIMESTAMP_QUERY) {
+            Some(device.create_query_set(&wgpu::QuerySetDescriptor {
+                count: 2,
+                ty: wgpu::QueryType::Timestamp,
+                label: None,
+            }))
+        } else {
+            None
+        };
+        Ok(ComputeRunner {
+            adapter_info: adapter.get_info(),
+            device,
+            queue,
+            query_set,
+            tune_cache: TuneCache::load_or_empty(&TuneCache::default_path()),
+            profiler: None,
+            timestamp_bufs: Mutex::new(None),
+            staging: Mutex::new(StagingPool::new(
+                wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            )),
+        })
+    }
+
+    /// Time every pass recorded by the runner, and add the timings of each
+    /// submission to `trace`.
+    ///
+    /// Kernel passes are labelled with their entry point, and the passes of
+    /// a [`Graph`](crate::graph::Graph) with their names. Fails if the
+    /// adapter doesn't support timestamp queries.
+    pub fn enable_profiling(&mut self, trace: &Trace) -> Result<(), Error> {
+        let profiler = Profiler::new(&self.device)?.with_trace(trace.clone());
+        self.profiler = Some(Mutex::new(profiler));
+        Ok(())
+    }
+
+    /// The trace passes are added to, if profiling is enabled.
+    pub fn trace(&self) -> Option<Trace> {
+        let profiler = self.profiler.as_ref()?.lock().unwrap();
+        profiler.trace().cloned()
+    }
+
+    /// Record commands with `record` in a scope named `label`, which is timed
+    /// if profiling is enabled.
+    pub fn profile_scope<R>(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        record: impl FnOnce(&mut wgpu::CommandEncoder) -> R,
+    ) -> R {
+        let profiler = match &self.profiler {
+            Some(profiler) => profiler,
+            None => return record(encoder),
+        };
+        // The lock isn't held while recording, which may open scopes of its
+        // own.
+        let scope = profiler
+            .lock()
+            .unwrap()
+            .begin_scope(&self.device, encoder, label);
+        let result = record(encoder);
+        profiler
+            .lock()
+            .unwrap()
+            .end_scope(&self.device, encoder, scope);
+        result
+    }
+
+    /// Compile a WGSL shader module.
+    ///
+    /// The source is checked with naga first, so that syntax and validation
+    /// errors are reported as an [`Error::Shader`] rather than a panic inside
+    /// wgpu.
+    pub fn create_shader_module(&self, source: &str) -> Result<wgpu::ShaderModule, Error> {
+        let module = reflect::parse(source)?;
+        reflect::validate(&module)?;
+        Ok(self
+            .device
+            .create_shader_module(&wgpu::ShaderModuleDescriptor {
+                label: None,
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            }))
+    }
+
+    /// Create a storage buffer initialized with `data`.
+    ///
+    /// The buffer can be bound to a kernel and read back afterwards.
+    pub fn upload<T: Pod>(&self, data: &[T]) -> wgpu::Buffer {
+        self.device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(data),
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::COPY_SRC,
+            })
+    }
+
+    /// Build the pipeline for a kernel with `n_bindings` storage buffers.
+    ///
+    /// The buffers are bound as read-write storage buffers in group 0, with
+    /// binding numbers counting up from 0.
+    pub fn create_kernel(
+        &self,
+        shader: &wgpu::ShaderModule,
+        entry: &str,
+        n_bindings: usize,
+    ) -> Kernel {
+        let layout = (0..n_bindings as u32)
+            .map(|binding| BindingLayout {
+                group: 0,
+                binding,
+                kind: BindingKind::Storage,
+            })
+            .collect::<Vec<_>>();
+        self.create_kernel_with_layout(shader, entry, &layout)
+    }
+
+    /// Build the pipeline for a kernel with the given bindings, in any number
+    /// of groups.
+    pub fn create_kernel_with_layout(
+        &self,
+        shader: &wgpu::ShaderModule,
+        entry: &str,
+        layout: &[BindingLayout],
+    ) -> Kernel {
+        let mut bindings = layout
+            .iter()
+            .map(|b| reflect::Binding {
+                group: b.group,
+                binding: b.binding,
+                name: None,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: b.kind.binding_type(),
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                element: None,
+            })
+            .collect::<Vec<_>>();
+        bindings.sort_by_key(|b| (b.group, b.binding));
+        self.create_kernel_from_layout(shader, entry, ShaderLayout { bindings })
+    }
+
+    /// Build the pipeline for a WGSL kernel, with the bind group layouts
+    /// derived from the resources its entry point uses.
+    pub fn create_reflected_kernel(&self, source: &str, entry: &str) -> Result<Kernel, Error> {
+        self.create_kernel_from_source(&KernelSource::Wgsl(source.into()), entry)
+    }
+
+    /// Build the pipeline for a kernel in any of the supported languages,
+    /// with the bind group layouts derived from the resources its entry point
+    /// uses.
+    pub fn create_kernel_from_source(
+        &self,
+        source: &KernelSource,
+        entry: &str,
+    ) -> Result<Kernel, Error> {
+        let module = source.parse()?;
+        let info = reflect::validate(&module)?;
+        let layout = ShaderLayout::new(&module, &info, &[entry])?;
+        let shader = self
+            .device
+            .create_shader_module(&wgpu::ShaderModuleDescriptor {
+                label: None,
+                source: source.to_wgpu(&module, &info)?,
+            });
+        Ok(self.create_kernel_from_layout(&shader, entry, layout))
+    }
+
+    fn create_kernel_from_layout(
+        &self,
+        shader: &wgpu::ShaderModule,
+        entry: &str,
+        layout: ShaderLayout,
+    ) -> Kernel {
+        let bind_group_layouts = layout.create_bind_group_layouts(&self.device);
+        let compute_pipeline_layout =
+            self.device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: None,
+                    bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(),
+                    push_constant_ranges: &[],
+                });
+        let pipeline = self
+            .device
+            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: None,
+                layout: Some(&compute_pipeline_layout),
+                module: shader,
+                entry_point: entry,
+            });
+        Kernel {
+            pipeline,
+            bind_group_layouts,
+            layout,
+            entry: entry.into(),
+        }
+    }
+
+    /// Create the bind groups for a kernel, from buffers and the group and
+    /// binding numbers they are bound to.
+    pub fn create_bind_groups(
+        &self,
+        kernel: &Kernel,
+        bindings: &[(u32, u32, &wgpu::Buffer)],
+    ) -> Vec<wgpu::BindGroup> {
+        kernel
+            .bind_group_layouts
+            .iter()
+            .enumerate()
+            .map(|(group, layout)| {
+                let entries = group_entries(bindings, group as u32);
+                self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: None,
+                    layout,
+                    entries: &entries,
+                })
+            })
+            .collect()
+    }
+
+    /// Run a kernel and wait for it to complete.
+    ///
+    /// This is a shortcut for [`create_kernel`](ComputeRunner::create_kernel)
+    /// followed by [`run_kernel`](ComputeRunner::run_kernel).
+    pub async fn dispatch(
+        &self,
+        shader: &wgpu::ShaderModule,
+        entry: &str,
+        bindings: &[&wgpu::Buffer],
+        workgroups: (u32, u32, u32),
+    ) -> Result<Option<Duration>, Error> {
+        let kernel = self.create_kernel(shader, entry, bindings.len());
+        self.run_kernel(&kernel, bindings, workgroups).await
+    }
+
+    /// Run a previously built kernel and wait for it to complete.
+    ///
+    /// Returns the GPU time spent in the compute pass, if timestamp queries
+    /// are available.
+    pub async fn run_kernel(
+        &self,
+        kernel: &Kernel,
+        bindings: &[&wgpu::Buffer],
+        workgroups: (u32, u32, u32),
+    ) -> Result<Option<Duration>, Error> {
+        self.submit(|encoder| self.encode_kernel(encoder, kernel, bindings, workgroups))
+            .await
+    }
+
+    /// Record a compute pass running `kernel` into `encoder`.
+    ///
+    /// This lets several kernels be recorded and submitted together with
+    /// [`submit`](ComputeRunner::submit).
+    pub fn encode_kernel(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        kernel: &Kernel,
+        bindings: &[&wgpu::Buffer],
+        workgroups: (u32, u32, u32),
+    ) {
+        let bindings = bindings
+            .iter()
+            .enumerate()
+            .map(|(i, buf)| (0, i as u32, *buf))
+            .collect::<Vec<_>>();
+        let bind_groups = self.create_bind_groups(kernel, &bindings);
+        self.encode_with_bind_groups(encoder, kernel, &bind_groups, workgroups);
+    }
+
+    /// Record a compute pass running `kernel` with bind groups from
+    /// [`create_bind_groups`](ComputeRunner::create_bind_groups).
+    pub fn encode_with_bind_groups(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        kernel: &Kernel,
+        bind_groups: &[wgpu::BindGroup],
+        workgroups: (u32, u32, u32),
+    ) {
+        self.profile_scope(encoder, &kernel.entry, |encoder| {
+            encode_dispatch(encoder, kernel, bind_groups, workgroups)
+        });
+    }
+
+    /// Record commands with `record`, submit them and wait for completion.
+    ///
+    /// Returns the GPU time taken by the recorded commands, if timestamp
+    /// queries are available.
+    pub async fn submit(
+        &self,
+        record: impl FnOnce(&mut wgpu::CommandEncoder),
+    ) -> Result<Option<Duration>, Error> {
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        if let Some(query_set) = &self.query_set {
+            encoder.write_timestamp(query_set, 0);
+        }
+        record(&mut encoder);
+        // Queries are resolved into a buffer which is copied to a mappable
+        // one, as they can't be resolved into that directly. The pair is
+        // reused across submissions, unless another one is still using it.
+        let timestamp_bufs = self.query_set.as_ref().map(|query_set| {
+            let bufs = self
+                .timestamp_bufs
+                .lock()
+                .unwrap()
+                .take()
+                .unwrap_or_else(|| self.create_timestamp_bufs());
+            encoder.write_timestamp(query_set, 1);
+            encoder.resolve_query_set(query_set, 0..2, &bufs.0, 0);
+            encoder.copy_buffer_to_buffer(&bufs.0, 0, &bufs.1, 0, TIMESTAMP_BUF_SIZE);
+            bufs
+        });
+        let frame = self
+            .profiler
+            .as_ref()
+            .and_then(|p| p.lock().unwrap().resolve(&self.device, &mut encoder));
+        self.queue.submit(Some(encoder.finish()));
+        if let Some(frame) = frame {
+            frame.read(&self.device, &self.queue).await?;
+        }
+
+        let Some(bufs) = timestamp_bufs else {
+            self.device.poll(wgpu::Maintain::Wait);
+            return Ok(None);
+        };
+        let ts_period = self.queue.get_timestamp_period();
+        let mut nanos = 0.0;
+        let result = map_read(&self.device, &[("timestamps", &bufs.1)], |_, data| {
+            let ts_data: &[u64] = bytemuck::cast_slice(data);
+            nanos = ts_data[1].saturating_sub(ts_data[0]) as f64 * ts_period as f64;
+        })
+        .await;
+        *self.timestamp_bufs.lock().unwrap() = Some(bufs);
+        result?;
+        Ok(Some(Duration::from_nanos(nanos as u64)))
+    }
+
+    /// Create a buffer to resolve the two timestamps of a submission into,
+    /// and one to copy them to for reading.
+    fn create_timestamp_bufs(&self) -> (wgpu::Buffer, wgpu::Buffer) {
+        let resolve = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("timestamp resolve"),
+            size: TIMESTAMP_BUF_SIZE,
+            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("timestamp readback"),
+            size: TIMESTAMP_BUF_SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        (resolve, readback)
+    }
+}
+
+/// Record a compute pass running `kernel`, without profiling it.
+pub(crate) fn encode_dispatch(
+    encoder: &mut wgpu::CommandEncoder,
+    kernel: &Kernel,
+    bind_groups: &[wgpu::BindGroup],
+    workgroups: (u32, u32, u32),
+) {
+    let mut cpass = encoder.begin_compute_pass(&Default::default());
+    cpass.set_pipeline(&kernel.pipeline);
+    for (group, bind_group) in bind_groups.iter().enumerate() {
+        cpass.set_bind_group(group as u32, bind_group, &[]);
+    }
+    cpass.dispatch(workgroups.0, workgroups.1, workgroups.2);
+}
+
+/// Size of the buffers the two timestamps of a submission are read through.
+const TIMESTAMP_BUF_SIZE: u64 = 16;
+
+/// Maximum number of workgroups in one dimension of a dispatch.
+///
+/// This is the default limit in wgpu, which doesn't expose the actual limit
+/// of the adapter yet.
+pub const MAX_WORKGROUPS_PER_DIMENSION: u32 = 65535;
+
+/// WGSL helpers for recovering a linear index in a kernel dispatched with
+/// [`grid`] or [`grid_for`].
+///
+/// Prepend this to a shader to use `linear_workgroup_index` and
+/// `linear_invocation_index`.
+pub const INDEX_WGSL: &str = include_str!("shaders/index.wgsl");
+
+/// A dispatch grid with at least `n_workgroups` workgroups, respecting the
+/// limit on workgroups per dimension.
+///
+/// The grid fills x before spilling into y, and y before z, so kernels can
+/// linearize the workgroup id as `x + (y + z * MAX) * MAX`, or equivalently
+/// `x + y * grid_width` in two dimensions, and skip ids past the end.
+pub fn grid(n_workgroups: u32) -> (u32, u32, u32) {
+    const MAX: u32 = MAX_WORKGROUPS_PER_DIMENSION;
+    if n_workgroups <= MAX {
+        (n_workgroups, 1, 1)
+    } else if n_workgroups <= MAX * MAX {
+        (MAX, n_workgroups.div_ceil(MAX), 1)
+    } else {
+        (MAX, MAX, n_workgroups.div_ceil(MAX * MAX))
+    }
+}
+
+/// A dispatch grid covering `n_elements` elements, one per invocation, with
+/// `workgroup_size` invocations per workgroup.
+pub fn grid_for(n_elements: usize, workgroup_size: u32) -> (u32, u32, u32) {
+    grid(n_elements.div_ceil(workgroup_size as usize) as u32)
+}
+
+/// Check a dispatch grid given explicitly, such as on the command line,
+/// against the limit on workgroups per dimension.
+pub fn check_grid(workgroups: (u32, u32, u32)) -> Result<(), Error> {
+    let (x, y, z) = workgroups;
+    if x.max(y).max(z) > MAX_WORKGROUPS_PER_DIMENSION {
+        return Err(Error::InvalidInput(format!(
+            "{} by {} by {} workgroups is more than the {} per dimension a dispatch allows",
+            x, y, z, MAX_WORKGROUPS_PER_DIMENSION
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+impl ComputeRunner {
+    /// A runner on a software adapter, for tests that need a device.
+    ///
+    /// Returns `None` if there is no such adapter, so that those tests can
+    /// skip their GPU checks rather than fail.
+    pub(crate) fn for_tests() -> Option<ComputeRunner> {
+        let options = AdapterOptions {
+            force_fallback: true,
+        };
+        match pollster::block_on(ComputeRunner::with_options(&options)) {
+            Ok(runner) => Some(runner),
+            Err(e @ Error::NoAdapter { .. }) => {
+                eprintln!("skipping GPU checks: {}", e);
+                None
+            }
+            Err(e) => panic!("creating a runner: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX: u32 = MAX_WORKGROUPS_PER_DIMENSION;
+
+    #[test]
+    fn grid_fills_x_then_y_then_z() {
+        assert_eq!(grid(0), (0, 1, 1));
+        assert_eq!(grid(1), (1, 1, 1));
+        assert_eq!(grid(MAX), (MAX, 1, 1));
+        assert_eq!(grid(MAX + 1), (MAX, 2, 1));
+        assert_eq!(grid(MAX * MAX), (MAX, MAX, 1));
+        assert_eq!(grid(MAX * MAX + 1), (MAX, MAX, 2));
+    }
+
+    #[test]
+    fn grid_covers_every_workgroup() {
+        for n in [1, 1000, MAX - 1, MAX, MAX + 1, 3 * MAX + 7, MAX * MAX + 1] {
+            let (x, y, z) = grid(n);
+            assert!(x <= MAX && y <= MAX && z <= MAX, "{}: {:?}", n, (x, y, z));
+            let total = x as u64 * y as u64 * z as u64;
+            assert!(total >= n as u64, "{} workgroups in {:?}", n, (x, y, z));
+            // Only the last row may be partly past the end, so the grid
+            // can't be more than one row too big.
+            let row = if z > 1 { x as u64 * y as u64 } else { x as u64 };
+            assert!(total - (n as u64) < row, "{}: {:?}", n, (x, y, z));
+        }
+    }
+
+    #[test]
+    fn check_grid_enforces_the_limit() {
+        assert!(check_grid((MAX, MAX, MAX)).is_ok());
+        assert!(check_grid((0, 1, 1)).is_ok());
+        for workgroups in [(MAX + 1, 1, 1), (1, MAX + 1, 1), (1, 1, u32::MAX)] {
+            assert!(
+                matches!(check_grid(workgroups), Err(Error::InvalidInput(_))),
+                "{:?}",
+                workgroups
+            );
+        }
+    }
+
+    #[test]
+    fn grid_for_rounds_up() {
+        assert_eq!(grid_for(0, 64), (0, 1, 1));
+        assert_eq!(grid_for(1, 64), (1, 1, 1));
+        assert_eq!(grid_for(64, 64), (1, 1, 1));
+        assert_eq!(grid_for(65, 64), (2, 1, 1));
+        assert_eq!(grid_for(MAX as usize * 256 + 1, 256), (MAX, 2, 1));
+    }
+}
@@ -0,0 +1,490 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! GPU prefix sum (scan).
+//!
+//! Two implementations are provided. [`ScanAlgorithm::ReduceThenScan`] is
+//! the classic multi-pass approach: reduce each partition, scan the
+//! partition totals (recursively), then scan each partition starting from
+//! its total. [`ScanAlgorithm::DecoupledLookback`] does the whole scan in a
+//! single pass, with each partition looking back at the results published by
+//! its predecessors.
+//!
+//! Both scan in place, and handle any length that fits in a buffer.
+
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use crate::graph::Graph;
+use crate::runner::grid;
+use crate::{ComputeRunner, Error, Kernel};
+
+/// Number of elements processed by one workgroup.
+///
+/// This must match `PARTITION_SIZE` in `scan_common.wgsl`.
+pub const PARTITION_SIZE: usize = 1024;
+
+/// Whether each output includes its own input element.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScanKind {
+    Inclusive,
+    Exclusive,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScanAlgorithm {
+    ReduceThenScan,
+    DecoupledLookback,
+}
+
+/// An element type that can be scanned on the GPU.
+pub trait ScanElement: Pod {
+    /// WGSL defining `Elem`, `ZERO`, `to_bits` and `from_bits`.
+    const PRELUDE: &'static str;
+
+    fn zero() -> Self;
+
+    /// Addition as done on the GPU; wrapping for integers.
+    fn combine(self, other: Self) -> Self;
+}
+
+impl ScanElement for u32 {
+    const PRELUDE: &'static str = "type Elem = u32;
+let ZERO: Elem = 0u;
+fn to_bits(x: Elem) -> u32 { return x; }
+fn from_bits(x: u32) -> Elem { return x; }
+";
+
+    fn zero() -> Self {
+        0
+    }
+
+    fn combine(self, other: Self) -> Self {
+        self.wrapping_add(other)
+    }
+}
+
+impl ScanElement for f32 {
+    const PRELUDE: &'static str = "type Elem = f32;
+let ZERO: Elem = 0.0;
+fn to_bits(x: Elem) -> u32 { return bitcast<u32>(x); }
+fn from_bits(x: u32) -> Elem { return bitcast<f32>(x); }
+";
+
+    fn zero() -> Self {
+        0.0
+    }
+
+    fn combine(self, other: Self) -> Self {
+        self + other
+    }
+}
+
+/// Parameters for one scan dispatch; matches `Params` in the shader.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct Params {
+    n: u32,
+    grid_width: u32,
+    exclusive: u32,
+    use_offsets: u32,
+}
+
+/// Compiled scan kernels for one element type.
+pub struct Scanner<T> {
+    reduce: Kernel,
+    scan: Kernel,
+    lookback: Kernel,
+    _phantom: PhantomData<T>,
+}
+
+/// One level of a reduce-then-scan, with the buffers it needs.
+struct Level {
+    params: wgpu::Buffer,
+    partials: wgpu::Buffer,
+    n_partitions: u32,
+}
+
+impl<T: ScanElement> Scanner<T> {
+    pub fn new(runner: &ComputeRunner) -> Result<Scanner<T>, Error> {
+        let [rts_source, lookback_source] = Self::sources();
+        let rts_module = runner.create_shader_module(&rts_source)?;
+        let lookback_module = runner.create_shader_module(&lookback_source)?;
+        Ok(Scanner {
+            reduce: runner.create_kernel(&rts_module, "reduce", 3),
+            scan: runner.create_kernel(&rts_module, "scan", 3),
+            lookback: runner.create_kernel(&lookback_module, "scan_lookback", 3),
+            _phantom: PhantomData,
+        })
+    }
+
+    /// The WGSL of the reduce-then-scan kernels and of the decoupled
+    /// look-back kernel.
+    pub(crate) fn sources() -> [String; 2] {
+        let common = include_str!("shaders/scan_common.wgsl");
+        [
+            include_str!("shaders/scan.wgsl"),
+            include_str!("shaders/scan_lookback.wgsl"),
+        ]
+        .map(|kernels| format!("{}{}{}", T::PRELUDE, common, kernels))
+    }
+
+    /// Scan the first `n` elements of `buf` in place.
+    ///
+    /// Returns the GPU time taken, if timestamp queries are available.
+    pub async fn scan(
+        &self,
+        runner: &ComputeRunner,
+        buf: &wgpu::Buffer,
+        n: usize,
+        kind: ScanKind,
+        algorithm: ScanAlgorithm,
+    ) -> Result<Option<Duration>, Error> {
+        if n == 0 {
+            return Ok(None);
+        }
+        let pass = self.prepare(runner, buf, n, kind, algorithm);
+        runner.submit(|encoder| pass.encode(runner, encoder)).await
+    }
+
+    /// Allocate the buffers for scanning the first `n` elements of `buf`,
+    /// so that the scan can be recorded along with other work.
+    ///
+    /// `n` must not be zero.
+    pub fn prepare<'a>(
+        &'a self,
+        runner: &ComputeRunner,
+        buf: &'a wgpu::Buffer,
+        n: usize,
+        kind: ScanKind,
+        algorithm: ScanAlgorithm,
+    ) -> ScanPass<'a, T> {
+        let plan = match algorithm {
+            ScanAlgorithm::ReduceThenScan => self.prepare_reduce_then_scan(runner, n, kind),
+            ScanAlgorithm::DecoupledLookback => self.prepare_lookback(runner, n, kind),
+        };
+        ScanPass {
+            scanner: self,
+            buf,
+            plan,
+        }
+    }
+
+    fn prepare_reduce_then_scan(&self, runner: &ComputeRunner, n: usize, kind: ScanKind) -> Plan {
+        // Level i scans the partials of level i - 1, and the last level fits
+        // in a single partition.
+        let mut levels = Vec::new();
+        let mut level_n = n;
+        let mut exclusive = kind == ScanKind::Exclusive;
+        loop {
+            let n_partitions = partitions(level_n);
+            let params = Params {
+                n: level_n as u32,
+                grid_width: grid(n_partitions).0,
+                exclusive: exclusive as u32,
+                use_offsets: (n_partitions > 1) as u32,
+            };
+            levels.push(Level {
+                params: params_buffer(runner, &params),
+                partials: runner.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: None,
+                    size: (n_partitions as usize * std::mem::size_of::<T>()) as u64,
+                    usage: wgpu::BufferUsages::STORAGE,
+                    mapped_at_creation: false,
+                }),
+                n_partitions,
+            });
+            if n_partitions == 1 {
+                break;
+            }
+            level_n = n_partitions as usize;
+            // Partition totals are always scanned exclusively, so that each
+            // partition starts from the sum of the ones before it.
+            exclusive = true;
+        }
+        Plan::ReduceThenScan { levels }
+    }
+
+    fn prepare_lookback(&self, runner: &ComputeRunner, n: usize, kind: ScanKind) -> Plan {
+        let n_partitions = partitions(n);
+        let params = params_buffer(
+            runner,
+            &Params {
+                n: n as u32,
+                grid_width: grid(n_partitions).0,
+                exclusive: (kind == ScanKind::Exclusive) as u32,
+                use_offsets: 0,
+            },
+        );
+        let state_size = ((1 + 3 * n_partitions as usize) * 4) as u64;
+        let state = runner.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: state_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let zeros = runner
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: &vec![0; state_size as usize],
+                usage: wgpu::BufferUsages::COPY_SRC,
+            });
+        Plan::DecoupledLookback(Box::new(Lookback {
+            params,
+            state,
+            zeros,
+            n_partitions,
+        }))
+    }
+}
+
+/// A scan with its buffers allocated, ready to be recorded.
+///
+/// A pass can be recorded any number of times, for example to scan the same
+/// buffer after each of several other passes have written to it.
+pub struct ScanPass<'a, T> {
+    scanner: &'a Scanner<T>,
+    buf: &'a wgpu::Buffer,
+    plan: Plan,
+}
+
+enum Plan {
+    ReduceThenScan { levels: Vec<Level> },
+    DecoupledLookback(Box<Lookback>),
+}
+
+/// Buffers for a decoupled lookback scan.
+struct Lookback {
+    params: wgpu::Buffer,
+    /// Partition counter and per-partition state; see `scan_lookback.wgsl`.
+    state: wgpu::Buffer,
+    /// Source for clearing `state` before each run.
+    zeros: wgpu::Buffer,
+    n_partitions: u32,
+}
+
+impl<'a, T> ScanPass<'a, T> {
+    /// Record the scan into `encoder`.
+    pub fn encode(&self, runner: &ComputeRunner, encoder: &mut wgpu::CommandEncoder) {
+        let scanner = self.scanner;
+        match &self.plan {
+            Plan::ReduceThenScan { levels } => {
+                let mut data = self.buf;
+                for level in levels {
+                    if level.n_partitions > 1 {
+                        let bindings = [&level.params, data, &level.partials];
+                        runner.encode_kernel(
+                            encoder,
+                            &scanner.reduce,
+                            &bindings,
+                            grid(level.n_partitions),
+                        );
+                    }
+                    data = &level.partials;
+                }
+                for (i, level) in levels.iter().enumerate().rev() {
+                    let data = if i == 0 {
+                        self.buf
+                    } else {
+                        &levels[i - 1].partials
+                    };
+                    let bindings = [&level.params, data, &level.partials];
+                    runner.encode_kernel(
+                        encoder,
+                        &scanner.scan,
+                        &bindings,
+                        grid(level.n_partitions),
+                    );
+                }
+            }
+            Plan::DecoupledLookback(lookback) => {
+                // The partition counter and flags must start at zero.
+                let state_size = (1 + 3 * lookback.n_partitions as u64) * 4;
+                encoder.copy_buffer_to_buffer(&lookback.zeros, 0, &lookback.state, 0, state_size);
+                let bindings = [&lookback.params, self.buf, &lookback.state];
+                runner.encode_kernel(
+                    encoder,
+                    &scanner.lookback,
+                    &bindings,
+                    grid(lookback.n_partitions),
+                );
+            }
+        }
+    }
+
+    /// Add the scan to `graph` as passes scanning the buffer named `buf`,
+    /// which must be the one the scan was prepared for.
+    ///
+    /// The scan's own buffers are added to the graph under names starting
+    /// with `buf`. The passes are named `scan reduce` and `scan` for
+    /// reduce-then-scan, and `scan clear` and `scan lookback` for decoupled
+    /// lookback.
+    pub fn add_to_graph<'g>(&'g self, graph: &mut Graph<'g>, buf: &str) {
+        let scanner = self.scanner;
+        match &self.plan {
+            Plan::ReduceThenScan { levels } => {
+                let names = (0..levels.len())
+                    .map(|i| {
+                        let params = format!("{}/params {}", buf, i);
+                        let partials = format!("{}/partials {}", buf, i);
+                        graph.import(&params, &levels[i].params);
+                        graph.import(&partials, &levels[i].partials);
+                        (params, partials)
+                    })
+                    .collect::<Vec<_>>();
+                let data = |i: usize| if i == 0 { buf } else { &names[i - 1].1 };
+                for (i, level) in levels.iter().enumerate() {
+                    if level.n_partitions > 1 {
+                        graph
+                            .dispatch("scan reduce", &scanner.reduce, grid(level.n_partitions))
+                            .read(0, 0, &names[i].0)
+                            .read(0, 1, data(i))
+                            .write(0, 2, &names[i].1);
+                    }
+                }
+                for (i, level) in levels.iter().enumerate().rev() {
+                    graph
+                        .dispatch("scan", &scanner.scan, grid(level.n_partitions))
+                        .read(0, 0, &names[i].0)
+                        .write(0, 1, data(i))
+                        .read(0, 2, &names[i].1);
+                }
+            }
+            Plan::DecoupledLookback(lookback) => {
+                let params = format!("{}/params", buf);
+                let state = format!("{}/state", buf);
+                let zeros = format!("{}/zeros", buf);
+                graph
+                    .import(&params, &lookback.params)
+                    .import(&state, &lookback.state)
+                    .import(&zeros, &lookback.zeros);
+                let state_size = (1 + 3 * lookback.n_partitions as u64) * 4;
+                graph.copy("scan clear", &zeros, &state, state_size);
+                graph
+                    .dispatch(
+                        "scan lookback",
+                        &scanner.lookback,
+                        grid(lookback.n_partitions),
+                    )
+                    .read(0, 0, &params)
+                    .write(0, 1, buf)
+                    .write(0, 2, &state);
+            }
+        }
+    }
+}
+
+/// Scan on the CPU, as a reference for checking the GPU results.
+pub fn scan_cpu<T: ScanElement>(input: &[T], kind: ScanKind) -> Vec<T> {
+    let mut sum = T::zero();
+    input
+        .iter()
+        .map(|&x| {
+            let prev = sum;
+            sum = sum.combine(x);
+            match kind {
+                ScanKind::Inclusive => sum,
+                ScanKind::Exclusive => prev,
+            }
+        })
+        .collect()
+}
+
+fn partitions(n: usize) -> u32 {
+    n.div_ceil(PARTITION_SIZE) as u32
+}
+
+fn params_buffer(runner: &ComputeRunner, params: &Params) -> wgpu::Buffer {
+    runner
+        .device
+        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::bytes_of(params),
+            usage: wgpu::BufferUsages::STORAGE,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_scan_is_a_prefix_sum() {
+        let input = [3u32, 1, 4, 1, 5];
+        assert_eq!(scan_cpu(&input, ScanKind::Inclusive), [3, 4, 8, 9, 14]);
+        assert_eq!(scan_cpu(&input, ScanKind::Exclusive), [0, 3, 4, 8, 9]);
+        assert_eq!(scan_cpu(&[u32::MAX, 2], ScanKind::Inclusive), [u32::MAX, 1]);
+    }
+
+    #[test]
+    fn gpu_scans_match_the_cpu() {
+        let Some(runner) = ComputeRunner::for_tests() else {
+            return;
+        };
+        let scanner = Scanner::<u32>::new(&runner).unwrap();
+        // One partially filled partition, several partitions with a partial
+        // last one, and more partitions than fit in one partition of
+        // partials, which takes two levels of reduce-then-scan.
+        let lengths = [
+            1,
+            1000,
+            3 * PARTITION_SIZE + 7,
+            PARTITION_SIZE * PARTITION_SIZE + 5,
+        ];
+        for algorithm in [
+            ScanAlgorithm::ReduceThenScan,
+            ScanAlgorithm::DecoupledLookback,
+        ] {
+            for kind in [ScanKind::Inclusive, ScanKind::Exclusive] {
+                for n in lengths {
+                    let input = (0..n as u32)
+                        .map(|i| i.wrapping_mul(2654435761) >> 24)
+                        .collect::<Vec<_>>();
+                    let buf = runner.upload(&input);
+                    pollster::block_on(scanner.scan(&runner, &buf, n, kind, algorithm)).unwrap();
+                    let result: Vec<u32> = pollster::block_on(runner.read_back(&buf, n)).unwrap();
+                    assert!(
+                        result == scan_cpu(&input, kind),
+                        "{:?} {:?} scan of {} elements",
+                        algorithm,
+                        kind,
+                        n
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn gpu_float_scan_matches_the_cpu() {
+        let Some(runner) = ComputeRunner::for_tests() else {
+            return;
+        };
+        let scanner = Scanner::<f32>::new(&runner).unwrap();
+        // Small integers keep the sums exact, whatever order they're added in.
+        let n = 2 * PARTITION_SIZE + 1;
+        let input = (0..n).map(|i| (i % 7) as f32).collect::<Vec<_>>();
+        let buf = runner.upload(&input);
+        let algorithm = ScanAlgorithm::DecoupledLookback;
+        pollster::block_on(scanner.scan(&runner, &buf, n, ScanKind::Inclusive, algorithm)).unwrap();
+        let result: Vec<f32> = pollster::block_on(runner.read_back(&buf, n)).unwrap();
+        assert_eq!(result, scan_cpu(&input, ScanKind::Inclusive));
+    }
+}
@@ -22,8 +22,18 @@ struct DataBuf {
 [[group(0), binding(0)]]
 var<storage, read_write> v_indices: DataBuf;
 
-[[stage(compute), workgroup_size(1)]]
-fn main([[builtin(global_invocation_id)]] global_id: vec3<u32>) {
+// Expects `INDEX_WGSL` to be prepended, so that it can be dispatched over a
+// grid with more workgroups than fit in one dimension. The workgroup size is
+// filled in by the tuner.
+[[stage(compute), workgroup_size(WORKGROUP_SIZE)]]
+fn main(
+    [[builtin(workgroup_id)]] wg_id: vec3<u32>,
+    [[builtin(local_invocation_index)]] local_ix: u32,
+) {
+    let ix = linear_invocation_index(wg_id, local_ix, WG_SIZE);
+    if (ix >= arrayLength(&v_indices.data)) {
+        return;
+    }
     // TODO: a more interesting computation than this.
-    v_indices.data[global_id.x] = v_indices.data[global_id.x] + 42.0;
+    v_indices.data[ix] = v_indices.data[ix] + 42.0;
 }
@@ -0,0 +1,170 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+// Matrix multiplication, C = A * B, with A of size m x k and B of size k x n,
+// all row-major f32. Each entry point is one step on the optimization ladder:
+//
+// - `naive`: each invocation computes one element of C, reading its inputs
+//   straight from global memory.
+// - `tiled`: a workgroup computes a 16x16 tile of C, staging 16x16 tiles of
+//   A and B in workgroup memory so that each input is loaded once per tile
+//   rather than once per invocation.
+// - `blocked`: a workgroup computes a 64x64 tile of C, and each invocation a
+//   4x4 block of it in registers, reusing every value loaded from workgroup
+//   memory four times.
+
+let TILE: u32 = 16u;
+let BLOCK: u32 = 64u;
+let BLOCK_K: u32 = 16u;
+let THREAD_BLOCK: u32 = 4u;
+
+[[block]]
+struct Params {
+    m: u32;
+    n: u32;
+    k: u32;
+};
+
+[[block]]
+struct Matrix {
+    data: [[stride(4)]] array<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: Params;
+
+[[group(0), binding(1)]]
+var<storage, read> a: Matrix;
+
+[[group(0), binding(2)]]
+var<storage, read> b: Matrix;
+
+[[group(0), binding(3)]]
+var<storage, read_write> c: Matrix;
+
+var<workgroup> tile_a: array<f32, 256>;
+var<workgroup> tile_b: array<f32, 256>;
+var<workgroup> block_a: array<f32, 1024>;
+var<workgroup> block_b: array<f32, 1024>;
+
+// Element (row, col) of A, or zero outside the matrix.
+fn load_a(row: u32, col: u32) -> f32 {
+    if (row < params.m && col < params.k) {
+        return a.data[row * params.k + col];
+    }
+    return 0.0;
+}
+
+// Element (row, col) of B, or zero outside the matrix.
+fn load_b(row: u32, col: u32) -> f32 {
+    if (row < params.k && col < params.n) {
+        return b.data[row * params.n + col];
+    }
+    return 0.0;
+}
+
+[[stage(compute), workgroup_size(16, 16)]]
+fn naive([[builtin(global_invocation_id)]] global_id: vec3<u32>) {
+    let row = global_id.y;
+    let col = global_id.x;
+    if (row >= params.m || col >= params.n) {
+        return;
+    }
+    var sum = 0.0;
+    for (var i = 0u; i < params.k; i = i + 1u) {
+        sum = sum + a.data[row * params.k + i] * b.data[i * params.n + col];
+    }
+    c.data[row * params.n + col] = sum;
+}
+
+[[stage(compute), workgroup_size(16, 16)]]
+fn tiled(
+    [[builtin(local_invocation_id)]] local_id: vec3<u32>,
+    [[builtin(workgroup_id)]] wg_id: vec3<u32>,
+) {
+    let tx = local_id.x;
+    let ty = local_id.y;
+    let row = wg_id.y * TILE + ty;
+    let col = wg_id.x * TILE + tx;
+    var sum = 0.0;
+    for (var k0 = 0u; k0 < params.k; k0 = k0 + TILE) {
+        tile_a[ty * TILE + tx] = load_a(row, k0 + tx);
+        tile_b[ty * TILE + tx] = load_b(k0 + ty, col);
+        workgroupBarrier();
+        for (var i = 0u; i < TILE; i = i + 1u) {
+            sum = sum + tile_a[ty * TILE + i] * tile_b[i * TILE + tx];
+        }
+        workgroupBarrier();
+    }
+    if (row < params.m && col < params.n) {
+        c.data[row * params.n + col] = sum;
+    }
+}
+
+[[stage(compute), workgroup_size(16, 16)]]
+fn blocked(
+    [[builtin(local_invocation_id)]] local_id: vec3<u32>,
+    [[builtin(local_invocation_index)]] local_ix: u32,
+    [[builtin(workgroup_id)]] wg_id: vec3<u32>,
+) {
+    let tx = local_id.x;
+    let ty = local_id.y;
+    let row0 = wg_id.y * BLOCK;
+    let col0 = wg_id.x * BLOCK;
+    // Invocation (tx, ty) computes rows ty + 16 * i and columns tx + 16 * j
+    // of the block, so that neighboring invocations touch neighboring
+    // columns.
+    var acc: array<f32, 16>;
+    for (var i = 0u; i < 16u; i = i + 1u) {
+        acc[i] = 0.0;
+    }
+    var a_reg: array<f32, 4>;
+    var b_reg: array<f32, 4>;
+    for (var k0 = 0u; k0 < params.k; k0 = k0 + BLOCK_K) {
+        // Each of the 256 invocations loads four elements of each block.
+        for (var i = 0u; i < 4u; i = i + 1u) {
+            let e = local_ix + i * 256u;
+            let a_row = e / BLOCK_K;
+            let a_col = e % BLOCK_K;
+            block_a[e] = load_a(row0 + a_row, k0 + a_col);
+            let b_row = e / BLOCK;
+            let b_col = e % BLOCK;
+            block_b[e] = load_b(k0 + b_row, col0 + b_col);
+        }
+        workgroupBarrier();
+        for (var kk = 0u; kk < BLOCK_K; kk = kk + 1u) {
+            for (var i = 0u; i < THREAD_BLOCK; i = i + 1u) {
+                a_reg[i] = block_a[(ty + i * TILE) * BLOCK_K + kk];
+                b_reg[i] = block_b[kk * BLOCK + tx + i * TILE];
+            }
+            for (var i = 0u; i < THREAD_BLOCK; i = i + 1u) {
+                for (var j = 0u; j < THREAD_BLOCK; j = j + 1u) {
+                    acc[i * THREAD_BLOCK + j] = acc[i * THREAD_BLOCK + j] + a_reg[i] * b_reg[j];
+                }
+            }
+        }
+        workgroupBarrier();
+    }
+    for (var i = 0u; i < THREAD_BLOCK; i = i + 1u) {
+        for (var j = 0u; j < THREAD_BLOCK; j = j + 1u) {
+            let row = row0 + ty + i * TILE;
+            let col = col0 + tx + j * TILE;
+            if (row < params.m && col < params.n) {
+                c.data[row * params.n + col] = acc[i * THREAD_BLOCK + j];
+            }
+        }
+    }
+}
@@ -0,0 +1,31 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+// Index helpers for kernels dispatched over a grid from `grid` or
+// `grid_for` in the runner. A grid only spills into the y and z dimensions
+// once x is full, so the linear workgroup index doesn't depend on the grid
+// size. Indices past the end of the data must be skipped.
+
+let MAX_WORKGROUPS_PER_DIMENSION: u32 = 65535u;
+
+fn linear_workgroup_index(wg_id: vec3<u32>) -> u32 {
+    return wg_id.x + (wg_id.y + wg_id.z * MAX_WORKGROUPS_PER_DIMENSION) * MAX_WORKGROUPS_PER_DIMENSION;
+}
+
+// The index of an invocation, for a one-dimensional workgroup size.
+fn linear_invocation_index(wg_id: vec3<u32>, local_index: u32, workgroup_size: u32) -> u32 {
+    return linear_workgroup_index(wg_id) * workgroup_size + local_index;
+}
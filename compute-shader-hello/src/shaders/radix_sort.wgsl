@@ -0,0 +1,177 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+// LSD radix sort passes. For each digit, `histogram` counts the digits in
+// each block of WG_SIZE keys, the host scans the counts (stored digit-major,
+// so the scan yields each block's starting offset for each digit), and
+// `scatter` moves the keys to their place. Scatter sorts each block locally
+// by digit first, which keeps the sort stable.
+
+let WG_SIZE: u32 = 256u;
+let LG_WG_SIZE: u32 = 8u;
+
+[[block]]
+struct Params {
+    n: u32;
+    shift: u32;
+    bits: u32;
+    n_blocks: u32;
+    grid_width: u32;
+    has_values: u32;
+};
+
+[[block]]
+struct Buf {
+    data: [[stride(4)]] array<u32>;
+};
+
+[[group(0), binding(0)]]
+var<storage, read_write> params: Params;
+
+[[group(0), binding(1)]]
+var<storage, read_write> keys_in: Buf;
+
+[[group(0), binding(2)]]
+var<storage, read_write> values_in: Buf;
+
+[[group(0), binding(3)]]
+var<storage, read_write> keys_out: Buf;
+
+[[group(0), binding(4)]]
+var<storage, read_write> values_out: Buf;
+
+[[group(0), binding(5)]]
+var<storage, read_write> block_hist: Buf;
+
+var<workgroup> sh_hist: array<atomic<u32>, WG_SIZE>;
+var<workgroup> sh_scan: array<u32, WG_SIZE>;
+var<workgroup> sh_start: array<u32, WG_SIZE>;
+var<workgroup> sh_keys: array<u32, WG_SIZE>;
+var<workgroup> sh_values: array<u32, WG_SIZE>;
+var<workgroup> sh_digits: array<u32, WG_SIZE>;
+var<workgroup> sh_valid: array<u32, WG_SIZE>;
+
+fn block_index(wg_id: vec3<u32>) -> u32 {
+    return wg_id.x + wg_id.y * params.grid_width;
+}
+
+fn digit_of(key: u32) -> u32 {
+    return (key >> params.shift) & ((1u << params.bits) - 1u);
+}
+
+// Exclusive scan of one value per invocation across the workgroup. The
+// total is left in `sh_scan[WG_SIZE - 1u]`.
+fn workgroup_scan(local_ix: u32, x: u32) -> u32 {
+    var agg = x;
+    sh_scan[local_ix] = agg;
+    for (var i = 0u; i < LG_WG_SIZE; i = i + 1u) {
+        workgroupBarrier();
+        if (local_ix >= (1u << i)) {
+            agg = sh_scan[local_ix - (1u << i)] + agg;
+        }
+        workgroupBarrier();
+        sh_scan[local_ix] = agg;
+    }
+    workgroupBarrier();
+    return agg - x;
+}
+
+[[stage(compute), workgroup_size(256)]]
+fn histogram(
+    [[builtin(local_invocation_id)]] local_id: vec3<u32>,
+    [[builtin(workgroup_id)]] wg_id: vec3<u32>,
+) {
+    let block = block_index(wg_id);
+    if (block >= params.n_blocks) {
+        return;
+    }
+    atomicStore(&sh_hist[local_id.x], 0u);
+    workgroupBarrier();
+    let ix = block * WG_SIZE + local_id.x;
+    if (ix < params.n) {
+        let old = atomicAdd(&sh_hist[digit_of(keys_in.data[ix])], 1u);
+    }
+    workgroupBarrier();
+    if (local_id.x < (1u << params.bits)) {
+        block_hist.data[local_id.x * params.n_blocks + block] = atomicLoad(&sh_hist[local_id.x]);
+    }
+}
+
+[[stage(compute), workgroup_size(256)]]
+fn scatter(
+    [[builtin(local_invocation_id)]] local_id: vec3<u32>,
+    [[builtin(workgroup_id)]] wg_id: vec3<u32>,
+) {
+    let block = block_index(wg_id);
+    if (block >= params.n_blocks) {
+        return;
+    }
+    let t = local_id.x;
+    let ix = block * WG_SIZE + t;
+    var key = 0u;
+    var value = 0u;
+    var valid = 0u;
+    // Slots past the end get the largest digit, so they stay behind all the
+    // real keys in the local sort.
+    var digit = (1u << params.bits) - 1u;
+    if (ix < params.n) {
+        key = keys_in.data[ix];
+        if (params.has_values != 0u) {
+            value = values_in.data[ix];
+        }
+        valid = 1u;
+        digit = digit_of(key);
+    }
+
+    // Where each digit starts within the locally sorted block.
+    atomicStore(&sh_hist[t], 0u);
+    workgroupBarrier();
+    if (valid != 0u) {
+        let old = atomicAdd(&sh_hist[digit], 1u);
+    }
+    workgroupBarrier();
+    sh_start[t] = workgroup_scan(t, atomicLoad(&sh_hist[t]));
+
+    // Stable local sort by digit, one bit at a time. At the start of each
+    // round, invocation t holds the element at position t.
+    for (var b = 0u; b < params.bits; b = b + 1u) {
+        let bit = (digit >> b) & 1u;
+        let zeros_before = workgroup_scan(t, 1u - bit);
+        let total_zeros = sh_scan[WG_SIZE - 1u];
+        var new_pos = zeros_before;
+        if (bit == 1u) {
+            new_pos = total_zeros + t - zeros_before;
+        }
+        workgroupBarrier();
+        sh_keys[new_pos] = key;
+        sh_values[new_pos] = value;
+        sh_digits[new_pos] = digit;
+        sh_valid[new_pos] = valid;
+        workgroupBarrier();
+        key = sh_keys[t];
+        value = sh_values[t];
+        digit = sh_digits[t];
+        valid = sh_valid[t];
+    }
+
+    if (valid != 0u) {
+        let dest = block_hist.data[digit * params.n_blocks + block] + t - sh_start[digit];
+        keys_out.data[dest] = key;
+        if (params.has_values != 0u) {
+            values_out.data[dest] = value;
+        }
+    }
+}
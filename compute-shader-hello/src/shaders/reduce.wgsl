@@ -0,0 +1,58 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+// Tree reduction. The host prepends definitions of `Elem`, `identity` and
+// `combine` for the element type and operation, and `load` for the kind of
+// input (elements, or the results of a previous pass). The workgroup size is
+// filled in as described in `tune.rs`.
+//
+// Each result carries the index of the element it came from, which is only
+// meaningful for min and max.
+
+let N_SEQ: u32 = 4u;
+
+[[stage(compute), workgroup_size(WORKGROUP_SIZE)]]
+fn main(
+    [[builtin(local_invocation_id)]] local_id: vec3<u32>,
+    [[builtin(workgroup_id)]] wg_id: vec3<u32>,
+) {
+    let partition_size = WG_SIZE * N_SEQ;
+    let n_partitions = (params.n + partition_size - 1u) / partition_size;
+    let part = wg_id.x + wg_id.y * params.grid_width;
+    if (part >= n_partitions) {
+        return;
+    }
+    // Consecutive invocations load consecutive elements.
+    let t = local_id.x;
+    var acc = identity();
+    for (var i = 0u; i < N_SEQ; i = i + 1u) {
+        let ix = part * partition_size + i * WG_SIZE + t;
+        if (ix < params.n) {
+            acc = combine(acc, load(ix));
+        }
+    }
+    sh_acc[t] = acc;
+    for (var stride = WG_SIZE / 2u; stride > 0u; stride = stride >> 1u) {
+        workgroupBarrier();
+        if (t < stride) {
+            acc = combine(acc, sh_acc[t + stride]);
+            sh_acc[t] = acc;
+        }
+    }
+    if (t == 0u) {
+        output.data[part] = acc;
+    }
+}
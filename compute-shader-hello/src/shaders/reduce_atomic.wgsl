@@ -0,0 +1,81 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+// Naive reductions of u32 elements, with every invocation applying an atomic
+// operation to a single result. This is the baseline that the tree reduction
+// in `reduce.wgsl` is compared against.
+
+[[block]]
+struct Params {
+    n: u32;
+    grid_width: u32;
+};
+
+[[block]]
+struct DataBuf {
+    data: [[stride(4)]] array<u32>;
+};
+
+[[block]]
+struct ResultBuf {
+    value: atomic<u32>;
+};
+
+[[group(0), binding(0)]]
+var<storage, read_write> params: Params;
+
+[[group(0), binding(1)]]
+var<storage, read_write> input: DataBuf;
+
+[[group(0), binding(2)]]
+var<storage, read_write> result: ResultBuf;
+
+fn element_index(local_id: vec3<u32>, wg_id: vec3<u32>) -> u32 {
+    return (wg_id.x + wg_id.y * params.grid_width) * 256u + local_id.x;
+}
+
+[[stage(compute), workgroup_size(256)]]
+fn atomic_sum(
+    [[builtin(local_invocation_id)]] local_id: vec3<u32>,
+    [[builtin(workgroup_id)]] wg_id: vec3<u32>,
+) {
+    let ix = element_index(local_id, wg_id);
+    if (ix < params.n) {
+        let old = atomicAdd(&result.value, input.data[ix]);
+    }
+}
+
+[[stage(compute), workgroup_size(256)]]
+fn atomic_min(
+    [[builtin(local_invocation_id)]] local_id: vec3<u32>,
+    [[builtin(workgroup_id)]] wg_id: vec3<u32>,
+) {
+    let ix = element_index(local_id, wg_id);
+    if (ix < params.n) {
+        let old = atomicMin(&result.value, input.data[ix]);
+    }
+}
+
+[[stage(compute), workgroup_size(256)]]
+fn atomic_max(
+    [[builtin(local_invocation_id)]] local_id: vec3<u32>,
+    [[builtin(workgroup_id)]] wg_id: vec3<u32>,
+) {
+    let ix = element_index(local_id, wg_id);
+    if (ix < params.n) {
+        let old = atomicMax(&result.value, input.data[ix]);
+    }
+}
@@ -0,0 +1,41 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+// Definitions shared by the reduction passes, after the element prelude.
+
+struct Acc {
+    value: Elem;
+    index: u32;
+};
+
+[[block]]
+struct Params {
+    n: u32;
+    grid_width: u32;
+};
+
+[[block]]
+struct AccBuf {
+    data: [[stride(8)]] array<Acc>;
+};
+
+[[group(0), binding(0)]]
+var<storage, read_write> params: Params;
+
+[[group(0), binding(2)]]
+var<storage, read_write> output: AccBuf;
+
+var<workgroup> sh_acc: array<Acc, WG_SIZE>;
@@ -0,0 +1,56 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+// Multi-pass reduce-then-scan. `reduce` writes the total of each partition
+// to `partials`; the host scans those (recursively, with the same kernels)
+// and then `scan` scans each partition, starting from its partial.
+
+[[group(0), binding(2)]]
+var<storage, read_write> partials: DataBuf;
+
+[[stage(compute), workgroup_size(256)]]
+fn reduce(
+    [[builtin(local_invocation_id)]] local_id: vec3<u32>,
+    [[builtin(workgroup_id)]] wg_id: vec3<u32>,
+) {
+    let part = partition_index(wg_id);
+    if (part * PARTITION_SIZE >= params.n) {
+        return;
+    }
+    let base = part * PARTITION_SIZE + local_id.x * N_SEQ;
+    // naga only accepts a call whose result is used as an expression.
+    let ignored = workgroup_scan(local_id.x, load_total(base));
+    if (local_id.x == 0u) {
+        partials.data[part] = sh_scan[WG_SIZE - 1u];
+    }
+}
+
+[[stage(compute), workgroup_size(256)]]
+fn scan(
+    [[builtin(local_invocation_id)]] local_id: vec3<u32>,
+    [[builtin(workgroup_id)]] wg_id: vec3<u32>,
+) {
+    let part = partition_index(wg_id);
+    if (part * PARTITION_SIZE >= params.n) {
+        return;
+    }
+    let base = part * PARTITION_SIZE + local_id.x * N_SEQ;
+    var prefix = workgroup_scan(local_id.x, load_total(base));
+    if (params.use_offsets != 0u) {
+        prefix = partials.data[part] + prefix;
+    }
+    write_scanned(base, prefix);
+}
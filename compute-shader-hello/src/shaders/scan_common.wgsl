@@ -0,0 +1,104 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+// Shared definitions for the scan kernels. The host prepends a prelude
+// defining `Elem`, `ZERO`, `to_bits` and `from_bits` for the element type.
+
+let WG_SIZE: u32 = 256u;
+let LG_WG_SIZE: u32 = 8u;
+let N_SEQ: u32 = 4u;
+let PARTITION_SIZE: u32 = 1024u;
+
+[[block]]
+struct Params {
+    n: u32;
+    grid_width: u32;
+    exclusive: u32;
+    use_offsets: u32;
+};
+
+[[block]]
+struct DataBuf {
+    data: [[stride(4)]] array<Elem>;
+};
+
+[[group(0), binding(0)]]
+var<storage, read_write> params: Params;
+
+[[group(0), binding(1)]]
+var<storage, read_write> data: DataBuf;
+
+var<workgroup> sh_scan: array<Elem, WG_SIZE>;
+
+fn partition_index(wg_id: vec3<u32>) -> u32 {
+    return wg_id.x + wg_id.y * params.grid_width;
+}
+
+// Scan one value per invocation across the workgroup.
+//
+// Returns the exclusive prefix for this invocation. The total over the
+// workgroup is left in `sh_scan[WG_SIZE - 1u]`.
+fn workgroup_scan(local_ix: u32, x: Elem) -> Elem {
+    var agg = x;
+    sh_scan[local_ix] = agg;
+    for (var i = 0u; i < LG_WG_SIZE; i = i + 1u) {
+        workgroupBarrier();
+        if (local_ix >= (1u << i)) {
+            agg = sh_scan[local_ix - (1u << i)] + agg;
+        }
+        workgroupBarrier();
+        sh_scan[local_ix] = agg;
+    }
+    workgroupBarrier();
+    var prefix = ZERO;
+    if (local_ix > 0u) {
+        prefix = sh_scan[local_ix - 1u];
+    }
+    return prefix;
+}
+
+// Sum this invocation's elements, treating those past the end as zero.
+fn load_total(base: u32) -> Elem {
+    var total = ZERO;
+    for (var i = 0u; i < N_SEQ; i = i + 1u) {
+        let ix = base + i;
+        if (ix < params.n) {
+            total = total + data.data[ix];
+        }
+    }
+    return total;
+}
+
+// Scan this invocation's elements in place, starting from `prefix_in`.
+//
+// Each invocation only touches its own elements, so they can be read again
+// here rather than kept in registers.
+fn write_scanned(base: u32, prefix_in: Elem) {
+    var prefix = prefix_in;
+    for (var i = 0u; i < N_SEQ; i = i + 1u) {
+        let ix = base + i;
+        if (ix < params.n) {
+            let x = data.data[ix];
+            if (params.exclusive != 0u) {
+                data.data[ix] = prefix;
+                prefix = prefix + x;
+            } else {
+                prefix = prefix + x;
+                data.data[ix] = prefix;
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+// Single-pass scan with decoupled lookback.
+//
+// Partitions are numbered in the order workgroups start, using an atomic
+// counter, so that a partition only ever waits on partitions that are
+// already running. Each partition publishes its aggregate as soon as it is
+// known, then looks back over its predecessors until it finds one with a
+// published inclusive prefix.
+//
+// The state buffer holds the counter followed by a (flag, aggregate,
+// inclusive prefix) triple per partition, and must be zeroed before each
+// run. Values are stored through atomics, and a storage barrier separates
+// writing a value from publishing its flag.
+
+let FLAG_AGGREGATE: u32 = 1u;
+let FLAG_PREFIX: u32 = 2u;
+
+[[block]]
+struct State {
+    data: [[stride(4)]] array<atomic<u32>>;
+};
+
+[[group(0), binding(2)]]
+var<storage, read_write> state: State;
+
+var<workgroup> sh_part: u32;
+var<workgroup> sh_flag: u32;
+var<workgroup> sh_exclusive: Elem;
+
+fn flag_ix(part: u32) -> u32 {
+    return 1u + 3u * part;
+}
+
+fn aggregate_ix(part: u32) -> u32 {
+    return 2u + 3u * part;
+}
+
+fn prefix_ix(part: u32) -> u32 {
+    return 3u + 3u * part;
+}
+
+[[stage(compute), workgroup_size(256)]]
+fn scan_lookback([[builtin(local_invocation_id)]] local_id: vec3<u32>) {
+    if (local_id.x == 0u) {
+        sh_part = atomicAdd(&state.data[0], 1u);
+        sh_exclusive = ZERO;
+    }
+    workgroupBarrier();
+    let part = sh_part;
+    if (part * PARTITION_SIZE >= params.n) {
+        return;
+    }
+    let base = part * PARTITION_SIZE + local_id.x * N_SEQ;
+    var prefix = workgroup_scan(local_id.x, load_total(base));
+    let aggregate = sh_scan[WG_SIZE - 1u];
+
+    if (local_id.x == 0u) {
+        if (part == 0u) {
+            atomicStore(&state.data[prefix_ix(part)], to_bits(aggregate));
+        } else {
+            atomicStore(&state.data[aggregate_ix(part)], to_bits(aggregate));
+        }
+    }
+    storageBarrier();
+    if (local_id.x == 0u) {
+        atomicStore(&state.data[flag_ix(part)], select(FLAG_AGGREGATE, FLAG_PREFIX, part == 0u));
+    }
+
+    if (part != 0u) {
+        var look = part - 1u;
+        loop {
+            if (local_id.x == 0u) {
+                sh_flag = atomicLoad(&state.data[flag_ix(look)]);
+            }
+            storageBarrier();
+            workgroupBarrier();
+            let flag = sh_flag;
+            if (flag == FLAG_PREFIX) {
+                if (local_id.x == 0u) {
+                    let p = from_bits(atomicLoad(&state.data[prefix_ix(look)]));
+                    sh_exclusive = p + sh_exclusive;
+                }
+                break;
+            }
+            if (flag == FLAG_AGGREGATE) {
+                if (local_id.x == 0u) {
+                    let a = from_bits(atomicLoad(&state.data[aggregate_ix(look)]));
+                    sh_exclusive = a + sh_exclusive;
+                }
+                look = look - 1u;
+            }
+            // Don't let the next flag load race with this iteration's reads.
+            workgroupBarrier();
+        }
+        workgroupBarrier();
+        let exclusive = sh_exclusive;
+        if (local_id.x == 0u) {
+            atomicStore(&state.data[prefix_ix(part)], to_bits(exclusive + aggregate));
+        }
+        storageBarrier();
+        if (local_id.x == 0u) {
+            atomicStore(&state.data[flag_ix(part)], FLAG_PREFIX);
+        }
+        prefix = exclusive + prefix;
+    }
+    write_scanned(base, prefix);
+}
@@ -0,0 +1,249 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Kernel source in WGSL, GLSL or SPIR-V.
+//!
+//! wgpu only takes WGSL, or SPIR-V with its `spirv` feature, so GLSL is
+//! parsed by naga and handed to wgpu translated to WGSL. All three are
+//! parsed with naga for validation and reflection.
+
+use std::borrow::Cow;
+use std::path::Path;
+
+use crate::Error;
+
+/// The magic number at the start of a SPIR-V module.
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+/// The source of a kernel, in one of the languages naga can read.
+#[derive(Clone, Debug)]
+pub enum KernelSource {
+    Wgsl(String),
+    /// A GLSL compute shader; its entry point is named `main`.
+    Glsl(String),
+    /// A SPIR-V module, as words. Needs the `spirv` feature to build.
+    SpirV(Vec<u32>),
+}
+
+impl KernelSource {
+    /// Read a kernel, picking the language by extension: `.wgsl`, `.comp`
+    /// for GLSL or `.spv`.
+    pub fn load(path: &Path) -> Result<KernelSource, Error> {
+        let read_string = || std::fs::read_to_string(path).map_err(|e| Error::Io(path.into(), e));
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("wgsl") => Ok(KernelSource::Wgsl(read_string()?)),
+            Some("comp") => Ok(KernelSource::Glsl(read_string()?)),
+            Some("spv") => {
+                let bytes = std::fs::read(path).map_err(|e| Error::Io(path.into(), e))?;
+                let words = bytes_to_u32(&bytes);
+                if bytes.len() % 4 != 0 || words.first() != Some(&SPIRV_MAGIC) {
+                    return Err(Error::Parse(path.into(), "not a SPIR-V module".into()));
+                }
+                Ok(KernelSource::SpirV(words))
+            }
+            _ => Err(Error::InvalidInput(format!(
+                "{}: expected a .wgsl, .comp (GLSL) or .spv kernel",
+                path.display()
+            ))),
+        }
+    }
+
+    /// The WGSL source, if this is WGSL.
+    pub fn as_wgsl(&self) -> Option<&str> {
+        match self {
+            KernelSource::Wgsl(source) => Some(source),
+            _ => None,
+        }
+    }
+
+    /// Parse the kernel into a naga module.
+    pub fn parse(&self) -> Result<naga::Module, Error> {
+        match self {
+            KernelSource::Wgsl(source) => crate::reflect::parse(source),
+            KernelSource::Glsl(source) => {
+                let options = naga::front::glsl::Options::from(naga::ShaderStage::Compute);
+                naga::front::glsl::Parser::default()
+                    .parse(&options, source)
+                    .map_err(|errors| {
+                        let messages = errors
+                            .iter()
+                            .map(|e| match e.meta.to_range() {
+                                Some(range) => {
+                                    let (line, col) = line_col(source, range.start);
+                                    format!("{}:{}: {}", line, col, e.kind)
+                                }
+                                None => e.kind.to_string(),
+                            })
+                            .collect::<Vec<_>>();
+                        Error::Shader(messages.join("\n"))
+                    })
+            }
+            #[cfg(feature = "spirv")]
+            KernelSource::SpirV(words) => {
+                naga::front::spv::Parser::new(words.iter().cloned(), &Default::default())
+                    .parse()
+                    .map_err(|e| Error::Shader(format!("{:?}", e)))
+            }
+            #[cfg(not(feature = "spirv"))]
+            KernelSource::SpirV(_) => Err(Error::Shader(
+                "SPIR-V kernels need the `spirv` feature".into(),
+            )),
+        }
+    }
+
+    /// The source to give wgpu, given the module parsed from it and its
+    /// validation info.
+    pub fn to_wgpu(
+        &self,
+        module: &naga::Module,
+        info: &naga::valid::ModuleInfo,
+    ) -> Result<wgpu::ShaderSource<'_>, Error> {
+        match self {
+            KernelSource::Wgsl(source) => Ok(wgpu::ShaderSource::Wgsl(Cow::Borrowed(source))),
+            KernelSource::Glsl(_) => naga::back::wgsl::write_string(module, info)
+                .map(|wgsl| wgpu::ShaderSource::Wgsl(Cow::Owned(wgsl)))
+                .map_err(|e| Error::Shader(format!("translating GLSL to WGSL: {}", e))),
+            #[cfg(feature = "spirv")]
+            KernelSource::SpirV(words) => Ok(wgpu::ShaderSource::SpirV(Cow::Borrowed(words))),
+            #[cfg(not(feature = "spirv"))]
+            KernelSource::SpirV(_) => Err(Error::Shader(
+                "SPIR-V kernels need the `spirv` feature".into(),
+            )),
+        }
+    }
+}
+
+/// The 1-based line and column of a byte offset into `source`.
+fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+/// Convert little-endian bytes, such as a SPIR-V binary loaded with
+/// `include_bytes!`, to words. Trailing bytes that don't fill a word are
+/// dropped.
+pub fn bytes_to_u32(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `bytes` to a fresh file named `name`, for `load` to read.
+    fn write_file(name: &str, bytes: &[u8]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("source-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    /// The header of an empty SPIR-V 1.0 module, as little-endian bytes.
+    fn spirv_header() -> Vec<u8> {
+        [SPIRV_MAGIC, 0x0001_0000, 0, 1, 0]
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect()
+    }
+
+    fn load_error(name: &str, bytes: &[u8]) -> String {
+        let path = write_file(name, bytes);
+        match KernelSource::load(&path) {
+            Err(Error::Parse(p, msg)) => {
+                assert_eq!(p, path);
+                msg
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn bytes_to_words() {
+        assert_eq!(
+            bytes_to_u32(&[0x03, 0x02, 0x23, 0x07, 1, 0, 0, 0]),
+            [SPIRV_MAGIC, 1]
+        );
+        assert_eq!(bytes_to_u32(&[1, 2, 3, 4, 5, 6]), [0x0403_0201]);
+        assert!(bytes_to_u32(&[1, 2, 3]).is_empty());
+    }
+
+    #[test]
+    fn spirv_modules_are_checked() {
+        let header = spirv_header();
+        match KernelSource::load(&write_file("empty.spv", &header)) {
+            Ok(KernelSource::SpirV(words)) => {
+                assert_eq!(words, [SPIRV_MAGIC, 0x0001_0000, 0, 1, 0])
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let mut wrong_magic = header.clone();
+        wrong_magic[0] = 0;
+        assert_eq!(load_error("magic.spv", &wrong_magic), "not a SPIR-V module");
+
+        let mut ragged = header.clone();
+        ragged.push(0);
+        assert_eq!(load_error("ragged.spv", &ragged), "not a SPIR-V module");
+
+        // A big-endian module starts with the magic number's bytes reversed.
+        let swapped = header
+            .chunks_exact(4)
+            .flat_map(|c| [c[3], c[2], c[1], c[0]])
+            .collect::<Vec<_>>();
+        assert_eq!(load_error("swapped.spv", &swapped), "not a SPIR-V module");
+
+        assert_eq!(load_error("empty_file.spv", &[]), "not a SPIR-V module");
+    }
+
+    #[test]
+    fn language_from_extension() {
+        let wgsl = write_file("k.wgsl", b"// wgsl");
+        assert_eq!(
+            KernelSource::load(&wgsl).unwrap().as_wgsl(),
+            Some("// wgsl")
+        );
+        let glsl = write_file("k.comp", b"// glsl");
+        assert!(matches!(
+            KernelSource::load(&glsl),
+            Ok(KernelSource::Glsl(source)) if source == "// glsl"
+        ));
+        match KernelSource::load(&write_file("k.hlsl", b"")) {
+            Err(Error::InvalidInput(msg)) => {
+                assert!(msg.ends_with("k.hlsl: expected a .wgsl, .comp (GLSL) or .spv kernel"))
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn glsl_errors_have_positions() {
+        let source = "#version 450\nlayout(local_size_x = 1) in;\nvoid main() {\n    x = 1;\n}\n";
+        match KernelSource::Glsl(source.into()).parse() {
+            Err(Error::Shader(msg)) => assert!(msg.starts_with("4:5: "), "{}", msg),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(line_col("ab\ncd", 4), (2, 2));
+        assert_eq!(line_col("ab", 10), (1, 3));
+    }
+}
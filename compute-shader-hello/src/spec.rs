@@ -0,0 +1,771 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Declarative descriptions of kernels with several bindings.
+//!
+//! A [`KernelSpec`] lists the buffers a kernel binds: where each is bound,
+//! how, what it starts out holding and whether to read it back afterwards.
+//! It can be built in Rust or loaded from a JSON manifest like this:
+//!
+//! ```json
+//! {
+//!     "shader": "saxpy.wgsl",
+//!     "entry": "main",
+//!     "buffers": [
+//!         { "name": "params", "binding": 0, "kind": "uniform", "data": [2.0] },
+//!         { "name": "x", "binding": 1, "file": "x.npy" },
+//!         { "name": "y", "binding": 2, "file": "y.npy", "read_back": true }
+//!     ]
+//! }
+//! ```
+//!
+//! Paths in a manifest are relative to the manifest. Buffers are bound in
+//! `group` 0 unless given, and start out as the contents of a `file`, inline
+//! `data`, or `len` zeroed elements. How each is bound is reflected from the
+//! shader; a `kind` is optional, and checked against the shader if given.
+//! WGSL shaders are preprocessed, with names from an optional `defines`
+//! object defined.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+use wgpu::util::DeviceExt;
+
+use crate::buffer::padded_size;
+use crate::npy;
+use crate::preprocess::Preprocessor;
+use crate::readback::map_read;
+use crate::reflect::{self, ShaderLayout};
+use crate::tune::{self, WorkgroupSize};
+use crate::{BindingKind, ComputeRunner, Dtype, Error, Kernel, KernelSource, INDEX_WGSL};
+
+/// What a buffer holds before the kernel runs.
+#[derive(Clone, Debug)]
+pub enum BufferInit {
+    /// `len` zeroed elements of type `dtype`.
+    Zeroed(Dtype, usize),
+    /// The contents of an array.
+    Array(npy::Array),
+    /// An array loaded from a `.npy` file, the named array in a `.npz`
+    /// archive, or raw `f32`s from any other file.
+    File(PathBuf, Option<String>),
+}
+
+/// One buffer bound to a kernel.
+#[derive(Clone, Debug)]
+pub struct BufferSpec {
+    pub name: String,
+    pub group: u32,
+    pub binding: u32,
+    /// How the buffer is bound; reflected from the shader if not given.
+    pub kind: Option<BindingKind>,
+    pub init: BufferInit,
+    /// Whether to copy the buffer back to the host after the kernel runs.
+    pub read_back: bool,
+}
+
+impl BufferSpec {
+    pub fn new(name: impl Into<String>, group: u32, binding: u32, init: BufferInit) -> BufferSpec {
+        BufferSpec {
+            name: name.into(),
+            group,
+            binding,
+            kind: None,
+            init,
+            read_back: false,
+        }
+    }
+
+    /// Require the buffer to be bound this way.
+    pub fn kind(mut self, kind: BindingKind) -> BufferSpec {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Read the buffer back after the kernel runs.
+    pub fn read_back(mut self) -> BufferSpec {
+        self.read_back = true;
+        self
+    }
+}
+
+/// A kernel along with the buffers it binds.
+#[derive(Clone, Debug)]
+pub struct KernelSpec {
+    /// The kernel; WGSL kernels may be tunable.
+    pub source: KernelSource,
+    pub entry: String,
+    /// The number of workgroups to dispatch. If not given, one invocation is
+    /// dispatched per element of the first read-write storage buffer.
+    pub workgroups: Option<(u32, u32, u32)>,
+    pub buffers: Vec<BufferSpec>,
+}
+
+impl KernelSpec {
+    /// A spec for a WGSL kernel.
+    pub fn new(source: impl Into<String>) -> KernelSpec {
+        KernelSpec::from_source(KernelSource::Wgsl(source.into()))
+    }
+
+    /// A spec for a kernel in any of the supported languages.
+    pub fn from_source(source: KernelSource) -> KernelSpec {
+        KernelSpec {
+            source,
+            entry: "main".into(),
+            workgroups: None,
+            buffers: Vec::new(),
+        }
+    }
+
+    pub fn entry(mut self, entry: impl Into<String>) -> KernelSpec {
+        self.entry = entry.into();
+        self
+    }
+
+    pub fn workgroups(mut self, workgroups: (u32, u32, u32)) -> KernelSpec {
+        self.workgroups = Some(workgroups);
+        self
+    }
+
+    pub fn buffer(mut self, buffer: BufferSpec) -> KernelSpec {
+        self.buffers.push(buffer);
+        self
+    }
+
+    /// Load a spec from a JSON manifest.
+    ///
+    /// WGSL shaders are run through `preprocessor`, with the manifest's
+    /// `defines` added for names it doesn't already define.
+    pub fn load(path: &Path, preprocessor: &Preprocessor) -> Result<KernelSpec, Error> {
+        let json = std::fs::read_to_string(path).map_err(|e| Error::Io(path.into(), e))?;
+        let manifest: Manifest =
+            serde_json::from_str(&json).map_err(|e| Error::Parse(path.into(), e.to_string()))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let shader = dir.join(&manifest.shader);
+        let preprocessor = manifest
+            .defines
+            .iter()
+            .filter(|(name, _)| !preprocessor.is_defined(name))
+            .fold(preprocessor.clone(), |p, (name, value)| {
+                p.define(name, value)
+            });
+        let source = preprocessor.load_kernel(&shader)?;
+        let buffers = manifest
+            .buffers
+            .into_iter()
+            .map(|b| b.into_spec(dir))
+            .collect::<Result<_, _>>()
+            .map_err(|e| Error::Parse(path.into(), e))?;
+        Ok(KernelSpec {
+            source,
+            entry: manifest.entry,
+            workgroups: manifest.workgroups.map(|[x, y, z]| (x, y, z)),
+            buffers,
+        })
+    }
+
+    /// Load the buffers' contents, create them along with the pipeline,
+    /// bind groups and staging buffers, and check them against the bindings
+    /// the shader declares.
+    ///
+    /// The index helpers are prepended to WGSL source, as for kernels run from
+    /// the command line.
+    pub fn build(&self, runner: &ComputeRunner) -> Result<SpecKernel, Error> {
+        self.check_unique()?;
+        let contents = self
+            .buffers
+            .iter()
+            .map(|b| b.init.load())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let source = match &self.source {
+            KernelSource::Wgsl(wgsl) => {
+                let wgsl = format!("{}{}", INDEX_WGSL, wgsl);
+                if tune::is_tunable(&wgsl) {
+                    let size = runner.tuned_size(&wgsl, &self.entry, tune::DEFAULT_WORKGROUP_SIZE);
+                    KernelSource::Wgsl(tune::instantiate(&wgsl, size))
+                } else {
+                    KernelSource::Wgsl(wgsl)
+                }
+            }
+            other => other.clone(),
+        };
+        let module = source.parse()?;
+        let kernel = runner.create_kernel_from_source(&source, &self.entry)?;
+        let kinds = self.check_buffers(&module, &kernel.layout, &contents)?;
+
+        let workgroups = match self.workgroups {
+            Some(workgroups) => workgroups,
+            None => {
+                let n = kinds
+                    .iter()
+                    .zip(&contents)
+                    .find(|(kind, _)| **kind == BindingKind::Storage)
+                    .map(|(_, array)| array.len() as u32)
+                    .ok_or_else(|| {
+                        Error::InvalidInput(
+                            "no read-write storage buffer to size the dispatch by; \
+                             give the number of workgroups"
+                                .into(),
+                        )
+                    })?;
+                let size =
+                    reflect::workgroup_size(&module, &self.entry).unwrap_or(WorkgroupSize(1, 1, 1));
+                tune::workgroups_for((n, 1, 1), size)
+            }
+        };
+
+        let buffers = self
+            .buffers
+            .iter()
+            .zip(kinds)
+            .zip(contents)
+            .map(|((spec, kind), array)| SpecBuffer::new(runner, spec, kind, array))
+            .collect::<Vec<_>>();
+        let bindings = self
+            .buffers
+            .iter()
+            .zip(&buffers)
+            .map(|(spec, buf)| (spec.group, spec.binding, &buf.buffer))
+            .collect::<Vec<_>>();
+        let sizes = buffers
+            .iter()
+            .map(|buf| (&buf.buffer, padded_size(buf.size)))
+            .collect::<Vec<_>>();
+        kernel.check_buffers(&bindings, &sizes)?;
+        let bind_groups = runner.create_bind_groups(&kernel, &bindings);
+        Ok(SpecKernel {
+            kernel,
+            bind_groups,
+            buffers,
+            workgroups,
+        })
+    }
+
+    /// Check that no two buffers share a name or a binding.
+    fn check_unique(&self) -> Result<(), Error> {
+        for (i, a) in self.buffers.iter().enumerate() {
+            for b in &self.buffers[..i] {
+                if a.name == b.name {
+                    return Err(Error::InvalidInput(format!(
+                        "two buffers are named '{}'",
+                        a.name
+                    )));
+                }
+                if (a.group, a.binding) == (b.group, b.binding) {
+                    return Err(Error::InvalidInput(format!(
+                        "'{}' and '{}' are both bound at group {}, binding {}",
+                        b.name, a.name, a.group, a.binding
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check each buffer and its contents against the binding the shader
+    /// declares for it, returning how each is bound.
+    fn check_buffers(
+        &self,
+        module: &naga::Module,
+        layout: &ShaderLayout,
+        contents: &[npy::Array],
+    ) -> Result<Vec<BindingKind>, Error> {
+        let mut kinds = Vec::with_capacity(self.buffers.len());
+        for (spec, array) in self.buffers.iter().zip(contents) {
+            let mismatch = |msg: String| Error::Bindings(format!("'{}': {}", spec.name, msg));
+            let binding = layout.get(spec.group, spec.binding).ok_or_else(|| {
+                mismatch(format!(
+                    "the shader doesn't use group {}, binding {}",
+                    spec.group, spec.binding
+                ))
+            })?;
+            let kind = match (binding.buffer_kind(), spec.kind) {
+                (None, _) => return Err(mismatch(format!("{} isn't a buffer", binding))),
+                (Some(declared), Some(kind)) if declared != kind => {
+                    return Err(mismatch(format!(
+                        "bound as {}, but the shader declares {}",
+                        kind, declared
+                    )))
+                }
+                (Some(declared), _) => declared,
+            };
+            if let wgpu::BindingType::Buffer {
+                min_binding_size: Some(min),
+                ..
+            } = binding.ty
+            {
+                if padded_size(array.data.len()) < min.get() {
+                    return Err(mismatch(format!(
+                        "{} bytes of data, but the shader needs at least {}",
+                        array.data.len(),
+                        min
+                    )));
+                }
+            }
+            if kind != BindingKind::Uniform {
+                if let Some(element) = reflect::buffer_element(module, spec.group, spec.binding) {
+                    element
+                        .check(array.dtype, array.len())
+                        .map_err(|e| Error::InvalidInput(format!("{}: {}", spec.name, e)))?;
+                }
+            }
+            kinds.push(kind);
+        }
+        Ok(kinds)
+    }
+}
+
+impl BufferInit {
+    fn load(&self) -> Result<npy::Array, Error> {
+        match self {
+            BufferInit::Zeroed(dtype, len) => Ok(npy::Array {
+                dtype: *dtype,
+                shape: vec![*len],
+                data: vec![0; len * dtype.size()],
+            }),
+            BufferInit::Array(array) => Ok(array.clone()),
+            BufferInit::File(path, name) => Ok(npy::load_any(path, name.as_deref(), Dtype::F32)?.1),
+        }
+    }
+}
+
+/// A buffer created from a [`BufferSpec`].
+struct SpecBuffer {
+    name: String,
+    dtype: Dtype,
+    shape: Vec<usize>,
+    /// Size of the contents in bytes, before padding.
+    size: usize,
+    buffer: wgpu::Buffer,
+    /// Where the buffer is copied for reading back, if it is.
+    staging: Option<wgpu::Buffer>,
+}
+
+impl SpecBuffer {
+    fn new(
+        runner: &ComputeRunner,
+        spec: &BufferSpec,
+        kind: BindingKind,
+        array: npy::Array,
+    ) -> SpecBuffer {
+        let mut usage = kind.usage();
+        if spec.read_back {
+            usage |= wgpu::BufferUsages::COPY_SRC;
+        }
+        let buffer = runner
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&spec.name),
+                contents: &array.data,
+                usage,
+            });
+        let staging = spec.read_back.then(|| {
+            runner.device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size: padded_size(array.data.len()),
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        });
+        SpecBuffer {
+            name: spec.name.clone(),
+            dtype: array.dtype,
+            shape: array.shape,
+            size: array.data.len(),
+            buffer,
+            staging,
+        }
+    }
+}
+
+/// A kernel built from a [`KernelSpec`], with its buffers.
+pub struct SpecKernel {
+    pub kernel: Kernel,
+    pub bind_groups: Vec<wgpu::BindGroup>,
+    buffers: Vec<SpecBuffer>,
+    pub workgroups: (u32, u32, u32),
+}
+
+impl SpecKernel {
+    /// The buffer with the given name.
+    pub fn buffer(&self, name: &str) -> Option<&wgpu::Buffer> {
+        self.buffers
+            .iter()
+            .find(|b| b.name == name)
+            .map(|b| &b.buffer)
+    }
+
+    /// Record the kernel, and the copies of the buffers to be read back.
+    pub fn encode(&self, runner: &ComputeRunner, encoder: &mut wgpu::CommandEncoder) {
+        runner.encode_with_bind_groups(encoder, &self.kernel, &self.bind_groups, self.workgroups);
+        for buf in &self.buffers {
+            if let Some(staging) = &buf.staging {
+                encoder.copy_buffer_to_buffer(&buf.buffer, 0, staging, 0, padded_size(buf.size));
+            }
+        }
+    }
+
+    /// Run the kernel and wait for it to complete.
+    pub async fn run(&self, runner: &ComputeRunner) -> Result<Option<Duration>, Error> {
+        runner.submit(|encoder| self.encode(runner, encoder)).await
+    }
+
+    /// The contents of the buffers to be read back, by name, as of the last
+    /// [`run`](SpecKernel::run).
+    pub async fn read_back(
+        &self,
+        runner: &ComputeRunner,
+    ) -> Result<Vec<(String, npy::Array)>, Error> {
+        let staged = self
+            .buffers
+            .iter()
+            .filter_map(|buf| Some((buf, buf.staging.as_ref()?)))
+            .collect::<Vec<_>>();
+        let mapped = staged
+            .iter()
+            .map(|(buf, staging)| (buf.name.as_str(), *staging))
+            .collect::<Vec<_>>();
+        let mut arrays = Vec::new();
+        map_read(&runner.device, &mapped, |i, data| {
+            let buf = staged[i].0;
+            arrays.push((
+                buf.name.clone(),
+                npy::Array {
+                    dtype: buf.dtype,
+                    shape: buf.shape.clone(),
+                    data: data[..buf.size].to_vec(),
+                },
+            ));
+        })
+        .await?;
+        Ok(arrays)
+    }
+}
+
+fn default_entry() -> String {
+    "main".into()
+}
+
+/// A kernel as written in a JSON manifest.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    shader: PathBuf,
+    #[serde(default = "default_entry")]
+    entry: String,
+    workgroups: Option<[u32; 3]>,
+    /// Names defined for the WGSL preprocessor.
+    #[serde(default)]
+    defines: BTreeMap<String, String>,
+    buffers: Vec<BufferManifest>,
+}
+
+/// A buffer as written in a JSON manifest.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BufferManifest {
+    name: String,
+    #[serde(default)]
+    group: u32,
+    binding: u32,
+    kind: Option<BindingKind>,
+    file: Option<PathBuf>,
+    /// The array to use from a `.npz` file.
+    array: Option<String>,
+    dtype: Option<Dtype>,
+    len: Option<usize>,
+    data: Option<Vec<f64>>,
+    #[serde(default)]
+    read_back: bool,
+}
+
+impl BufferManifest {
+    fn into_spec(self, dir: &Path) -> Result<BufferSpec, String> {
+        let error = |msg: &str| Err(format!("buffer '{}': {}", self.name, msg));
+        let init = match (&self.file, self.len, &self.data) {
+            (Some(file), None, None) => {
+                if self.dtype.is_some() {
+                    return error("the dtype of a file is given by the file");
+                }
+                BufferInit::File(dir.join(file), self.array.clone())
+            }
+            (None, Some(len), None) => BufferInit::Zeroed(self.dtype.unwrap_or(Dtype::F32), len),
+            (None, None, Some(data)) => {
+                BufferInit::Array(inline_array(self.dtype.unwrap_or(Dtype::F32), data)?)
+            }
+            _ => return error("give exactly one of file, len or data"),
+        };
+        if self.array.is_some() && self.file.is_none() {
+            return error("array names an array in a file");
+        }
+        Ok(BufferSpec {
+            name: self.name,
+            group: self.group,
+            binding: self.binding,
+            kind: self.kind,
+            init,
+            read_back: self.read_back,
+        })
+    }
+}
+
+/// An array of the numbers written inline in a manifest.
+fn inline_array(dtype: Dtype, data: &[f64]) -> Result<npy::Array, String> {
+    let mut bytes = Vec::with_capacity(data.len() * dtype.size());
+    for &x in data {
+        let integral = |lo: f64, hi: f64| {
+            if x.fract() == 0.0 && (lo..=hi).contains(&x) {
+                Ok(x)
+            } else {
+                Err(format!("{} is not a {}", x, dtype))
+            }
+        };
+        match dtype {
+            Dtype::F32 => bytes.extend_from_slice(&(x as f32).to_le_bytes()),
+            Dtype::U32 => {
+                let x = integral(0.0, u32::MAX as f64)?;
+                bytes.extend_from_slice(&(x as u32).to_le_bytes())
+            }
+            Dtype::I32 => {
+                let x = integral(i32::MIN as f64, i32::MAX as f64)?;
+                bytes.extend_from_slice(&(x as i32).to_le_bytes())
+            }
+            Dtype::U8 => bytes.push(integral(0.0, u8::MAX as f64)? as u8),
+            Dtype::F16 => return Err("inline data can't be f16; use a file".into()),
+        }
+    }
+    npy::Array::from_bytes(dtype, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest_path(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("manifests")
+            .join(name)
+    }
+
+    fn buffer_manifest(json: &str) -> Result<BufferSpec, String> {
+        serde_json::from_str::<BufferManifest>(json)
+            .unwrap()
+            .into_spec(Path::new(""))
+    }
+
+    /// The checks `build` makes once the buffers are loaded, without a device.
+    fn check(spec: &KernelSpec) -> Result<Vec<BindingKind>, Error> {
+        spec.check_unique()?;
+        let contents = spec
+            .buffers
+            .iter()
+            .map(|b| b.init.load())
+            .collect::<Result<Vec<_>, _>>()?;
+        let source = match &spec.source {
+            KernelSource::Wgsl(wgsl) => KernelSource::Wgsl(format!("{}{}", INDEX_WGSL, wgsl)),
+            other => other.clone(),
+        };
+        let module = source.parse()?;
+        let info = reflect::validate(&module)?;
+        let layout = ShaderLayout::new(&module, &info, &[&spec.entry])?;
+        spec.check_buffers(&module, &layout, &contents)
+    }
+
+    fn saxpy() -> KernelSpec {
+        let f32s = |data: &[f64]| BufferInit::Array(inline_array(Dtype::F32, data).unwrap());
+        KernelSpec::new(include_str!("../manifests/saxpy.wgsl"))
+            .buffer(BufferSpec::new("params", 0, 0, f32s(&[2.0])))
+            .buffer(BufferSpec::new("x", 0, 1, f32s(&[1.0, 2.0])))
+            .buffer(BufferSpec::new("y", 0, 2, f32s(&[3.0, 4.0])))
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let json = r#"{ "shader": "a.wgsl", "buffers": [], "entry_point": "main" }"#;
+        let err = serde_json::from_str::<Manifest>(json).err().unwrap();
+        assert!(
+            err.to_string().contains("unknown field `entry_point`"),
+            "{}",
+            err
+        );
+
+        let json = r#"{ "name": "x", "binding": 0, "len": 4, "readback": true }"#;
+        let err = serde_json::from_str::<BufferManifest>(json).err().unwrap();
+        assert!(
+            err.to_string().contains("unknown field `readback`"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn buffers_start_from_exactly_one_source() {
+        for json in [
+            r#"{ "name": "x", "binding": 0 }"#,
+            r#"{ "name": "x", "binding": 0, "len": 4, "data": [1.0] }"#,
+            r#"{ "name": "x", "binding": 0, "file": "x.npy", "len": 4 }"#,
+            r#"{ "name": "x", "binding": 0, "file": "x.npy", "data": [1.0] }"#,
+        ] {
+            assert_eq!(
+                buffer_manifest(json).err().as_deref(),
+                Some("buffer 'x': give exactly one of file, len or data"),
+                "{}",
+                json
+            );
+        }
+        let spec =
+            buffer_manifest(r#"{ "name": "x", "binding": 3, "dtype": "u32", "len": 4 }"#).unwrap();
+        assert_eq!((spec.group, spec.binding), (0, 3));
+        assert!(matches!(spec.init, BufferInit::Zeroed(Dtype::U32, 4)));
+    }
+
+    #[test]
+    fn inline_values_fit_the_dtype() {
+        let ok = |dtype, data: &[f64]| inline_array(dtype, data).unwrap().data;
+        let err = |dtype, data: &[f64]| inline_array(dtype, data).err().unwrap();
+
+        assert_eq!(
+            ok(Dtype::U32, &[0.0, 4294967295.0]),
+            [0, 0, 0, 0, 255, 255, 255, 255]
+        );
+        assert_eq!(err(Dtype::U32, &[-1.0]), "-1 is not a u32");
+        assert_eq!(err(Dtype::U32, &[4294967296.0]), "4294967296 is not a u32");
+        assert_eq!(err(Dtype::U32, &[1.5]), "1.5 is not a u32");
+
+        assert_eq!(ok(Dtype::I32, &[-2147483648.0]), [0, 0, 0, 128]);
+        assert_eq!(err(Dtype::I32, &[2147483648.0]), "2147483648 is not a i32");
+        assert_eq!(
+            err(Dtype::I32, &[-2147483649.0]),
+            "-2147483649 is not a i32"
+        );
+
+        assert_eq!(ok(Dtype::U8, &[0.0, 255.0]), [0, 255]);
+        assert_eq!(err(Dtype::U8, &[256.0]), "256 is not a u8");
+        assert_eq!(err(Dtype::U8, &[-1.0]), "-1 is not a u8");
+
+        assert_eq!(ok(Dtype::F32, &[1.5]), 1.5f32.to_le_bytes());
+        assert_eq!(
+            err(Dtype::F16, &[1.0]),
+            "inline data can't be f16; use a file"
+        );
+    }
+
+    #[test]
+    fn names_and_bindings_are_unique() {
+        let zeroed = || BufferInit::Zeroed(Dtype::F32, 4);
+        let spec = saxpy().buffer(BufferSpec::new("x", 1, 0, zeroed()));
+        match spec.check_unique() {
+            Err(Error::InvalidInput(msg)) => assert_eq!(msg, "two buffers are named 'x'"),
+            other => panic!("{:?}", other),
+        }
+        let spec = saxpy().buffer(BufferSpec::new("z", 0, 1, zeroed()));
+        match spec.check_unique() {
+            Err(Error::InvalidInput(msg)) => {
+                assert_eq!(msg, "'x' and 'z' are both bound at group 0, binding 1")
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(saxpy().check_unique().is_ok());
+    }
+
+    #[test]
+    fn kinds_are_reflected_and_checked() {
+        assert_eq!(
+            check(&saxpy()).unwrap(),
+            [
+                BindingKind::Uniform,
+                BindingKind::StorageReadOnly,
+                BindingKind::Storage
+            ]
+        );
+
+        let mut spec = saxpy();
+        spec.buffers[0] = spec.buffers[0].clone().kind(BindingKind::Storage);
+        match check(&spec) {
+            Err(Error::Bindings(msg)) => assert_eq!(
+                msg,
+                "'params': bound as storage, but the shader declares uniform"
+            ),
+            other => panic!("{:?}", other),
+        }
+
+        let mut spec = saxpy();
+        spec.buffers[2] = spec.buffers[2].clone().kind(BindingKind::Uniform);
+        match check(&spec) {
+            Err(Error::Bindings(msg)) => assert_eq!(
+                msg,
+                "'y': bound as uniform, but the shader declares storage"
+            ),
+            other => panic!("{:?}", other),
+        }
+
+        let spec = saxpy().buffer(BufferSpec::new(
+            "z",
+            0,
+            3,
+            BufferInit::Zeroed(Dtype::F32, 4),
+        ));
+        match check(&spec) {
+            Err(Error::Bindings(msg)) => {
+                assert_eq!(msg, "'z': the shader doesn't use group 0, binding 3")
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn example_manifests_load() {
+        let preprocessor = Preprocessor::new();
+
+        let saxpy = KernelSpec::load(&manifest_path("saxpy.json"), &preprocessor).unwrap();
+        assert!(matches!(saxpy.source, KernelSource::Wgsl(_)));
+        assert_eq!(saxpy.entry, "main");
+        assert_eq!(saxpy.workgroups, None);
+        let names = saxpy
+            .buffers
+            .iter()
+            .map(|b| &b.name[..])
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["params", "x", "y"]);
+        assert_eq!(saxpy.buffers[0].kind, Some(BindingKind::Uniform));
+        assert!(saxpy.buffers[2].read_back);
+        assert_eq!(
+            check(&saxpy).unwrap(),
+            [
+                BindingKind::Uniform,
+                BindingKind::StorageReadOnly,
+                BindingKind::Storage
+            ]
+        );
+
+        let add42 = KernelSpec::load(&manifest_path("add42.json"), &preprocessor).unwrap();
+        assert!(matches!(add42.source, KernelSource::Glsl(_)));
+        match &add42.buffers[0].init {
+            BufferInit::Array(array) => {
+                assert_eq!(array.dtype, Dtype::U32);
+                assert_eq!(array.data, 4u32.to_le_bytes());
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            check(&add42).unwrap(),
+            [BindingKind::Uniform, BindingKind::Storage]
+        );
+    }
+}
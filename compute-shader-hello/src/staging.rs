@@ -0,0 +1,384 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Reusing staging buffers across submissions.
+//!
+//! Creating a buffer for every upload or readback is cheap to write but
+//! churns allocations in loops that run every frame or every iteration.
+//! [`StagingPool`] keeps readback buffers once they are unmapped, by size
+//! class, and [`UploadRing`] writes uploads into a small ring of mapped
+//! buffers, one per frame in flight.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+use bytemuck::Pod;
+use serde::Serialize;
+use wgpu::util::DeviceExt;
+
+use crate::buffer::padded_size;
+use crate::{Error, GpuBuffer};
+
+/// Smallest staging buffer the pool creates.
+const MIN_SIZE_CLASS: u64 = 256;
+
+/// Free buffers kept per size class; more are dropped when released.
+const MAX_FREE_PER_CLASS: usize = 8;
+
+/// How many staging buffers were asked for, and how many of those had to be
+/// created.
+///
+/// Without reuse, every request is an allocation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct StagingStats {
+    pub requests: u64,
+    pub allocations: u64,
+}
+
+impl std::ops::Sub for StagingStats {
+    type Output = StagingStats;
+
+    fn sub(self, other: StagingStats) -> StagingStats {
+        StagingStats {
+            requests: self.requests - other.requests,
+            allocations: self.allocations - other.allocations,
+        }
+    }
+}
+
+/// A buffer from a [`StagingPool`], with the size it was created with.
+pub struct StagingBuffer {
+    pub buffer: wgpu::Buffer,
+    size: u64,
+}
+
+impl StagingBuffer {
+    /// The size of the buffer, which may be larger than was asked for.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// Unmapped staging buffers with one usage, kept for reuse.
+///
+/// Sizes are rounded up to a power of two, so a buffer can serve any request
+/// in its size class.
+pub struct StagingPool {
+    usage: wgpu::BufferUsages,
+    free: HashMap<u64, Vec<wgpu::Buffer>>,
+    stats: StagingStats,
+}
+
+impl StagingPool {
+    /// A pool of buffers with `usage`; `MAP_READ | COPY_DST` for readback.
+    pub fn new(usage: wgpu::BufferUsages) -> StagingPool {
+        StagingPool {
+            usage,
+            free: HashMap::new(),
+            stats: StagingStats::default(),
+        }
+    }
+
+    /// A buffer of at least `size` bytes, reused if one is free.
+    pub fn acquire(&mut self, device: &wgpu::Device, size: u64) -> StagingBuffer {
+        let size = size.max(MIN_SIZE_CLASS).next_power_of_two();
+        self.stats.requests += 1;
+        if let Some(buffer) = self.free.get_mut(&size).and_then(Vec::pop) {
+            return StagingBuffer { buffer, size };
+        }
+        self.stats.allocations += 1;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("staging"),
+            size,
+            usage: self.usage,
+            mapped_at_creation: false,
+        });
+        StagingBuffer { buffer, size }
+    }
+
+    /// Return a buffer to the pool. It must be unmapped, and the commands
+    /// using it must have completed.
+    pub fn release(&mut self, buf: StagingBuffer) {
+        let free = self.free.entry(buf.size).or_default();
+        if free.len() < MAX_FREE_PER_CLASS {
+            free.push(buf.buffer);
+        }
+    }
+
+    pub fn stats(&self) -> StagingStats {
+        self.stats
+    }
+}
+
+type MapFuture = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>> + Send>>;
+
+/// The state of one buffer in an [`UploadRing`].
+enum Chunk {
+    /// Mapped, with this many bytes written in the current frame.
+    Mapped(u64),
+    /// Unmapped by [`finish`](UploadRing::finish), with this many bytes
+    /// written for copies that haven't been submitted yet.
+    Unmapped(u64),
+    /// Unmapped for a submission, and waiting to be mapped again.
+    InFlight(MapFuture),
+}
+
+/// A ring of mapped buffers for uploading small amounts of data every frame,
+/// such as shader parameters.
+///
+/// Each frame writes into one buffer and records copies out of it. Between
+/// recording and submitting, [`finish`](UploadRing::finish) unmaps it, and
+/// after submitting, [`recall`](UploadRing::recall) maps it again for a later
+/// frame and moves on to the next buffer. Only when the ring comes back to a
+/// buffer whose mapping hasn't completed does uploading wait for the GPU.
+pub struct UploadRing {
+    buffers: Vec<wgpu::Buffer>,
+    chunks: Vec<Chunk>,
+    current: usize,
+    chunk_size: u64,
+    stats: StagingStats,
+}
+
+impl UploadRing {
+    /// A ring of `frames` buffers of `chunk_size` bytes each.
+    pub fn new(device: &wgpu::Device, frames: usize, chunk_size: u64) -> UploadRing {
+        let align = wgpu::COPY_BUFFER_ALIGNMENT;
+        let chunk_size = chunk_size.max(1).div_ceil(align) * align;
+        let buffers = (0..frames.max(1))
+            .map(|_| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("upload ring"),
+                    size: chunk_size,
+                    usage: wgpu::BufferUsages::MAP_WRITE | wgpu::BufferUsages::COPY_SRC,
+                    mapped_at_creation: true,
+                })
+            })
+            .collect::<Vec<_>>();
+        let stats = StagingStats {
+            requests: 0,
+            allocations: buffers.len() as u64,
+        };
+        UploadRing {
+            chunks: buffers.iter().map(|_| Chunk::Mapped(0)).collect(),
+            buffers,
+            current: 0,
+            chunk_size,
+            stats,
+        }
+    }
+
+    /// Record a copy of `data` into `dst` at `offset`.
+    ///
+    /// The length of `data` and `offset` must be multiples of 4 bytes.
+    /// Uploads that don't fit in what is left of this frame's buffer get a
+    /// buffer of their own.
+    pub fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        data: &[u8],
+        dst: &wgpu::Buffer,
+        offset: u64,
+    ) -> Result<(), Error> {
+        let size = data.len() as u64;
+        if size % wgpu::COPY_BUFFER_ALIGNMENT != 0 {
+            return Err(Error::InvalidInput(format!(
+                "uploads must be a multiple of {} bytes, not {}",
+                wgpu::COPY_BUFFER_ALIGNMENT,
+                size
+            )));
+        }
+        if offset % wgpu::COPY_BUFFER_ALIGNMENT != 0 {
+            return Err(Error::InvalidInput(format!(
+                "uploads must be to a multiple of {} bytes, not offset {}",
+                wgpu::COPY_BUFFER_ALIGNMENT,
+                offset
+            )));
+        }
+        self.stats.requests += 1;
+        let used = self.map_current(device)?;
+        if used + size > self.chunk_size {
+            self.stats.allocations += 1;
+            let buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("upload"),
+                contents: data,
+                usage: wgpu::BufferUsages::COPY_SRC,
+            });
+            encoder.copy_buffer_to_buffer(&buf, 0, dst, offset, size);
+            return Ok(());
+        }
+        let buffer = &self.buffers[self.current];
+        buffer
+            .slice(used..used + size)
+            .get_mapped_range_mut()
+            .copy_from_slice(data);
+        encoder.copy_buffer_to_buffer(buffer, used, dst, offset, size);
+        self.chunks[self.current] = Chunk::Mapped(used + size);
+        Ok(())
+    }
+
+    /// Record a copy of `data` over the contents of `dst`, which must have
+    /// the same length.
+    pub fn upload_to<T: Pod>(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        dst: &GpuBuffer<T>,
+        data: &[T],
+    ) -> Result<(), Error> {
+        if data.len() != dst.len() {
+            return Err(Error::InvalidInput(format!(
+                "buffer has {} elements, but {} were given",
+                dst.len(),
+                data.len()
+            )));
+        }
+        let mut bytes = bytemuck::cast_slice::<T, u8>(data).to_vec();
+        // The buffer is padded, so the copy can be too.
+        bytes.resize(padded_size(bytes.len()) as usize, 0);
+        self.upload(device, encoder, &bytes, dst.buffer(), 0)
+    }
+
+    /// Unmap this frame's buffer, before submitting the commands copying from
+    /// it. Uploading again before [`recall`](UploadRing::recall) maps it
+    /// again, and it must be finished again before submitting.
+    pub fn finish(&mut self) {
+        if let Chunk::Mapped(used) = self.chunks[self.current] {
+            if used > 0 {
+                self.buffers[self.current].unmap();
+                self.chunks[self.current] = Chunk::Unmapped(used);
+            }
+        }
+    }
+
+    /// Start mapping this frame's buffer again, once the commands copying
+    /// from it are submitted, and move on to the next one.
+    pub fn recall(&mut self) {
+        if let Chunk::Unmapped(_) = self.chunks[self.current] {
+            let slice = self.buffers[self.current].slice(..);
+            self.chunks[self.current] =
+                Chunk::InFlight(Box::pin(slice.map_async(wgpu::MapMode::Write)));
+        }
+        self.current = (self.current + 1) % self.buffers.len();
+    }
+
+    pub fn stats(&self) -> StagingStats {
+        self.stats
+    }
+
+    /// Make sure the current buffer is mapped, and return how much of it is
+    /// used.
+    fn map_current(&mut self, device: &wgpu::Device) -> Result<u64, Error> {
+        let current = self.current;
+        let map_error = |e| Error::Map(format!("upload ring buffer {}", current), e);
+        let future = match &mut self.chunks[current] {
+            Chunk::Mapped(used) => return Ok(*used),
+            Chunk::InFlight(future) => future,
+            // Uploading again after `finish` but before `recall`: map the
+            // buffer again, and write after what the recorded copies read.
+            Chunk::Unmapped(used) => {
+                let used = *used;
+                let future = self.buffers[current]
+                    .slice(..)
+                    .map_async(wgpu::MapMode::Write);
+                device.poll(wgpu::Maintain::Wait);
+                pollster::block_on(future).map_err(map_error)?;
+                self.chunks[current] = Chunk::Mapped(used);
+                return Ok(used);
+            }
+        };
+        device.poll(wgpu::Maintain::Poll);
+        let mut cx = Context::from_waker(Waker::noop());
+        let result = match future.as_mut().poll(&mut cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => {
+                device.poll(wgpu::Maintain::Wait);
+                pollster::block_on(future)
+            }
+        };
+        result.map_err(map_error)?;
+        self.chunks[current] = Chunk::Mapped(0);
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ComputeRunner;
+
+    #[test]
+    fn upload_after_finish_maps_again() {
+        let Some(runner) = ComputeRunner::for_tests() else {
+            return;
+        };
+        let device = &runner.device;
+        let dst = runner.upload(&[0u32; 4]);
+        let mut ring = UploadRing::new(device, 2, 64);
+        let mut encoder = device.create_command_encoder(&Default::default());
+        ring.upload(
+            device,
+            &mut encoder,
+            bytemuck::bytes_of(&[1u32, 2]),
+            &dst,
+            0,
+        )
+        .unwrap();
+        ring.finish();
+        ring.upload(
+            device,
+            &mut encoder,
+            bytemuck::bytes_of(&[3u32, 4]),
+            &dst,
+            8,
+        )
+        .unwrap();
+        ring.finish();
+        runner.queue.submit(Some(encoder.finish()));
+        ring.recall();
+
+        let result: Vec<u32> = pollster::block_on(runner.read_back(&dst, 4)).unwrap();
+        assert_eq!(result, [1, 2, 3, 4]);
+        assert_eq!(ring.stats().allocations, 2);
+    }
+
+    #[test]
+    fn unaligned_uploads_are_rejected() {
+        let Some(runner) = ComputeRunner::for_tests() else {
+            return;
+        };
+        let device = &runner.device;
+        let dst = runner.upload(&[0u32; 4]);
+        let mut ring = UploadRing::new(device, 2, 64);
+        let mut encoder = device.create_command_encoder(&Default::default());
+        let mut upload =
+            |data: &[u8], offset| match ring.upload(device, &mut encoder, data, &dst, offset) {
+                Err(Error::InvalidInput(msg)) => msg,
+                other => panic!("{:?}", other),
+            };
+        assert_eq!(
+            upload(&[1, 2, 3], 0),
+            "uploads must be a multiple of 4 bytes, not 3"
+        );
+        assert_eq!(
+            upload(&[1, 2, 3, 4], 6),
+            "uploads must be to a multiple of 4 bytes, not offset 6"
+        );
+        assert_eq!(ring.stats().requests, 0);
+    }
+}
@@ -0,0 +1,467 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Workgroup size autotuning.
+//!
+//! A tunable kernel leaves its workgroup size as a placeholder, writing
+//! `workgroup_size(WORKGROUP_SIZE)` in its entry point attribute. The
+//! constants `WG_SIZE_X`, `WG_SIZE_Y`, `WG_SIZE_Z` and `WG_SIZE` (the total
+//! number of invocations) are defined for it to use. [`tune`] benchmarks the
+//! kernel with each candidate size, and the fastest is recorded in a
+//! [`TuneCache`] for the current adapter.
+//!
+//! The cache is loaded when a [`ComputeRunner`] is created, and
+//! [`ComputeRunner::create_tuned_kernel`] uses the recorded size when there
+//! is one.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bench::{BenchOptions, Stats, Timer};
+use crate::runner::grid;
+use crate::{ComputeRunner, Error, Kernel};
+
+/// Environment variable overriding the path of the tuning cache.
+pub const CACHE_ENV: &str = "COMPUTE_SHADER_TUNE_CACHE";
+
+/// Path of the tuning cache when [`CACHE_ENV`] isn't set.
+pub const DEFAULT_CACHE_PATH: &str = "workgroup-sizes.json";
+
+/// The text replaced by the workgroup size in a tunable kernel.
+pub const PLACEHOLDER: &str = "workgroup_size(WORKGROUP_SIZE)";
+
+/// Workgroup size for tunable kernels that haven't been tuned.
+pub const DEFAULT_WORKGROUP_SIZE: WorkgroupSize = WorkgroupSize(64, 1, 1);
+
+/// A workgroup size in up to three dimensions.
+///
+/// Parsed from and displayed as `x`, `xxy` or `xxyxz`, for example `256` or
+/// `16x16`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkgroupSize(pub u32, pub u32, pub u32);
+
+impl WorkgroupSize {
+    /// A one-dimensional workgroup size.
+    pub fn linear(x: u32) -> WorkgroupSize {
+        WorkgroupSize(x, 1, 1)
+    }
+
+    /// The number of invocations in a workgroup.
+    pub fn invocations(&self) -> u32 {
+        self.0 * self.1 * self.2
+    }
+
+    /// Candidates for kernels over one-dimensional data.
+    pub fn candidates_1d() -> Vec<WorkgroupSize> {
+        [32, 64, 128, 256].map(WorkgroupSize::linear).to_vec()
+    }
+
+    /// Candidates for kernels over two-dimensional data.
+    pub fn candidates_2d() -> Vec<WorkgroupSize> {
+        [(8, 8), (16, 8), (8, 16), (16, 16), (32, 8), (8, 32)]
+            .map(|(x, y)| WorkgroupSize(x, y, 1))
+            .to_vec()
+    }
+}
+
+impl fmt::Display for WorkgroupSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WorkgroupSize(x, 1, 1) => write!(f, "{}", x),
+            WorkgroupSize(x, y, 1) => write!(f, "{}x{}", x, y),
+            WorkgroupSize(x, y, z) => write!(f, "{}x{}x{}", x, y, z),
+        }
+    }
+}
+
+impl FromStr for WorkgroupSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let dims = s
+            .split('x')
+            .map(|d| d.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid workgroup size '{}': {}", s, e))?;
+        let size = match dims[..] {
+            [x] => WorkgroupSize(x, 1, 1),
+            [x, y] => WorkgroupSize(x, y, 1),
+            [x, y, z] => WorkgroupSize(x, y, z),
+            _ => return Err(format!("expected 1 to 3 dimensions, got '{}'", s)),
+        };
+        if size.invocations() == 0 {
+            return Err(format!("workgroup size '{}' is empty", s));
+        }
+        Ok(size)
+    }
+}
+
+/// Fill in the workgroup size of a tunable kernel.
+pub fn instantiate(source: &str, size: WorkgroupSize) -> String {
+    // naga only accepts literals in the workgroup_size attribute, so the
+    // constants can't be used there.
+    let WorkgroupSize(x, y, z) = size;
+    format!(
+        "let WG_SIZE_X: u32 = {}u;\nlet WG_SIZE_Y: u32 = {}u;\nlet WG_SIZE_Z: u32 = {}u;\nlet WG_SIZE: u32 = {}u;\n{}",
+        x,
+        y,
+        z,
+        size.invocations(),
+        source.replace(PLACEHOLDER, &format!("workgroup_size({}, {}, {})", x, y, z))
+    )
+}
+
+/// Whether `source` has a placeholder for the workgroup size.
+pub fn is_tunable(source: &str) -> bool {
+    source.contains(PLACEHOLDER)
+}
+
+/// The timing of one candidate workgroup size.
+#[derive(Clone, Copy, Debug)]
+pub struct Candidate {
+    pub workgroup_size: WorkgroupSize,
+    pub timer: Timer,
+    pub stats_ms: Stats,
+}
+
+/// Benchmark a tunable kernel with each of the candidate workgroup sizes.
+///
+/// The buffers are bound in group 0, in order, and must match the ones the
+/// kernel declares. The kernel is dispatched with enough workgroups to cover `invocations`
+/// invocations in each dimension. One-dimensional dispatches are spread over
+/// a grid as described in [`grid`], so such kernels should use the index
+/// helpers in [`INDEX_WGSL`](crate::INDEX_WGSL).
+///
+/// Returns the candidates in the order given; see [`fastest`].
+pub async fn tune(
+    runner: &ComputeRunner,
+    source: &str,
+    entry: &str,
+    bindings: &[&wgpu::Buffer],
+    invocations: (u32, u32, u32),
+    candidates: &[WorkgroupSize],
+    options: &BenchOptions,
+) -> Result<Vec<Candidate>, Error> {
+    let mut results = Vec::with_capacity(candidates.len());
+    let indexed = bindings
+        .iter()
+        .enumerate()
+        .map(|(i, buf)| (0, i as u32, *buf))
+        .collect::<Vec<_>>();
+    for &size in candidates {
+        let kernel = runner.create_reflected_kernel(&instantiate(source, size), entry)?;
+        kernel.check_buffers(&indexed, &[])?;
+        let workgroups = workgroups_for(invocations, size);
+        let samples = runner
+            .bench_with(options, |encoder| {
+                runner.encode_kernel(encoder, &kernel, bindings, workgroups)
+            })
+            .await?;
+        results.push(Candidate {
+            workgroup_size: size,
+            timer: samples.timer,
+            stats_ms: Stats::from_durations(&samples.durations),
+        });
+    }
+    Ok(results)
+}
+
+/// The candidate with the lowest median time.
+pub fn fastest(candidates: &[Candidate]) -> Option<&Candidate> {
+    candidates
+        .iter()
+        .min_by(|a, b| a.stats_ms.median.total_cmp(&b.stats_ms.median))
+}
+
+/// The number of workgroups of `size` needed to cover `invocations`.
+pub fn workgroups_for(invocations: (u32, u32, u32), size: WorkgroupSize) -> (u32, u32, u32) {
+    let x = invocations.0.div_ceil(size.0);
+    let y = invocations.1.div_ceil(size.1);
+    let z = invocations.2.div_ceil(size.2);
+    if y == 1 && z == 1 {
+        grid(x)
+    } else {
+        (x, y, z)
+    }
+}
+
+/// The tuned size of one kernel.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct TunedSize {
+    pub workgroup_size: WorkgroupSize,
+    /// Median time with this size when it was tuned.
+    pub median_ms: f64,
+    pub timer: Timer,
+}
+
+/// The best known workgroup sizes, per adapter and kernel.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TuneCache {
+    adapters: BTreeMap<String, BTreeMap<String, TunedSize>>,
+}
+
+impl TuneCache {
+    /// The path given by [`CACHE_ENV`], or [`DEFAULT_CACHE_PATH`].
+    pub fn default_path() -> PathBuf {
+        std::env::var_os(CACHE_ENV)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| DEFAULT_CACHE_PATH.into())
+    }
+
+    /// Load a cache file, or an empty cache if the file doesn't exist.
+    pub fn load(path: &Path) -> Result<TuneCache, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(json) => {
+                serde_json::from_str(&json).map_err(|e| Error::Parse(path.into(), e.to_string()))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(TuneCache::default()),
+            Err(e) => Err(Error::Io(path.into(), e)),
+        }
+    }
+
+    /// Load a cache file as [`load`](TuneCache::load) does, but warn and
+    /// fall back to an empty cache if it can't be read or parsed.
+    ///
+    /// Kernels then use their default sizes, which is better than failing
+    /// for work that doesn't tune.
+    pub fn load_or_empty(path: &Path) -> TuneCache {
+        TuneCache::load(path).unwrap_or_else(|e| {
+            eprintln!("warning: ignoring the tuning cache: {}", e);
+            TuneCache::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let json = serde_json::to_string_pretty(self).unwrap();
+        std::fs::write(path, json).map_err(|e| Error::Io(path.into(), e))
+    }
+
+    /// The tuned size of a kernel on an adapter, if there is one.
+    pub fn get(
+        &self,
+        adapter: &wgpu::AdapterInfo,
+        source: &str,
+        entry: &str,
+    ) -> Option<&TunedSize> {
+        self.adapters
+            .get(&adapter_key(adapter))?
+            .get(&kernel_key(source, entry))
+    }
+
+    /// Record the tuned size of a kernel on an adapter.
+    pub fn insert(
+        &mut self,
+        adapter: &wgpu::AdapterInfo,
+        source: &str,
+        entry: &str,
+        tuned: TunedSize,
+    ) {
+        self.adapters
+            .entry(adapter_key(adapter))
+            .or_default()
+            .insert(kernel_key(source, entry), tuned);
+    }
+}
+
+/// Identifies an adapter in the cache. wgpu doesn't report the driver
+/// version, so a driver update won't invalidate the entries.
+fn adapter_key(info: &wgpu::AdapterInfo) -> String {
+    format!(
+        "{} ({:?}, {:04x}:{:04x})",
+        info.name, info.backend, info.vendor, info.device
+    )
+}
+
+/// Identifies a kernel in the cache, by entry point and a hash of the
+/// source, so that editing the kernel invalidates its entry.
+fn kernel_key(source: &str, entry: &str) -> String {
+    // FNV-1a, which unlike the standard library's hasher is stable across
+    // Rust versions.
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in source.as_bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{}:{:016x}", entry, hash)
+}
+
+impl ComputeRunner {
+    /// Build a tunable kernel, using the workgroup size from the tuning cache
+    /// if the kernel has been tuned on this adapter, and `default` otherwise.
+    ///
+    /// The bind group layouts are derived from the shader. Returns the kernel
+    /// along with the workgroup size it was built with.
+    pub fn create_tuned_kernel(
+        &self,
+        source: &str,
+        entry: &str,
+        default: WorkgroupSize,
+    ) -> Result<(Kernel, WorkgroupSize), Error> {
+        let size = self.tuned_size(source, entry, default);
+        let kernel = self.create_reflected_kernel(&instantiate(source, size), entry)?;
+        Ok((kernel, size))
+    }
+
+    /// The workgroup size for a tunable kernel: the tuned one if the kernel
+    /// has been tuned on this adapter, and `default` otherwise.
+    pub fn tuned_size(&self, source: &str, entry: &str, default: WorkgroupSize) -> WorkgroupSize {
+        self.tune_cache
+            .get(&self.adapter_info, source, entry)
+            .map_or(default, |tuned| tuned.workgroup_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adapter(name: &str) -> wgpu::AdapterInfo {
+        wgpu::AdapterInfo {
+            name: name.into(),
+            vendor: 0x10de,
+            device: 0x2204,
+            device_type: wgpu::DeviceType::DiscreteGpu,
+            backend: wgpu::Backend::Vulkan,
+        }
+    }
+
+    fn tuned(x: u32) -> TunedSize {
+        TunedSize {
+            workgroup_size: WorkgroupSize::linear(x),
+            median_ms: 1.5,
+            timer: Timer::Gpu,
+        }
+    }
+
+    #[test]
+    fn workgroup_size_parses_and_displays() {
+        for (s, size) in [
+            ("256", WorkgroupSize(256, 1, 1)),
+            ("16x16", WorkgroupSize(16, 16, 1)),
+            ("4x4x4", WorkgroupSize(4, 4, 4)),
+        ] {
+            assert_eq!(s.parse::<WorkgroupSize>(), Ok(size));
+            assert_eq!(size.to_string(), s);
+        }
+        assert_eq!(" 8 x 2 ".parse(), Ok(WorkgroupSize(8, 2, 1)));
+        // Trailing dimensions of 1 are left out when displayed.
+        assert_eq!(WorkgroupSize(8, 1, 1).to_string(), "8");
+        assert_eq!(WorkgroupSize(1, 1, 4).to_string(), "1x1x4");
+        for bad in ["", "x", "16x", "8x8x8x8", "-1", "0", "16x0"] {
+            assert!(bad.parse::<WorkgroupSize>().is_err(), "'{}' parsed", bad);
+        }
+    }
+
+    #[test]
+    fn instantiate_fills_in_the_size() {
+        let source = "[[stage(compute), workgroup_size(WORKGROUP_SIZE)]]\nfn main() {}\n";
+        assert!(is_tunable(source));
+        let instantiated = instantiate(source, WorkgroupSize(16, 8, 1));
+        assert!(!is_tunable(&instantiated));
+        assert!(instantiated.contains("workgroup_size(16, 8, 1)"));
+        for constant in [
+            "let WG_SIZE_X: u32 = 16u;",
+            "let WG_SIZE_Y: u32 = 8u;",
+            "let WG_SIZE_Z: u32 = 1u;",
+            "let WG_SIZE: u32 = 128u;",
+        ] {
+            assert!(instantiated.contains(constant), "missing {}", constant);
+        }
+        crate::reflect::validate(&crate::reflect::parse(&instantiated).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn workgroups_cover_invocations() {
+        assert_eq!(
+            workgroups_for((1000, 1, 1), WorkgroupSize::linear(64)),
+            (16, 1, 1)
+        );
+        assert_eq!(
+            workgroups_for((100, 30, 1), WorkgroupSize(16, 16, 1)),
+            (7, 2, 1)
+        );
+        // Large one-dimensional dispatches spill over into y.
+        assert_eq!(
+            workgroups_for((70000 * 64, 1, 1), WorkgroupSize::linear(64)),
+            grid(70000)
+        );
+    }
+
+    #[test]
+    fn fastest_has_lowest_median() {
+        let candidate = |x, median| Candidate {
+            workgroup_size: WorkgroupSize::linear(x),
+            timer: Timer::Gpu,
+            stats_ms: Stats {
+                median,
+                ..Stats::from_durations(&[std::time::Duration::from_millis(1)])
+            },
+        };
+        let candidates = [candidate(32, 2.0), candidate(64, 0.5), candidate(128, 1.0)];
+        assert_eq!(
+            fastest(&candidates).unwrap().workgroup_size,
+            WorkgroupSize::linear(64)
+        );
+        assert!(fastest(&[]).is_none());
+    }
+
+    #[test]
+    fn cache_round_trips() {
+        let source = "fn main() {}";
+        let mut cache = TuneCache::default();
+        cache.insert(&adapter("gpu"), source, "main", tuned(128));
+        cache.insert(&adapter("gpu"), source, "other", tuned(32));
+
+        let path =
+            std::env::temp_dir().join(format!("tune-round-trip-{}.json", std::process::id()));
+        cache.save(&path).unwrap();
+        let loaded = TuneCache::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let get = |adapter_name, source, entry| {
+            loaded
+                .get(&adapter(adapter_name), source, entry)
+                .map(|t| t.workgroup_size)
+        };
+        assert_eq!(get("gpu", source, "main"), Some(WorkgroupSize::linear(128)));
+        assert_eq!(get("gpu", source, "other"), Some(WorkgroupSize::linear(32)));
+        // Another adapter, entry point or source has no entry.
+        assert_eq!(get("other gpu", source, "main"), None);
+        assert_eq!(get("gpu", source, "missing"), None);
+        assert_eq!(get("gpu", "fn main() { }", "main"), None);
+    }
+
+    #[test]
+    fn kernel_key_is_stable() {
+        // Saved caches depend on the hash staying the same.
+        assert_eq!(kernel_key("", "main"), "main:cbf29ce484222325");
+        assert_ne!(kernel_key("a", "main"), kernel_key("b", "main"));
+    }
+
+    #[test]
+    fn unreadable_cache_loads_empty() {
+        let path = std::env::temp_dir().join(format!("tune-cache-{}.json", std::process::id()));
+        std::fs::write(&path, "{ not json").unwrap();
+        assert!(matches!(TuneCache::load(&path), Err(Error::Parse(..))));
+        assert!(TuneCache::load_or_empty(&path).adapters.is_empty());
+        std::fs::remove_file(&path).unwrap();
+        assert!(TuneCache::load_or_empty(&path).adapters.is_empty());
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+// Buffer A of the Game of Life example: one cell per pixel, alive in the red
+// channel, with a fading trail of where cells have been in the green channel.
+// Dragging with the mouse brings cells to life.
+
+[[block]]
+struct Params {
+    width: u32;
+    height: u32;
+    iTime: f32;
+    iTimeDelta: f32;
+    iFrame: i32;
+    iFrameRate: f32;
+    iMouse: vec4<f32>;
+    iDate: vec4<f32>;
+    iResolution: vec3<f32>;
+};
+
+[[group(0), binding(0)]] var<uniform> params: Params;
+[[group(0), binding(1)]] var output: texture_storage_2d<rgba16float,write>;
+[[group(0), binding(3)]] var bufferA: texture_2d<f32>;
+
+// The cell at `p` in the previous frame, wrapping around the edges.
+fn cell(p: vec2<i32>) -> f32 {
+    let w = i32(params.width);
+    let h = i32(params.height);
+    return textureLoad(bufferA, vec2<i32>((p.x + w) % w, (p.y + h) % h), 0).r;
+}
+
+[[stage(compute), workgroup_size(16, 16)]]
+fn main([[builtin(global_invocation_id)]] global_ix: vec3<u32>) {
+    let p = vec2<i32>(global_ix.xy);
+    let prev = textureLoad(bufferA, p, 0);
+    var alive: f32 = 0.0;
+    if (params.iFrame == 0) {
+        // Start from random cells.
+        let h = fract(sin(dot(vec2<f32>(p), vec2<f32>(12.9898, 78.233))) * 43758.5453);
+        if (h > 0.8) {
+            alive = 1.0;
+        }
+    } else {
+        let n = cell(p + vec2<i32>(-1, -1)) + cell(p + vec2<i32>(0, -1)) + cell(p + vec2<i32>(1, -1))
+            + cell(p + vec2<i32>(-1, 0)) + cell(p + vec2<i32>(1, 0))
+            + cell(p + vec2<i32>(-1, 1)) + cell(p + vec2<i32>(0, 1)) + cell(p + vec2<i32>(1, 1));
+        if (n == 3.0 || (n == 2.0 && prev.r > 0.5)) {
+            alive = 1.0;
+        }
+    }
+    if (params.iMouse.z > 0.0 && distance(vec2<f32>(p), params.iMouse.xy) < 8.0) {
+        alive = 1.0;
+    }
+    let trail = max(alive, prev.g * 0.97);
+    textureStore(output, p, vec4<f32>(alive, trail, 0.0, 1.0));
+}
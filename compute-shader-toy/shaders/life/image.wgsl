@@ -0,0 +1,46 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+// The image pass of the Game of Life example, drawing the cells of Buffer A.
+//
+//     cargo run -- --buffer shaders/life/buffer_a.wgsl --shader shaders/life/image.wgsl
+
+[[block]]
+struct Params {
+    width: u32;
+    height: u32;
+    iTime: f32;
+    iTimeDelta: f32;
+    iFrame: i32;
+    iFrameRate: f32;
+    iMouse: vec4<f32>;
+    iDate: vec4<f32>;
+    iResolution: vec3<f32>;
+};
+
+[[group(0), binding(0)]] var<uniform> params: Params;
+[[group(0), binding(1)]] var outputTex: texture_storage_2d<rgba8unorm,write>;
+[[group(0), binding(2)]] var samp: sampler;
+[[group(0), binding(3)]] var bufferA: texture_2d<f32>;
+
+[[stage(compute), workgroup_size(16, 16)]]
+fn main([[builtin(global_invocation_id)]] global_ix: vec3<u32>) {
+    let uv = (vec2<f32>(global_ix.xy) + vec2<f32>(0.5, 0.5)) / params.iResolution.xy;
+    let c = textureSampleLevel(bufferA, samp, uv, 0.0);
+    let background = vec3<f32>(0.05, 0.05, 0.1) + vec3<f32>(0.0, 0.25, 0.4) * c.g;
+    let color = mix(background, vec3<f32>(1.0, 0.85, 0.3), c.r);
+    textureStore(outputTex, vec2<i32>(global_ix.xy), vec4<f32>(color, 1.0));
+}
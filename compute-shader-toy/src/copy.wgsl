@@ -38,8 +38,13 @@ fn vs_main(
 var r_color: texture_2d<f32>;
 [[group(0), binding(1)]]
 var r_sampler: sampler;
+// Text drawn over the image, such as shader errors; transparent elsewhere.
+[[group(0), binding(2)]]
+var r_overlay: texture_2d<f32>;
 
 [[stage(fragment)]]
 fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-    return textureSample(r_color, r_sampler, in.tex_coord);
+    let color = textureSample(r_color, r_sampler, in.tex_coord);
+    let overlay = textureSample(r_overlay, r_sampler, in.tex_coord);
+    return vec4<f32>(mix(color.rgb, overlay.rgb, overlay.a), color.a);
 }
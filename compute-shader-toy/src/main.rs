@@ -15,8 +15,28 @@
 // Also licensed under MIT license, at your choice.
 
 //! A simple compute shader example that draws into a window, based on wgpu.
+//!
+//! With `--headless out.ppm`, a single frame is rendered offscreen and saved
+//! instead, which doesn't need a window system or (with `--fallback`) a GPU.
+//!
+//! The paint shader is read from `src/paint.wgsl` at startup, or from the
+//! file given with `--shader`, and preprocessed, so it can `#include`
+//! helpers and be configured with `--define`. In a window, it is reloaded
+//! whenever it or a file it includes changes; if the new version fails to
+//! compile, the last good one keeps running and the error is shown on top.
+//!
+//! Up to four buffer passes can be added with `--buffer`, each with its own
+//! shader, to keep state from frame to frame as Shadertoy's Buffer A to D
+//! do; see [`passes`].
 
-use wgpu::util::DeviceExt;
+mod overlay;
+mod passes;
+mod watch;
+
+use std::path::{Path, PathBuf};
+
+use bytemuck::{Pod, Zeroable};
+use clap::Parser;
 use wgpu::{BufferUsages, Extent3d};
 
 use winit::{
@@ -25,192 +45,602 @@ use winit::{
     window::Window,
 };
 
-async fn run(event_loop: EventLoop<()>, window: Window) {
-    let instance = wgpu::Instance::new(wgpu::Backends::PRIMARY);
-    let surface = unsafe { instance.create_surface(&window) };
-    let adapter = instance
-        .request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: Default::default(),
-            force_fallback_adapter: false,
-            compatible_surface: Some(&surface),
+use compute_shader_hello::preprocess::{Preprocessed, Preprocessor};
+use compute_shader_hello::profile::{Profiler, Trace};
+use compute_shader_hello::reflect::{self, ShaderLayout};
+use compute_shader_hello::staging::UploadRing;
+use compute_shader_hello::tune::WorkgroupSize;
+use compute_shader_hello::{AdapterOptions, Error, GpuBuffer};
+use toy_inputs::{Frame, Inputs};
+
+use overlay::Overlay;
+use passes::{Images, Pass};
+use watch::Reload;
+
+#[derive(Parser)]
+#[command(about = "Run a compute shader and draw its output.")]
+struct Cli {
+    /// Use a software adapter instead of a GPU.
+    #[arg(long)]
+    fallback: bool,
+    /// Render a single frame to this PPM file instead of opening a window.
+    #[arg(long)]
+    headless: Option<PathBuf>,
+    /// Image size for headless rendering, as `WIDTHxHEIGHT`.
+    #[arg(long, default_value = "512x512", value_parser = parse_size)]
+    size: (u32, u32),
+    /// Shader time for headless rendering, in seconds.
+    #[arg(long, default_value_t = 0.5)]
+    time: f32,
+    /// Frames to render headless, 1/60 s apart, before saving the last.
+    #[arg(long, default_value_t = 1)]
+    frames: u32,
+    /// The compute shader to paint with.
+    #[arg(long, default_value = concat!(env!("CARGO_MANIFEST_DIR"), "/src/paint.wgsl"))]
+    shader: PathBuf,
+    /// The compute shader of a buffer pass, run before the image pass. Given
+    /// up to four times, for Buffer A to D.
+    #[arg(long = "buffer", value_name = "SHADER")]
+    buffers: Vec<PathBuf>,
+    /// Define a name for the WGSL preprocessor, as `NAME` or `NAME=VALUE`.
+    #[arg(long = "define", short = 'D', value_name = "NAME[=VALUE]")]
+    defines: Vec<String>,
+    /// Time the paint and copy passes of every frame with timestamp queries,
+    /// and write the timings here as a Chrome trace on exit.
+    #[arg(long)]
+    trace: Option<PathBuf>,
+}
+
+impl Cli {
+    fn shader_path(&self, pass: Pass) -> &Path {
+        match pass {
+            Pass::Buffer(i) => &self.buffers[i],
+            Pass::Image => &self.shader,
+        }
+    }
+}
+
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let (w, h) = s
+        .split_once('x')
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, got '{}'", s))?;
+    let parse = |d: &str| {
+        d.parse::<u32>()
+            .map_err(|e| format!("invalid size '{}': {}", s, e))
+    };
+    Ok((parse(w)?, parse(h)?))
+}
+
+/// The shader's `Params` uniform, checked against the shader's declaration
+/// when the pipeline is built.
+///
+/// Besides the image size, it has the Shadertoy inputs. The padding is where
+/// WGSL's alignment rules for vectors put it.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct Params {
+    width: u32,
+    height: u32,
+    i_time: f32,
+    i_time_delta: f32,
+    i_frame: i32,
+    i_frame_rate: f32,
+    _pad0: [u32; 2],
+    i_mouse: [f32; 4],
+    i_date: [f32; 4],
+    i_resolution: [f32; 3],
+    _pad1: u32,
+}
+
+impl Params {
+    fn new(width: u32, height: u32, frame: &Frame) -> Params {
+        Params {
+            width,
+            height,
+            i_time: frame.time,
+            i_time_delta: frame.time_delta,
+            i_frame: frame.frame,
+            i_frame_rate: frame.frame_rate,
+            i_mouse: frame.mouse,
+            i_date: frame.date,
+            i_resolution: [width as f32, height as f32, 1.0],
+            ..Zeroable::zeroed()
+        }
+    }
+}
+
+/// Frames whose parameter uploads can be in flight at once.
+const FRAMES_IN_FLIGHT: usize = 3;
+
+/// The pipeline of one pass, and the layout it was built with.
+struct PaintPipeline {
+    pipeline: wgpu::ComputePipeline,
+    layout: ShaderLayout,
+    bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+    workgroup_size: WorkgroupSize,
+}
+
+impl PaintPipeline {
+    /// Build the pipeline for `pass`, checking the shader binds what passes
+    /// are given as expected.
+    ///
+    /// The shader is validated with naga first, so that errors are returned
+    /// rather than raised by wgpu, which panics on them.
+    fn new(
+        device: &wgpu::Device,
+        source: &Preprocessed,
+        pass: Pass,
+        n_buffers: usize,
+        params: &GpuBuffer<Params>,
+    ) -> Result<PaintPipeline, Error> {
+        let module = source.parse()?;
+        let layout = ShaderLayout::new(&module, &reflect::validate(&module)?, &["main"])?;
+        passes::check_bindings(&layout, pass, n_buffers)?;
+        if layout.get(0, passes::PARAMS_BINDING).is_some() {
+            params.check(&layout, 0, passes::PARAMS_BINDING)?;
+        }
+        let workgroup_size = reflect::workgroup_size(&module, "main")
+            .ok_or_else(|| Error::Shader("no entry point named 'main'".into()))?;
+        let cs_module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(source.text.as_str().into()),
+        });
+        let bind_group_layouts = layout.create_bind_group_layouts(device);
+        let compute_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(),
+                push_constant_ranges: &[],
+            });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&compute_pipeline_layout),
+            module: &cs_module,
+            entry_point: "main",
+        });
+        Ok(PaintPipeline {
+            pipeline,
+            layout,
+            bind_group_layouts,
+            workgroup_size,
         })
-        .await
-        .expect("error finding adapter");
+    }
+
+    /// Bind the resources passes are given, leaving out those the shader
+    /// doesn't use.
+    fn bind(
+        &self,
+        device: &wgpu::Device,
+        params: &GpuBuffer<Params>,
+        sampler: &wgpu::Sampler,
+        output: &wgpu::TextureView,
+        buffers: &[&wgpu::TextureView],
+    ) -> Result<wgpu::BindGroup, Error> {
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: passes::PARAMS_BINDING,
+                resource: params.as_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: passes::OUTPUT_BINDING,
+                resource: wgpu::BindingResource::TextureView(output),
+            },
+            wgpu::BindGroupEntry {
+                binding: passes::SAMPLER_BINDING,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ];
+        entries.extend(
+            buffers
+                .iter()
+                .zip(passes::BUFFERS_BINDING..)
+                .map(|(view, binding)| wgpu::BindGroupEntry {
+                    binding,
+                    resource: wgpu::BindingResource::TextureView(view),
+                }),
+        );
+        entries.retain(|entry| self.layout.get(0, entry.binding).is_some());
+        self.layout
+            .create_bind_group(device, &self.bind_group_layouts, 0, &entries)
+    }
+}
 
+/// The shaders of every pass, as loaded at startup.
+struct Shaders {
+    buffers: Vec<Preprocessed>,
+    image: Preprocessed,
+}
+
+impl Shaders {
+    fn load(cli: &Cli) -> Result<Shaders, Error> {
+        if cli.buffers.len() > passes::MAX_BUFFERS {
+            return Err(Error::InvalidInput(format!(
+                "{} buffer passes given, but there can be at most {}",
+                cli.buffers.len(),
+                passes::MAX_BUFFERS
+            )));
+        }
+        let preprocessor = Preprocessor::new().defines(&cli.defines);
+        Ok(Shaders {
+            buffers: cli
+                .buffers
+                .iter()
+                .map(|path| preprocessor.process_file(path))
+                .collect::<Result<_, _>>()?,
+            image: preprocessor.process_file(&cli.shader)?,
+        })
+    }
+
+    fn get(&self, pass: Pass) -> &Preprocessed {
+        match pass {
+            Pass::Buffer(i) => &self.buffers[i],
+            Pass::Image => &self.image,
+        }
+    }
+}
+
+/// The compute half of the toy: the pipelines of the passes and their
+/// parameters.
+struct Painter {
+    buffers: Vec<PaintPipeline>,
+    image: PaintPipeline,
+    params: GpuBuffer<Params>,
+    sampler: wgpu::Sampler,
+    uploads: UploadRing,
+}
+
+impl Painter {
+    fn new(device: &wgpu::Device, shaders: &Shaders) -> Result<Painter, Error> {
+        let params = GpuBuffer::new(device, 1, BufferUsages::UNIFORM);
+        let n_buffers = shaders.buffers.len();
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        Ok(Painter {
+            buffers: shaders
+                .buffers
+                .iter()
+                .enumerate()
+                .map(|(i, source)| {
+                    PaintPipeline::new(device, source, Pass::Buffer(i), n_buffers, &params)
+                })
+                .collect::<Result<_, _>>()?,
+            image: PaintPipeline::new(device, &shaders.image, Pass::Image, n_buffers, &params)?,
+            uploads: UploadRing::new(device, FRAMES_IN_FLIGHT, params.size()),
+            params,
+            sampler,
+        })
+    }
+
+    fn pipeline(&self, pass: Pass) -> &PaintPipeline {
+        match pass {
+            Pass::Buffer(i) => &self.buffers[i],
+            Pass::Image => &self.image,
+        }
+    }
+
+    fn passes(&self) -> impl Iterator<Item = Pass> {
+        Pass::all(self.buffers.len())
+    }
+
+    /// Rebuild the pipeline of `pass` from new source, keeping the old one
+    /// if that fails. Returns the bind groups to run it with.
+    fn reload(
+        &mut self,
+        device: &wgpu::Device,
+        pass: Pass,
+        source: &Preprocessed,
+        images: &Images,
+    ) -> Result<[wgpu::BindGroup; 2], Error> {
+        let pipeline = PaintPipeline::new(device, source, pass, self.buffers.len(), &self.params)?;
+        let bind_groups = self.bind_pipeline(device, &pipeline, pass, images)?;
+        match pass {
+            Pass::Buffer(i) => self.buffers[i] = pipeline,
+            Pass::Image => self.image = pipeline,
+        }
+        Ok(bind_groups)
+    }
+
+    /// The bind groups of every pass, in the order they run, for frames of
+    /// either parity.
+    fn bind(
+        &self,
+        device: &wgpu::Device,
+        images: &Images,
+    ) -> Result<Vec<[wgpu::BindGroup; 2]>, Error> {
+        self.passes()
+            .map(|pass| self.bind_pipeline(device, self.pipeline(pass), pass, images))
+            .collect()
+    }
+
+    fn bind_pipeline(
+        &self,
+        device: &wgpu::Device,
+        pipeline: &PaintPipeline,
+        pass: Pass,
+        images: &Images,
+    ) -> Result<[wgpu::BindGroup; 2], Error> {
+        let bind = |parity| {
+            let (output, buffers) = images.views(pass, parity);
+            pipeline.bind(device, &self.params, &self.sampler, output, &buffers)
+        };
+        Ok([bind(0)?, bind(1)?])
+    }
+
+    /// Record the parameter upload and the compute passes that paint the
+    /// images, using `bind_groups` from [`bind`](Painter::bind).
+    fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_groups: &[[wgpu::BindGroup; 2]],
+        width: u32,
+        height: u32,
+        frame: &Frame,
+    ) -> Result<(), Error> {
+        let params = Params::new(width, height, frame);
+        self.uploads
+            .upload_to(device, encoder, &self.params, &[params])?;
+        // Each buffer is written in frames of one parity and read in the
+        // others.
+        let parity = passes::parity(frame.frame);
+        for (pass, bind_groups) in self.passes().zip(bind_groups) {
+            let pipeline = self.pipeline(pass);
+            let WorkgroupSize(x, y, _) = pipeline.workgroup_size;
+            // A pass of its own for each, so that later passes see what
+            // earlier ones wrote.
+            let mut cpass = encoder.begin_compute_pass(&Default::default());
+            cpass.set_pipeline(&pipeline.pipeline);
+            cpass.set_bind_group(0, &bind_groups[parity], &[]);
+            // Round up so the edges are painted when the size isn't a
+            // multiple of the workgroup size.
+            cpass.dispatch(width.div_ceil(x), height.div_ceil(y), 1);
+        }
+        Ok(())
+    }
+
+    /// Submit `encoder`, which [`encode`](Painter::encode) recorded into.
+    fn submit(&mut self, queue: &wgpu::Queue, encoder: wgpu::CommandEncoder) {
+        self.uploads.finish();
+        queue.submit(Some(encoder.finish()));
+        self.uploads.recall();
+    }
+}
+
+/// Request a device, with timestamp queries if profiling.
+async fn request_device(
+    adapter: &wgpu::Adapter,
+    trace: Option<&Trace>,
+) -> Result<(wgpu::Device, wgpu::Queue, Option<Profiler>), Error> {
+    let features = match trace {
+        Some(_) => adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
+        None => wgpu::Features::empty(),
+    };
     let (device, queue) = adapter
-        .request_device(&Default::default(), None)
-        .await
-        .expect("error creating device");
-    let size = window.inner_size();
-    let format = surface.get_preferred_format(&adapter).unwrap();
-    let sc = wgpu::SurfaceConfiguration {
-        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-        format: format,
-        width: size.width,
-        height: size.height,
-        present_mode: wgpu::PresentMode::Mailbox,
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features,
+                limits: Default::default(),
+            },
+            None,
+        )
+        .await?;
+    let profiler = match trace {
+        Some(trace) => Some(Profiler::new(&device)?.with_trace(trace.clone())),
+        None => None,
     };
-    surface.configure(&device, &sc);
+    Ok((device, queue, profiler))
+}
 
-    // We use a render pipeline just to copy the output buffer of the compute shader to the
-    // swapchain. It would be nice if we could skip this, but swapchains with storage usage
-    // are not fully portable.
-    let copy_shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-        label: None,
-        source: wgpu::ShaderSource::Wgsl(include_str!("copy.wgsl").into()),
-    });
-    let copy_bind_group_layout =
-        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+/// Record `record` in a scope named `label` if profiling.
+fn scope<R>(
+    profiler: &mut Option<Profiler>,
+    device: &wgpu::Device,
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    record: impl FnOnce(&mut wgpu::CommandEncoder) -> R,
+) -> R {
+    match profiler {
+        Some(profiler) => profiler.scope(device, encoder, label, record),
+        None => record(encoder),
+    }
+}
+
+/// The render pipeline that copies the painted image to the window, with
+/// the overlay blended over it.
+///
+/// We use a render pipeline just to copy the output of the compute shader to
+/// the swapchain. It would be nice if we could skip this, but swapchains with
+/// storage usage are not fully portable.
+struct Copier {
+    pipeline: wgpu::RenderPipeline,
+    layout: ShaderLayout,
+    bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+    sampler: wgpu::Sampler,
+}
+
+impl Copier {
+    fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Result<Copier, Error> {
+        let source = include_str!("copy.wgsl");
+        let layout = ShaderLayout::from_wgsl(source, &["vs_main", "fs_main"])?;
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let bind_group_layouts = layout.create_bind_group_layouts(device);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(),
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: None,
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[format.into()],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        Ok(Copier {
+            pipeline,
+            layout,
+            bind_group_layouts,
+            sampler,
+        })
+    }
+
+    fn bind(
+        &self,
+        device: &wgpu::Device,
+        img_view: &wgpu::TextureView,
+        overlay_view: &wgpu::TextureView,
+    ) -> Result<wgpu::BindGroup, Error> {
+        self.layout.create_bind_group(
+            device,
+            &self.bind_group_layouts,
+            0,
+            &[
+                wgpu::BindGroupEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        // Should filterable be false if we want nearest-neighbor?
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                    },
-                    count: None,
+                    resource: wgpu::BindingResource::TextureView(img_view),
                 },
-                wgpu::BindGroupLayoutEntry {
+                wgpu::BindGroupEntry {
                     binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler {
-                        filtering: false,
-                        comparison: false,
-                    },
-                    count: None,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(overlay_view),
                 },
             ],
+        )
+    }
+
+    /// Record the render pass drawing the image to `view`.
+    fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        bind_group: &wgpu::BindGroup,
+    ) {
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::GREEN),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
         });
-    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: None,
-        bind_group_layouts: &[&copy_bind_group_layout],
-        push_constant_ranges: &[],
-    });
-    let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: None,
-        layout: Some(&pipeline_layout),
-        vertex: wgpu::VertexState {
-            module: &copy_shader,
-            entry_point: "vs_main",
-            buffers: &[],
-        },
-        fragment: Some(wgpu::FragmentState {
-            module: &copy_shader,
-            entry_point: "fs_main",
-            targets: &[format.into()],
-        }),
-        primitive: wgpu::PrimitiveState::default(),
-        depth_stencil: None,
-        multisample: wgpu::MultisampleState::default(),
-    });
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, bind_group, &[]);
+        rpass.draw(0..3, 0..2);
+    }
+}
 
-    let img = device.create_texture(&wgpu::TextureDescriptor {
-        label: None,
-        size: Extent3d {
-            width: size.width,
-            height: size.height,
-            depth_or_array_layers: 1,
-        },
-        mip_level_count: 1,
-        sample_count: 1,
-        dimension: wgpu::TextureDimension::D2,
-        format: wgpu::TextureFormat::Rgba8Unorm,
-        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
-    });
-    let img_view = img.create_view(&Default::default());
+/// The images drawn each frame, sized to the window, and the bind groups
+/// using them.
+struct Target {
+    images: Images,
+    paint_bind_groups: Vec<[wgpu::BindGroup; 2]>,
+    overlay: Overlay,
+    copy_bind_group: wgpu::BindGroup,
+}
 
-    const CONFIG_SIZE: u64 = 12;
+impl Target {
+    fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        painter: &Painter,
+        copier: &Copier,
+        images: Images,
+    ) -> Result<Target, Error> {
+        let overlay = Overlay::new(device, queue, images.width, images.height);
+        let overlay_view = overlay.texture.create_view(&Default::default());
+        Ok(Target {
+            paint_bind_groups: painter.bind(device, &images)?,
+            copy_bind_group: copier.bind(device, &images.image_view, &overlay_view)?,
+            images,
+            overlay,
+        })
+    }
+}
 
-    let config_dev = device.create_buffer(&wgpu::BufferDescriptor {
-        label: None,
-        size: CONFIG_SIZE,
-        usage: BufferUsages::COPY_DST | BufferUsages::STORAGE | BufferUsages::UNIFORM,
-        mapped_at_creation: false,
-    });
-    let config_resource = config_dev.as_entire_binding();
+/// The errors from the last reload of each pass, one per line, or `None` if
+/// every pass compiled.
+fn error_text(errors: &[Option<String>]) -> Option<String> {
+    let lines = errors.iter().flatten().cloned().collect::<Vec<_>>();
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
 
-    let cs_module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-        label: None,
-        source: wgpu::ShaderSource::Wgsl(include_str!("paint.wgsl").into()),
-    });
-    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        label: None,
-        entries: &[
-            wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-            wgpu::BindGroupLayoutEntry {
-                binding: 1,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::StorageTexture {
-                    access: wgpu::StorageTextureAccess::WriteOnly,
-                    format: wgpu::TextureFormat::Rgba8Unorm,
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                },
-                count: None,
-            },
-        ],
-    });
-    let compute_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: None,
-        bind_group_layouts: &[&bind_group_layout],
-        push_constant_ranges: &[],
-    });
-    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-        label: None,
-        layout: Some(&compute_pipeline_layout),
-        module: &cs_module,
-        entry_point: "main",
-    });
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        layout: &bind_group_layout,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: config_resource,
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: wgpu::BindingResource::TextureView(&img_view),
-            },
-        ],
-    });
-    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-        address_mode_u: wgpu::AddressMode::ClampToEdge,
-        address_mode_v: wgpu::AddressMode::ClampToEdge,
-        address_mode_w: wgpu::AddressMode::ClampToEdge,
-        mag_filter: wgpu::FilterMode::Nearest,
-        min_filter: wgpu::FilterMode::Nearest,
-        mipmap_filter: wgpu::FilterMode::Nearest,
-        ..Default::default()
-    });
-    let copy_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        layout: &copy_bind_group_layout,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&img_view),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: wgpu::BindingResource::Sampler(&sampler),
-            },
-        ],
-    });
-    let start_time = std::time::Instant::now();
+async fn run(
+    event_loop: EventLoop<Reload>,
+    window: Window,
+    options: AdapterOptions,
+    shaders: Shaders,
+    trace: Option<(Trace, PathBuf)>,
+) -> Result<(), Error> {
+    let instance = wgpu::Instance::new(options.backends());
+    let surface = unsafe { instance.create_surface(&window) };
+    let adapter =
+        compute_shader_hello::request_adapter(&instance, &options, Some(&surface)).await?;
+
+    let (device, queue, mut profiler) =
+        request_device(&adapter, trace.as_ref().map(|(trace, _)| trace)).await?;
+    let size = window.inner_size();
+    let format = surface.get_preferred_format(&adapter).unwrap();
+    let mut sc = wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format,
+        width: size.width,
+        height: size.height,
+        present_mode: wgpu::PresentMode::Mailbox,
+    };
+    surface.configure(&device, &sc);
+
+    let copier = Copier::new(&device, format)?;
+    let mut painter = Painter::new(&device, &shaders)?;
+    let n_buffers = shaders.buffers.len();
+    let images = Images::new(
+        &device,
+        size.width,
+        size.height,
+        n_buffers,
+        wgpu::TextureUsages::TEXTURE_BINDING,
+    );
+    let mut target = Target::new(&device, &queue, &painter, &copier, images)?;
+    // The error from the last reload of each pass, shown until a reload of
+    // it succeeds.
+    let mut errors: Vec<Option<String>> = vec![None; n_buffers + 1];
+    let mut inputs = Inputs::new();
 
     event_loop.run(move |event, _, control_flow| {
         // TODO: this may be excessive polling. It really should be synchronized with
@@ -218,60 +648,245 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
         *control_flow = ControlFlow::Poll;
         match event {
             Event::RedrawRequested(_) => {
-                let frame = surface
-                    .get_current_texture()
-                    .expect("error getting texture from swap chain");
-
-                let i_time: f32 = 0.5 + start_time.elapsed().as_micros() as f32 * 1e-6;
-                let config_data = [size.width, size.height, i_time.to_bits()];
-                let config_host = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: None,
-                    contents: bytemuck::bytes_of(&config_data),
-                    usage: BufferUsages::COPY_SRC,
-                });
-                let mut encoder = device.create_command_encoder(&Default::default());
-                encoder.copy_buffer_to_buffer(&config_host, 0, &config_dev, 0, CONFIG_SIZE);
-                {
-                    let mut cpass = encoder.begin_compute_pass(&Default::default());
-                    cpass.set_pipeline(&pipeline);
-                    cpass.set_bind_group(0, &bind_group, &[]);
-                    cpass.dispatch(size.width / 16, size.height / 16, 1);
+                // A minimized window has no surface to draw to.
+                if sc.width == 0 || sc.height == 0 {
+                    return;
                 }
-                {
-                    let view = frame.texture.create_view(&wgpu::TextureViewDescriptor::default());
-                    let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: None,
-                        color_attachments: &[wgpu::RenderPassColorAttachment {
-                            view: &view,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(wgpu::Color::GREEN),
-                                store: true,
-                            },
-                        }],
-                        depth_stencil_attachment: None,
-                    });
-                    rpass.set_pipeline(&render_pipeline);
-                    rpass.set_bind_group(0, &copy_bind_group, &[]);
-                    rpass.draw(0..3, 0..2);
+                let frame = match surface.get_current_texture() {
+                    Ok(frame) => frame,
+                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                        surface.configure(&device, &sc);
+                        return;
+                    }
+                    Err(wgpu::SurfaceError::Timeout) => return,
+                    Err(wgpu::SurfaceError::OutOfMemory) => {
+                        eprintln!("error: out of memory for the swap chain");
+                        *control_flow = ControlFlow::Exit;
+                        return;
+                    }
+                };
+
+                let frame_inputs = inputs.next_frame();
+                let mut encoder = device.create_command_encoder(&Default::default());
+                let painted = scope(&mut profiler, &device, &mut encoder, "paint", |encoder| {
+                    painter.encode(
+                        &device,
+                        encoder,
+                        &target.paint_bind_groups,
+                        target.images.width,
+                        target.images.height,
+                        &frame_inputs,
+                    )
+                });
+                if let Err(e) = painted {
+                    eprintln!("error: {}", e);
                 }
-                queue.submit(Some(encoder.finish()));
+                scope(&mut profiler, &device, &mut encoder, "copy", |encoder| {
+                    let view = frame
+                        .texture
+                        .create_view(&wgpu::TextureViewDescriptor::default());
+                    copier.encode(encoder, &view, &target.copy_bind_group);
+                });
+                let timestamps = profiler
+                    .as_mut()
+                    .and_then(|profiler| profiler.resolve(&device, &mut encoder));
+                painter.submit(&queue, encoder);
                 frame.present();
+                // Waiting for the timestamps stalls the pipeline, but only
+                // when profiling.
+                if let Some(timestamps) = timestamps {
+                    if let Err(e) = pollster::block_on(timestamps.read(&device, &queue)) {
+                        eprintln!("error reading timestamps: {}", e);
+                    }
+                }
             }
             Event::MainEventsCleared => {
                 window.request_redraw();
             }
+            Event::UserEvent(Reload { pass, source }) => {
+                // Keep painting with the last good shader if this one fails.
+                let index = pass.index(n_buffers);
+                match source
+                    .and_then(|source| painter.reload(&device, pass, &source, &target.images))
+                {
+                    Ok(bind_groups) => {
+                        target.paint_bind_groups[index] = bind_groups;
+                        errors[index] = None;
+                        eprintln!("{} shader reloaded", pass);
+                    }
+                    Err(e) => {
+                        eprintln!("error in {}: {}", pass, e);
+                        errors[index] = Some(format!("error in {}: {}", pass, e));
+                    }
+                }
+                target.overlay.show(&queue, error_text(&errors).as_deref());
+            }
+            Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                ..
+            } => {
+                sc.width = size.width;
+                sc.height = size.height;
+                if size.width != 0 && size.height != 0 {
+                    surface.configure(&device, &sc);
+                    let images = target
+                        .images
+                        .resized(&device, &queue, size.width, size.height);
+                    match Target::new(&device, &queue, &painter, &copier, images) {
+                        Ok(new_target) => {
+                            target = new_target;
+                            target.overlay.show(&queue, error_text(&errors).as_deref());
+                        }
+                        Err(e) => eprintln!("error: {}", e),
+                    }
+                }
+            }
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 ..
-            } => *control_flow = ControlFlow::Exit,
+            } => {
+                if let Some((trace, path)) = &trace {
+                    if let Err(e) = trace.write(path) {
+                        eprintln!("error: {}", e);
+                    }
+                }
+                *control_flow = ControlFlow::Exit
+            }
+            Event::WindowEvent { event, .. } => inputs.event(&event),
             _ => (),
         }
     });
 }
 
+/// Render `frames` frames offscreen, 1/60 s apart from `i_time`, and write
+/// the last to `path` as a binary PPM.
+async fn run_headless(
+    options: AdapterOptions,
+    shaders: Shaders,
+    (width, height): (u32, u32),
+    i_time: f32,
+    frames: u32,
+    path: &Path,
+    trace: Option<&Trace>,
+) -> Result<(), Error> {
+    let instance = wgpu::Instance::new(options.backends());
+    let adapter = compute_shader_hello::request_adapter(&instance, &options, None).await?;
+    let (device, queue, mut profiler) = request_device(&adapter, trace).await?;
+
+    let mut painter = Painter::new(&device, &shaders)?;
+    let images = Images::new(
+        &device,
+        width,
+        height,
+        shaders.buffers.len(),
+        wgpu::TextureUsages::COPY_SRC,
+    );
+    let bind_groups = painter.bind(&device, &images)?;
+
+    // Rows of a texture copy must be aligned to COPY_BYTES_PER_ROW_ALIGNMENT.
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padded_row = (width * 4).div_ceil(align) * align;
+    let output_buf = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: padded_row as u64 * height as u64,
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    for i in 0..frames.max(1) {
+        let frame = Frame {
+            time: i_time + i as f32 / 60.0,
+            time_delta: if i == 0 { 0.0 } else { 1.0 / 60.0 },
+            frame: i as i32,
+            frame_rate: 60.0,
+            ..Frame::at(i_time)
+        };
+        let mut encoder = device.create_command_encoder(&Default::default());
+        scope(&mut profiler, &device, &mut encoder, "paint", |encoder| {
+            painter.encode(&device, encoder, &bind_groups, width, height, &frame)
+        })?;
+        let timestamps = profiler
+            .as_mut()
+            .and_then(|profiler| profiler.resolve(&device, &mut encoder));
+        painter.submit(&queue, encoder);
+        if let Some(timestamps) = timestamps {
+            timestamps.read(&device, &queue).await?;
+        }
+    }
+    let mut encoder = device.create_command_encoder(&Default::default());
+    encoder.copy_texture_to_buffer(
+        images.image.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &output_buf,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(padded_row),
+                rows_per_image: None,
+            },
+        },
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit(Some(encoder.finish()));
+
+    let buf_slice = output_buf.slice(..);
+    let buf_future = buf_slice.map_async(wgpu::MapMode::Read);
+    device.poll(wgpu::Maintain::Wait);
+    buf_future
+        .await
+        .map_err(|e| Error::Map("rendered frame".into(), e))?;
+    let data = buf_slice.get_mapped_range();
+    let mut ppm = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    for row in data.chunks(padded_row as usize) {
+        for pixel in row[..width as usize * 4].chunks(4) {
+            ppm.extend_from_slice(&pixel[..3]);
+        }
+    }
+    std::fs::write(path, ppm).map_err(|e| Error::Io(path.to_owned(), e))
+}
+
 fn main() {
-    let event_loop = EventLoop::new();
-    let window = Window::new(&event_loop).unwrap();
-    pollster::block_on(run(event_loop, window));
+    let cli = Cli::parse();
+    let mut options = AdapterOptions::from_env();
+    options.force_fallback |= cli.fallback;
+    let trace = cli.trace.as_ref().map(|_| Trace::new());
+    let result = Shaders::load(&cli).and_then(|shaders| match &cli.headless {
+        Some(path) => {
+            pollster::block_on(run_headless(
+                options,
+                shaders,
+                cli.size,
+                cli.time,
+                cli.frames,
+                path,
+                trace.as_ref(),
+            ))?;
+            match (&trace, &cli.trace) {
+                (Some(trace), Some(path)) => trace.write(path),
+                _ => Ok(()),
+            }
+        }
+        None => {
+            let event_loop = EventLoop::with_user_event();
+            for pass in Pass::all(shaders.buffers.len()) {
+                watch::watch(
+                    Preprocessor::new().defines(&cli.defines),
+                    pass,
+                    cli.shader_path(pass).to_owned(),
+                    shaders.get(pass),
+                    event_loop.create_proxy(),
+                );
+            }
+            let window = Window::new(&event_loop).unwrap();
+            let trace = trace.zip(cli.trace.clone());
+            pollster::block_on(run(event_loop, window, options, shaders, trace))
+        }
+    });
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
 }
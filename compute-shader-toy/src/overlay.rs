@@ -0,0 +1,341 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Drawing text over the painted image, to show shader errors in the window.
+//!
+//! Text is rasterized on the CPU with a small built-in font into an image
+//! the size of the window, which the copy shader blends over the paint
+//! output.
+
+use wgpu::Extent3d;
+
+/// The printable ASCII characters from `' '` to `'~'`, as seven rows of five
+/// pixels each, the leftmost pixel in bit 4.
+const FONT: [[u8; 7]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04], // '!'
+    [0x0a, 0x0a, 0x0a, 0x00, 0x00, 0x00, 0x00], // '"'
+    [0x0a, 0x0a, 0x1f, 0x0a, 0x1f, 0x0a, 0x0a], // '#'
+    [0x04, 0x0f, 0x14, 0x0e, 0x05, 0x1e, 0x04], // '$'
+    [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03], // '%'
+    [0x0c, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0d], // '&'
+    [0x04, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00], // '\''
+    [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02], // '('
+    [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08], // ')'
+    [0x00, 0x04, 0x15, 0x0e, 0x15, 0x04, 0x00], // '*'
+    [0x00, 0x04, 0x04, 0x1f, 0x04, 0x04, 0x00], // '+'
+    [0x00, 0x00, 0x00, 0x00, 0x0c, 0x04, 0x08], // ','
+    [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00], // '-'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c], // '.'
+    [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00], // '/'
+    [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e], // '0'
+    [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e], // '1'
+    [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f], // '2'
+    [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e], // '3'
+    [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02], // '4'
+    [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e], // '5'
+    [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e], // '6'
+    [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08], // '7'
+    [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e], // '8'
+    [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c], // '9'
+    [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00], // ':'
+    [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x04, 0x08], // ';'
+    [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02], // '<'
+    [0x00, 0x00, 0x1f, 0x00, 0x1f, 0x00, 0x00], // '='
+    [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08], // '>'
+    [0x0e, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04], // '?'
+    [0x0e, 0x11, 0x01, 0x0d, 0x15, 0x15, 0x0e], // '@'
+    [0x0e, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11], // 'A'
+    [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e], // 'B'
+    [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e], // 'C'
+    [0x1c, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1c], // 'D'
+    [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f], // 'E'
+    [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10], // 'F'
+    [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f], // 'G'
+    [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11], // 'H'
+    [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e], // 'I'
+    [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c], // 'J'
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11], // 'K'
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f], // 'L'
+    [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11], // 'M'
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11], // 'N'
+    [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e], // 'O'
+    [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10], // 'P'
+    [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d], // 'Q'
+    [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11], // 'R'
+    [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e], // 'S'
+    [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], // 'T'
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e], // 'U'
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04], // 'V'
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a], // 'W'
+    [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11], // 'X'
+    [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04], // 'Y'
+    [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f], // 'Z'
+    [0x0e, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0e], // '['
+    [0x00, 0x10, 0x08, 0x04, 0x02, 0x01, 0x00], // '\\'
+    [0x0e, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0e], // ']'
+    [0x04, 0x0a, 0x11, 0x00, 0x00, 0x00, 0x00], // '^'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f], // '_'
+    [0x08, 0x04, 0x02, 0x00, 0x00, 0x00, 0x00], // '`'
+    [0x00, 0x00, 0x0e, 0x01, 0x0f, 0x11, 0x0f], // 'a'
+    [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x1e], // 'b'
+    [0x00, 0x00, 0x0e, 0x10, 0x10, 0x11, 0x0e], // 'c'
+    [0x01, 0x01, 0x0d, 0x13, 0x11, 0x11, 0x0f], // 'd'
+    [0x00, 0x00, 0x0e, 0x11, 0x1f, 0x10, 0x0e], // 'e'
+    [0x06, 0x09, 0x08, 0x1c, 0x08, 0x08, 0x08], // 'f'
+    [0x00, 0x0f, 0x11, 0x11, 0x0f, 0x01, 0x0e], // 'g'
+    [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x11], // 'h'
+    [0x04, 0x00, 0x0c, 0x04, 0x04, 0x04, 0x0e], // 'i'
+    [0x02, 0x00, 0x06, 0x02, 0x02, 0x12, 0x0c], // 'j'
+    [0x10, 0x10, 0x12, 0x14, 0x18, 0x14, 0x12], // 'k'
+    [0x0c, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e], // 'l'
+    [0x00, 0x00, 0x1a, 0x15, 0x15, 0x11, 0x11], // 'm'
+    [0x00, 0x00, 0x16, 0x19, 0x11, 0x11, 0x11], // 'n'
+    [0x00, 0x00, 0x0e, 0x11, 0x11, 0x11, 0x0e], // 'o'
+    [0x00, 0x00, 0x1e, 0x11, 0x1e, 0x10, 0x10], // 'p'
+    [0x00, 0x00, 0x0d, 0x13, 0x0f, 0x01, 0x01], // 'q'
+    [0x00, 0x00, 0x16, 0x19, 0x10, 0x10, 0x10], // 'r'
+    [0x00, 0x00, 0x0e, 0x10, 0x0e, 0x01, 0x1e], // 's'
+    [0x08, 0x08, 0x1c, 0x08, 0x08, 0x09, 0x06], // 't'
+    [0x00, 0x00, 0x11, 0x11, 0x11, 0x13, 0x0d], // 'u'
+    [0x00, 0x00, 0x11, 0x11, 0x11, 0x0a, 0x04], // 'v'
+    [0x00, 0x00, 0x11, 0x11, 0x15, 0x15, 0x0a], // 'w'
+    [0x00, 0x00, 0x11, 0x0a, 0x04, 0x0a, 0x11], // 'x'
+    [0x00, 0x00, 0x11, 0x11, 0x0f, 0x01, 0x0e], // 'y'
+    [0x00, 0x00, 0x1f, 0x02, 0x04, 0x08, 0x1f], // 'z'
+    [0x02, 0x04, 0x04, 0x08, 0x04, 0x04, 0x02], // '{'
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], // '|'
+    [0x08, 0x04, 0x04, 0x02, 0x04, 0x04, 0x08], // '}'
+    [0x00, 0x00, 0x08, 0x15, 0x02, 0x00, 0x00], // '~'
+];
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+/// Each pixel of a glyph is drawn as a square this many pixels wide.
+const SCALE: usize = 2;
+/// The size of a character, with spacing.
+const CELL_WIDTH: usize = (GLYPH_WIDTH + 1) * SCALE;
+const CELL_HEIGHT: usize = (GLYPH_HEIGHT + 3) * SCALE;
+/// Space around the text, inside the panel.
+const MARGIN: usize = 8;
+
+const TEXT_COLOR: [u8; 4] = [255, 255, 255, 255];
+const PANEL_COLOR: [u8; 4] = [96, 0, 0, 216];
+
+/// Render `text` in a panel across the top of a `width` by `height` RGBA
+/// image, wrapping long lines. Everything outside the panel is transparent.
+pub fn render_text(text: &str, width: u32, height: u32) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let mut pixels = vec![0; width * height * 4];
+    let columns = (width.saturating_sub(2 * MARGIN) / CELL_WIDTH).max(1);
+    let lines = wrap(text, columns);
+    let panel_height = (2 * MARGIN + lines.len() * CELL_HEIGHT).min(height);
+    for pixel in pixels[..panel_height * width * 4].chunks_mut(4) {
+        pixel.copy_from_slice(&PANEL_COLOR);
+    }
+    for (row, line) in lines.iter().enumerate() {
+        for (column, c) in line.chars().enumerate() {
+            let x0 = MARGIN + column * CELL_WIDTH;
+            let y0 = MARGIN + row * CELL_HEIGHT;
+            draw_glyph(&mut pixels, width, panel_height, x0, y0, c);
+        }
+    }
+    pixels
+}
+
+/// Split `text` into lines of at most `columns` characters, replacing tabs
+/// and characters the font doesn't have.
+fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let chars = line
+            .replace('\t', "    ")
+            .chars()
+            .map(|c| if (' '..='~').contains(&c) { c } else { '?' })
+            .collect::<Vec<_>>();
+        if chars.is_empty() {
+            lines.push(String::new());
+        }
+        lines.extend(chars.chunks(columns).map(|chunk| chunk.iter().collect()));
+    }
+    lines
+}
+
+fn draw_glyph(pixels: &mut [u8], width: usize, height: usize, x0: usize, y0: usize, c: char) {
+    let glyph = &FONT[c as usize - ' ' as usize];
+    for (gy, bits) in glyph.iter().enumerate() {
+        for gx in 0..GLYPH_WIDTH {
+            if bits & (1 << (GLYPH_WIDTH - 1 - gx)) == 0 {
+                continue;
+            }
+            for y in y0 + gy * SCALE..y0 + (gy + 1) * SCALE {
+                for x in x0 + gx * SCALE..x0 + (gx + 1) * SCALE {
+                    if x < width && y < height {
+                        let i = (y * width + x) * 4;
+                        pixels[i..i + 4].copy_from_slice(&TEXT_COLOR);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A texture holding text to draw over the window, or nothing.
+pub struct Overlay {
+    pub texture: wgpu::Texture,
+    width: u32,
+    height: u32,
+}
+
+impl Overlay {
+    /// An empty overlay of the given size.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32) -> Overlay {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("overlay"),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+        let overlay = Overlay {
+            texture,
+            width,
+            height,
+        };
+        overlay.show(queue, None);
+        overlay
+    }
+
+    /// Show `text` over the image, or clear the overlay if `None`.
+    pub fn show(&self, queue: &wgpu::Queue, text: Option<&str>) {
+        let pixels = match text {
+            Some(text) => render_text(text, self.width, self.height),
+            None => vec![0; self.width as usize * self.height as usize * 4],
+        };
+        queue.write_texture(
+            self.texture.as_image_copy(),
+            &pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(self.width * 4),
+                rows_per_image: None,
+            },
+            Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The color of the pixel at `x`, `y` of a `width` wide image.
+    fn pixel(pixels: &[u8], width: usize, x: usize, y: usize) -> [u8; 4] {
+        let i = (y * width + x) * 4;
+        pixels[i..i + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn lines_wrap_at_the_column_limit() {
+        assert_eq!(wrap("abc", 3), ["abc"]);
+        assert_eq!(wrap("abcd", 3), ["abc", "d"]);
+        // Words aren't kept whole; a long one is split across lines.
+        assert_eq!(wrap("a verylongword", 5), ["a ver", "ylong", "word"]);
+        assert_eq!(wrap("ab", 1), ["a", "b"]);
+    }
+
+    #[test]
+    fn newlines_start_lines() {
+        assert_eq!(wrap("ab\ncd", 10), ["ab", "cd"]);
+        assert_eq!(wrap("ab\n\ncd\r\n", 10), ["ab", "", "cd"]);
+        assert_eq!(wrap("abcd\nef", 3), ["abc", "d", "ef"]);
+        assert!(wrap("", 10).is_empty());
+    }
+
+    #[test]
+    fn tabs_and_unknown_characters_are_replaced() {
+        assert_eq!(wrap("\tx", 10), ["    x"]);
+        assert_eq!(wrap("é→~", 10), ["??~"]);
+        assert_eq!(wrap("\tx", 3), ["   ", " x"]);
+    }
+
+    #[test]
+    fn glyphs_are_drawn_scaled() {
+        let (width, height) = (64, 48);
+        let pixels = render_text("A", width as u32, height as u32);
+        assert_eq!(pixels.len(), width * height * 4);
+        let glyph = FONT['A' as usize - ' ' as usize];
+        for (gy, bits) in glyph.iter().enumerate() {
+            for gx in 0..GLYPH_WIDTH {
+                let lit = bits & (1 << (GLYPH_WIDTH - 1 - gx)) != 0;
+                let expected = if lit { TEXT_COLOR } else { PANEL_COLOR };
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let x = MARGIN + gx * SCALE + dx;
+                    let y = MARGIN + gy * SCALE + dy;
+                    assert_eq!(pixel(&pixels, width, x, y), expected, "{}, {}", x, y);
+                }
+            }
+        }
+        // The top row of 'A' is .###.
+        assert_eq!(pixel(&pixels, width, MARGIN, MARGIN), PANEL_COLOR);
+        assert_eq!(pixel(&pixels, width, MARGIN + SCALE, MARGIN), TEXT_COLOR);
+        assert_eq!(
+            pixel(&pixels, width, MARGIN + 4 * SCALE, MARGIN),
+            PANEL_COLOR
+        );
+    }
+
+    #[test]
+    fn the_panel_fits_the_lines() {
+        // Room for three columns, so "abcdef" takes two lines.
+        let width = 2 * MARGIN + 3 * CELL_WIDTH;
+        let height = 100;
+        let pixels = render_text("abcdef", width as u32, height);
+        let panel_height = 2 * MARGIN + 2 * CELL_HEIGHT;
+        assert_eq!(pixel(&pixels, width, 0, panel_height - 1), PANEL_COLOR);
+        assert_eq!(pixel(&pixels, width, 0, panel_height), [0; 4]);
+        assert_eq!(
+            pixel(&pixels, width, width - 1, height as usize - 1),
+            [0; 4]
+        );
+        // The 'd' starts the second line.
+        let d = FONT['d' as usize - ' ' as usize];
+        let lit = (0..GLYPH_WIDTH)
+            .find(|gx| d[0] & (1 << (GLYPH_WIDTH - 1 - gx)) != 0)
+            .unwrap();
+        let y = MARGIN + CELL_HEIGHT;
+        assert_eq!(pixel(&pixels, width, MARGIN + lit * SCALE, y), TEXT_COLOR);
+    }
+
+    #[test]
+    fn text_is_clipped_to_the_image() {
+        let pixels = render_text("line\nline\nline\nline", 40, 30);
+        assert_eq!(pixels.len(), 40 * 30 * 4);
+        assert!(pixels
+            .chunks(4)
+            .all(|p| p == PANEL_COLOR || p == TEXT_COLOR));
+    }
+}
@@ -14,11 +14,18 @@
 //
 // Also licensed under MIT license, at your choice.
 
+// The image size and the Shadertoy inputs, laid out like `Params` in main.rs.
 [[block]]
 struct Params {
     width: u32;
     height: u32;
     iTime: f32;
+    iTimeDelta: f32;
+    iFrame: i32;
+    iFrameRate: f32;
+    iMouse: vec4<f32>;
+    iDate: vec4<f32>;
+    iResolution: vec3<f32>;
 };
 
 [[group(0), binding(0)]] var<uniform> params: Params;
@@ -0,0 +1,420 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Buffer passes, which keep their output from one frame to the next.
+//!
+//! Like Shadertoy's Buffer A to D, each buffer pass is a compute shader that
+//! writes a float image, and the image pass that draws to the window runs
+//! after them. Every buffer's image is double-buffered: in each frame, the
+//! buffer passes write one of the pair and read the other, which holds the
+//! previous frame's output of every buffer, including their own. The image
+//! pass then reads what the buffer passes have just written.
+//!
+//! All passes are given the same resources in bind group 0, and bind the
+//! ones they use:
+//!
+//! | binding | resource                                          |
+//! |---------|---------------------------------------------------|
+//! | 0       | `Params`, the size and Shadertoy inputs           |
+//! | 1       | the pass's output, a write-only storage texture   |
+//! | 2       | a linear sampler, clamping to the edge            |
+//! | 3 to 6  | the images of Buffer A to D, as `texture_2d<f32>` |
+
+use std::fmt;
+
+use compute_shader_hello::reflect::ShaderLayout;
+use compute_shader_hello::Error;
+
+/// The most buffer passes there can be.
+pub const MAX_BUFFERS: usize = 4;
+
+pub const PARAMS_BINDING: u32 = 0;
+pub const OUTPUT_BINDING: u32 = 1;
+pub const SAMPLER_BINDING: u32 = 2;
+/// The binding of Buffer A's image; the other buffers follow it.
+pub const BUFFERS_BINDING: u32 = 3;
+
+/// The format of the buffers' images, which can be both written as storage
+/// textures and sampled with filtering.
+const BUFFER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// The format of the image pass's output.
+const IMAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+/// One of the passes run each frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pass {
+    /// A buffer pass, numbered from 0 for Buffer A.
+    Buffer(usize),
+    /// The pass drawing the image shown in the window.
+    Image,
+}
+
+impl Pass {
+    /// The passes when there are `n_buffers` buffer passes, in the order
+    /// they run.
+    pub fn all(n_buffers: usize) -> impl Iterator<Item = Pass> {
+        (0..n_buffers).map(Pass::Buffer).chain(Some(Pass::Image))
+    }
+
+    /// The position of the pass in [`all`](Pass::all).
+    pub fn index(self, n_buffers: usize) -> usize {
+        match self {
+            Pass::Buffer(i) => i,
+            Pass::Image => n_buffers,
+        }
+    }
+
+    /// The format of the texture the pass writes, and its name in WGSL.
+    fn output_format(self) -> (wgpu::TextureFormat, &'static str) {
+        match self {
+            Pass::Buffer(_) => (BUFFER_FORMAT, "rgba16float"),
+            Pass::Image => (IMAGE_FORMAT, "rgba8unorm"),
+        }
+    }
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pass::Buffer(i) => write!(f, "Buffer {}", (b'A' + *i as u8) as char),
+            Pass::Image => write!(f, "Image"),
+        }
+    }
+}
+
+/// Check that the shader of `pass` binds only resources passes are given,
+/// each with the type it is given as. `Params` is checked separately, by
+/// [`GpuBuffer::check`](compute_shader_hello::GpuBuffer::check).
+///
+/// Binding something else would make wgpu panic, so this is checked before
+/// the pipeline is built.
+pub fn check_bindings(layout: &ShaderLayout, pass: Pass, n_buffers: usize) -> Result<(), Error> {
+    let (format, format_name) = pass.output_format();
+    let mut problems = Vec::new();
+    for b in &layout.bindings {
+        let ok = match (b.group, b.binding) {
+            (0, PARAMS_BINDING) => continue,
+            (0, OUTPUT_BINDING) => {
+                b.ty == wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                }
+            }
+            (0, SAMPLER_BINDING) => matches!(
+                b.ty,
+                wgpu::BindingType::Sampler {
+                    comparison: false,
+                    ..
+                }
+            ),
+            (0, binding)
+                if (BUFFERS_BINDING..BUFFERS_BINDING + MAX_BUFFERS as u32).contains(&binding) =>
+            {
+                let i = (binding - BUFFERS_BINDING) as usize;
+                if i >= n_buffers {
+                    problems.push(format!(
+                        "{}: reads {}, but there are only {} buffer passes",
+                        b,
+                        Pass::Buffer(i),
+                        n_buffers
+                    ));
+                    continue;
+                }
+                matches!(
+                    b.ty,
+                    wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { .. },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    }
+                )
+            }
+            _ => {
+                problems.push(format!(
+                    "{}: passes are only given bindings 0 to 6 of group 0",
+                    b
+                ));
+                continue;
+            }
+        };
+        if !ok {
+            let expected = match b.binding {
+                OUTPUT_BINDING => format!("texture_storage_2d<{}, write>", format_name),
+                SAMPLER_BINDING => "sampler".to_string(),
+                _ => "texture_2d<f32>".to_string(),
+            };
+            problems.push(format!("{}: {} is given as `{}`", b, pass, expected));
+        }
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Bindings(problems.join("; ")))
+    }
+}
+
+/// The parity of frame number `frame`, which picks the bind groups used.
+pub fn parity(frame: i32) -> usize {
+    frame.rem_euclid(2) as usize
+}
+
+/// Which of each buffer's pair of images `pass` uses in frames of `parity`:
+/// the one it writes, if it is a buffer pass, and the one it reads of every
+/// buffer.
+///
+/// Buffer passes write the image of their parity and read the other, which
+/// the previous frame wrote. The image pass runs after them, so it reads the
+/// images of this frame's parity.
+fn sides(pass: Pass, parity: usize) -> (Option<usize>, usize) {
+    match pass {
+        Pass::Buffer(_) => (Some(parity), 1 - parity),
+        Pass::Image => (None, parity),
+    }
+}
+
+/// The images the passes write, all the same size.
+pub struct Images {
+    pub width: u32,
+    pub height: u32,
+    pub image: wgpu::Texture,
+    pub image_view: wgpu::TextureView,
+    image_usage: wgpu::TextureUsages,
+    buffers: Vec<[wgpu::Texture; 2]>,
+    buffer_views: Vec<[wgpu::TextureView; 2]>,
+}
+
+impl Images {
+    /// Create cleared images, with `image_usage` added to what the image
+    /// pass needs of its output.
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        n_buffers: usize,
+        image_usage: wgpu::TextureUsages,
+    ) -> Images {
+        let image = create_texture(device, width, height, IMAGE_FORMAT, image_usage);
+        let buffers = (0..n_buffers)
+            .map(|_| {
+                [0, 1].map(|_| {
+                    create_texture(
+                        device,
+                        width,
+                        height,
+                        BUFFER_FORMAT,
+                        wgpu::TextureUsages::TEXTURE_BINDING
+                            | wgpu::TextureUsages::COPY_SRC
+                            | wgpu::TextureUsages::COPY_DST,
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        Images {
+            width,
+            height,
+            image_view: image.create_view(&Default::default()),
+            image,
+            image_usage,
+            buffer_views: buffers
+                .iter()
+                .map(|pair| [0, 1].map(|i| pair[i].create_view(&Default::default())))
+                .collect(),
+            buffers,
+        }
+    }
+
+    /// Images of a new size, with the buffers' contents copied over where
+    /// the sizes overlap, so that simulations carry on.
+    pub fn resized(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        width: u32,
+        height: u32,
+    ) -> Images {
+        let images = Images::new(device, width, height, self.buffers.len(), self.image_usage);
+        let mut encoder = device.create_command_encoder(&Default::default());
+        for (old, new) in self.buffers.iter().zip(&images.buffers) {
+            for (old, new) in old.iter().zip(new) {
+                encoder.copy_texture_to_texture(
+                    old.as_image_copy(),
+                    new.as_image_copy(),
+                    wgpu::Extent3d {
+                        width: width.min(self.width),
+                        height: height.min(self.height),
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+        queue.submit(Some(encoder.finish()));
+        images
+    }
+
+    /// The view `pass` writes in frames of `parity`, and the views of the
+    /// buffers it reads.
+    pub fn views(
+        &self,
+        pass: Pass,
+        parity: usize,
+    ) -> (&wgpu::TextureView, Vec<&wgpu::TextureView>) {
+        let (written, read) = sides(pass, parity);
+        let output = match (pass, written) {
+            (Pass::Buffer(i), Some(side)) => &self.buffer_views[i][side],
+            _ => &self.image_view,
+        };
+        let buffers = self.buffer_views.iter().map(|pair| &pair[read]).collect();
+        (output, buffers)
+    }
+}
+
+fn create_texture(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+    usage: wgpu::TextureUsages,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | usage,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The layout of a shader declaring `decls` after an output of `format`,
+    /// and storing `value` to the output so that all of them are used.
+    fn layout(format: &str, decls: &str, value: &str) -> ShaderLayout {
+        let source = format!(
+            "[[group(0), binding(1)]]
+var output: texture_storage_2d<{}, write>;
+{}
+[[stage(compute), workgroup_size(1)]]
+fn main() {{
+    textureStore(output, vec2<i32>(0, 0), {});
+}}
+",
+            format, decls, value
+        );
+        ShaderLayout::from_wgsl(&source, &["main"]).unwrap()
+    }
+
+    fn problems(layout: &ShaderLayout, pass: Pass, n_buffers: usize) -> String {
+        match check_bindings(layout, pass, n_buffers) {
+            Err(Error::Bindings(msg)) => msg,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn frames_alternate_parity() {
+        assert_eq!((0..4).map(parity).collect::<Vec<_>>(), vec![0, 1, 0, 1]);
+        assert_eq!(parity(-1), 1);
+    }
+
+    #[test]
+    fn buffers_read_last_frame_and_the_image_reads_this_one() {
+        assert_eq!(sides(Pass::Buffer(0), 0), (Some(0), 1));
+        assert_eq!(sides(Pass::Buffer(3), 1), (Some(1), 0));
+        assert_eq!(sides(Pass::Image, 0), (None, 0));
+        assert_eq!(sides(Pass::Image, 1), (None, 1));
+        for frame in 0..4 {
+            let (written, read) = sides(Pass::Buffer(1), parity(frame));
+            let (next_written, next_read) = sides(Pass::Buffer(1), parity(frame + 1));
+            // A buffer never reads the image it is writing.
+            assert_ne!(written, Some(read));
+            // What it writes, the buffers read in the next frame, and they
+            // write over what they read in this one.
+            assert_eq!(written, Some(next_read));
+            assert_eq!(next_written, Some(read));
+            // The image pass runs last, and reads what was just written.
+            assert_eq!(sides(Pass::Image, parity(frame)).1, written.unwrap());
+        }
+    }
+
+    #[test]
+    fn the_bindings_passes_are_given_are_accepted() {
+        let layout = layout(
+            "rgba16float",
+            "[[group(0), binding(2)]] var s: sampler;
+[[group(0), binding(3)]] var a: texture_2d<f32>;
+[[group(0), binding(6)]] var d: texture_2d<f32>;",
+            "textureSampleLevel(a, s, vec2<f32>(0.0), 0.0) + textureSampleLevel(d, s, vec2<f32>(0.0), 0.0)",
+        );
+        check_bindings(&layout, Pass::Buffer(0), 4).unwrap();
+    }
+
+    #[test]
+    fn bindings_outside_0_to_6_of_group_0_are_rejected() {
+        let layout = layout(
+            "rgba8unorm",
+            "[[group(0), binding(7)]] var extra: texture_2d<f32>;
+[[group(1), binding(0)]] var other: sampler;",
+            "textureSampleLevel(extra, other, vec2<f32>(0.0), 0.0)",
+        );
+        let msg = problems(&layout, Pass::Image, 4);
+        assert!(msg.contains("extra"), "{}", msg);
+        assert!(msg.contains("other"), "{}", msg);
+        assert_eq!(
+            msg.matches("passes are only given bindings 0 to 6 of group 0")
+                .count(),
+            2,
+            "{}",
+            msg
+        );
+    }
+
+    #[test]
+    fn reading_a_missing_buffer_is_rejected() {
+        let layout = layout(
+            "rgba8unorm",
+            "[[group(0), binding(5)]] var c: texture_2d<f32>;",
+            "textureLoad(c, vec2<i32>(0, 0), 0)",
+        );
+        let msg = problems(&layout, Pass::Image, 2);
+        assert!(
+            msg.contains("reads Buffer C, but there are only 2 buffer passes"),
+            "{}",
+            msg
+        );
+        check_bindings(&layout, Pass::Image, 3).unwrap();
+    }
+
+    #[test]
+    fn the_output_format_depends_on_the_pass() {
+        let layout = layout("rgba16float", "", "vec4<f32>(0.0)");
+        check_bindings(&layout, Pass::Buffer(0), 1).unwrap();
+        let msg = problems(&layout, Pass::Image, 1);
+        assert!(
+            msg.contains("Image is given as `texture_storage_2d<rgba8unorm, write>`"),
+            "{}",
+            msg
+        );
+    }
+}
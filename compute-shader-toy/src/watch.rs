@@ -0,0 +1,91 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Reloading the shaders of the passes when they change on disk.
+//!
+//! The files each shader was preprocessed from are polled for changes on a
+//! background thread, which preprocesses it again and sends the result to
+//! the event loop.
+
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use winit::event_loop::EventLoopProxy;
+
+use compute_shader_hello::preprocess::{Preprocessed, Preprocessor};
+use compute_shader_hello::Error;
+
+use crate::passes::Pass;
+
+/// How often the shader's files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A pass's shader as preprocessed after a change, or why that failed.
+pub struct Reload {
+    pub pass: Pass,
+    pub source: Result<Preprocessed, Error>,
+}
+
+/// Watch the files `source` came from, and send the shader of `pass` at
+/// `path`, preprocessed again, to `proxy` whenever one of them changes.
+///
+/// The set of files is updated from each successful reload, so includes
+/// added later are watched too. The thread stops when the event loop exits.
+pub fn watch(
+    preprocessor: Preprocessor,
+    pass: Pass,
+    path: PathBuf,
+    source: &Preprocessed,
+    proxy: EventLoopProxy<Reload>,
+) {
+    let mut files = source.files().to_vec();
+    std::thread::spawn(move || {
+        let mut modified = modified_times(&files);
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let now = modified_times(&files);
+            if now == modified {
+                continue;
+            }
+            modified = now;
+            let result = preprocessor.process_file(&path);
+            if let Ok(source) = &result {
+                if source.files() != files {
+                    files = source.files().to_vec();
+                    modified = modified_times(&files);
+                }
+            }
+            if proxy
+                .send_event(Reload {
+                    pass,
+                    source: result,
+                })
+                .is_err()
+            {
+                return;
+            }
+        }
+    });
+}
+
+/// The modification time of each file, or `None` if it can't be read, as
+/// while an editor is replacing it.
+fn modified_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
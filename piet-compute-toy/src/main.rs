@@ -23,7 +23,10 @@
 //! (cd shader && ninja) && cargo run
 //! ```
 
-use piet_gpu_hal::{BufferUsage, Error, Instance, ImageLayout, Session};
+use piet_gpu_hal::{
+    Buffer, BufferUsage, DescriptorSet, Error, Image, ImageLayout, Instance, Pipeline, Semaphore,
+    Session, Surface, Swapchain,
+};
 use piet_gpu_hal::include_shader;
 
 use winit::{
@@ -32,8 +35,101 @@ use winit::{
     window::WindowBuilder,
 };
 
+use toy_inputs::{Frame, Inputs};
+
 const NUM_FRAMES: usize = 2;
 
+/// The shader's `Params` buffer: the image size and the Shadertoy inputs,
+/// laid out by std430 rules, with the floats as bits.
+fn params(width: u32, height: u32, frame: &Frame) -> [u32; 20] {
+    let f = |x: f32| x.to_bits();
+    [
+        width,
+        height,
+        f(frame.time),
+        f(frame.time_delta),
+        frame.frame as u32,
+        f(frame.frame_rate),
+        0,
+        0,
+        f(frame.mouse[0]),
+        f(frame.mouse[1]),
+        f(frame.mouse[2]),
+        f(frame.mouse[3]),
+        f(frame.date[0]),
+        f(frame.date[1]),
+        f(frame.date[2]),
+        f(frame.date[3]),
+        f(width as f32),
+        f(height as f32),
+        f(1.0),
+        0,
+    ]
+}
+
+/// Everything made for a window size. The swapchain is made from the
+/// device, which the session takes ownership of, so a resize starts over
+/// from a new device.
+///
+/// Fields are dropped in order: what the session made goes first, then the
+/// swapchain, then the session and its device.
+struct Gpu {
+    descriptor_set: DescriptorSet,
+    pipeline: Pipeline,
+    staging_img: Image,
+    config_host: Buffer,
+    config_dev: Buffer,
+    present_semaphores: Vec<Semaphore>,
+    swapchain: Swapchain,
+    session: Session,
+    width: u32,
+    height: u32,
+}
+
+impl Gpu {
+    unsafe fn new(
+        instance: &Instance,
+        surface: &Surface,
+        width: u32,
+        height: u32,
+    ) -> Result<Gpu, Error> {
+        let device = instance.device(Some(surface))?;
+        let swapchain = instance.swapchain(width as _, height as _, &device, surface)?;
+        let session = Session::new(device);
+        let config_size = 80;
+        let config_host = session.create_buffer(config_size, BufferUsage::COPY_SRC | BufferUsage::MAP_WRITE)?;
+        let config_dev = session.create_buffer(config_size, BufferUsage::COPY_DST | BufferUsage::STORAGE)?;
+        let staging_img = session.create_image2d(width, height)?;
+
+        let shader_code = include_shader!(&session, "../shader/gen/shader");
+        let pipeline = session.pipeline_builder()
+            .add_buffers(1)
+            .add_images(1)
+            .create_compute_pipeline(&session, shader_code)?;
+
+        let descriptor_set = session.descriptor_set_builder()
+            .add_buffers(&[&config_dev])
+            .add_images(&[&staging_img])
+            .build(&session, &pipeline)?;
+
+        let present_semaphores = (0..NUM_FRAMES)
+            .map(|_| session.create_semaphore())
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Gpu {
+            descriptor_set,
+            pipeline,
+            staging_img,
+            config_host,
+            config_dev,
+            present_semaphores,
+            swapchain,
+            session,
+            width,
+            height,
+        })
+    }
+}
+
 unsafe fn toy() -> Result<(), Error> {
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
@@ -41,36 +137,18 @@ unsafe fn toy() -> Result<(), Error> {
             width: 1024.0,
             height: 768.0,
         })
-        .with_resizable(false) // currently not supported
         .build(&event_loop)?;
     let size = window.inner_size();
     println!("window size = {} x {}", size.width, size.height);
     let (instance, surface) = Instance::new(Some(&window))?;
     let surface = surface.ok_or("no surface")?;
-    let device = instance.device(Some(&surface))?;
-    let mut swapchain = instance.swapchain(size.width as _, size.height as _, &device, &surface)?;
-    let session = Session::new(device);
-    let config_size = 12;
-    let mut config_host = session.create_buffer(config_size, BufferUsage::COPY_SRC | BufferUsage::MAP_WRITE)?;
-    let config_dev = session.create_buffer(config_size, BufferUsage::COPY_DST | BufferUsage::STORAGE)?;
-    let staging_img = session.create_image2d(size.width, size.height)?;
-    let start_time = std::time::Instant::now();
-
-    let shader_code = include_shader!(&session, "../shader/gen/shader");
-    let pipeline = session.pipeline_builder()
-        .add_buffers(1)
-        .add_images(1)
-        .create_compute_pipeline(&session, shader_code)?;
-
-    let descriptor_set = session.descriptor_set_builder()
-        .add_buffers(&[&config_dev])
-        .add_images(&[&staging_img])
-        .build(&session, &pipeline)?;
+    let mut gpu = Some(Gpu::new(&instance, &surface, size.width, size.height)?);
+    let mut inputs = Inputs::new();
 
     let mut current_frame = 0;
-    let present_semaphores = (0..NUM_FRAMES)
-        .map(|_| session.create_semaphore())
-        .collect::<Result<Vec<_>, Error>>()?;
+    // The last frame's commands, which read the config buffer and the
+    // staging image.
+    let mut in_flight = None;
 
     event_loop.run(move |event, _, control_flow| {
         //println!("event {:?}", event);
@@ -80,49 +158,69 @@ unsafe fn toy() -> Result<(), Error> {
                 window.request_redraw();
             }
             Event::RedrawRequested(_) => {
+                // Nothing is drawn while the window is minimized.
+                let gpu = match &mut gpu {
+                    Some(gpu) => gpu,
+                    None => return,
+                };
                 let frame_idx = current_frame % NUM_FRAMES;
-                let (image_idx, acquisition_semaphore) = swapchain.next().unwrap();
-                let swap_image = swapchain.image(image_idx);
-
-                let i_time: f32 = 0.5 + start_time.elapsed().as_micros() as f32 * 1e-6;
-                let config_data = [
-                    size.width,
-                    size.height,
-                    i_time.to_bits(),
-                ];
-                config_host.write(&config_data).unwrap();
-
-                let mut cmd_buf = session.cmd_buf().unwrap();
+                let (image_idx, acquisition_semaphore) = gpu.swapchain.next().unwrap();
+                let swap_image = gpu.swapchain.image(image_idx);
+
+                if let Some(submitted) = in_flight.take() {
+                    submitted.wait().unwrap();
+                }
+                let config_data = params(gpu.width, gpu.height, &inputs.next_frame());
+                gpu.config_host.write(&config_data).unwrap();
+
+                let mut cmd_buf = gpu.session.cmd_buf().unwrap();
                 cmd_buf.begin();
                 cmd_buf.image_barrier(&swap_image, ImageLayout::Undefined, ImageLayout::BlitDst);
-                cmd_buf.copy_buffer(&config_host, &config_dev);
+                cmd_buf.copy_buffer(&gpu.config_host, &gpu.config_dev);
                 cmd_buf.memory_barrier();
 
-                cmd_buf.image_barrier(&staging_img, ImageLayout::Undefined, ImageLayout::General);
-                let wg_x = size.width / 16;
-                let wg_y = size.height / 16;
-                cmd_buf.dispatch(&pipeline, &descriptor_set, (wg_x, wg_y, 1), (16, 16, 1));
-                cmd_buf.image_barrier(&staging_img, ImageLayout::General, ImageLayout::BlitSrc);
-                cmd_buf.blit_image(&staging_img, &swap_image);
+                cmd_buf.image_barrier(&gpu.staging_img, ImageLayout::Undefined, ImageLayout::General);
+                // The window can be any size, so round up; stores past the edge
+                // are dropped.
+                let wg_x = (gpu.width + 15) / 16;
+                let wg_y = (gpu.height + 15) / 16;
+                cmd_buf.dispatch(&gpu.pipeline, &gpu.descriptor_set, (wg_x, wg_y, 1), (16, 16, 1));
+                cmd_buf.image_barrier(&gpu.staging_img, ImageLayout::General, ImageLayout::BlitSrc);
+                cmd_buf.blit_image(&gpu.staging_img, &swap_image);
                 cmd_buf.image_barrier(&swap_image, ImageLayout::BlitDst, ImageLayout::Present);
                 cmd_buf.finish();
-                let submitted = session
+                let submitted = gpu.session
                     .run_cmd_buf(
                         cmd_buf,
                         &[&acquisition_semaphore],
-                        &[&present_semaphores[frame_idx]],
+                        &[&gpu.present_semaphores[frame_idx]],
                     )
                     .unwrap();
-                swapchain
-                    .present(image_idx, &[&present_semaphores[frame_idx]])
+                gpu.swapchain
+                    .present(image_idx, &[&gpu.present_semaphores[frame_idx]])
                     .unwrap();
-                submitted.wait().unwrap();
+                in_flight = Some(submitted);
                 current_frame += 1;
             }
+            Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                ..
+            } => {
+                if let Some(submitted) = in_flight.take() {
+                    submitted.wait().unwrap();
+                }
+                // The old swapchain has to go before a new one is made for
+                // the surface.
+                gpu = None;
+                if size.width > 0 && size.height > 0 {
+                    gpu = Some(Gpu::new(&instance, &surface, size.width, size.height).unwrap());
+                }
+            }
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 ..
             } => *control_flow = ControlFlow::Exit,
+            Event::WindowEvent { event, .. } => inputs.event(&event),
             _ => (),
         }
     });
@@ -2,10 +2,27 @@
 
 use bytemuck::{Pod, Zeroable};
 
+/// The push constants of the compute shader: the image size and the
+/// Shadertoy inputs (`iTime`, `iTimeDelta` and so on), in the same layout as
+/// the uniforms of the other toys.
+///
+/// Vectors are plain arrays so the layout doesn't depend on glam's alignment;
+/// the padding is where GLSL and WGSL would put it.
 #[repr(C)]
 #[derive(Clone, Copy, Pod, Zeroable)]
 pub struct Config {
     pub width: u32,
     pub height: u32,
     pub time: f32,
+    pub time_delta: f32,
+    pub frame: i32,
+    pub frame_rate: f32,
+    pub _pad0: [u32; 2],
+    /// Pointer position while the left button is held, and where it was
+    /// pressed, as described for Shadertoy's `iMouse`.
+    pub mouse: [f32; 4],
+    /// Year, month (from 0), day and seconds since midnight, in UTC.
+    pub date: [f32; 4],
+    pub resolution: [f32; 3],
+    pub _pad1: u32,
 }
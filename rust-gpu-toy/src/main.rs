@@ -16,9 +16,8 @@
 
 //! A simple compute shader example that draws into a window, based on wgpu.
 
-use std::time::Instant;
-
 use rust_gpu_toy_shared::Config;
+use toy_inputs::{Frame, Inputs};
 use wgpu::{Extent3d, ShaderModule};
 
 use winit::dpi::PhysicalSize;
@@ -62,22 +61,28 @@ struct State {
     compute_bind_group: wgpu::BindGroup,
     copy_bind_group: wgpu::BindGroup,
 
-    start_time: Instant,
+    inputs: Inputs,
 }
 
 impl State {
     async fn new(window: &Window, compilation: CompileResult) -> Self {
         let size = window.inner_size();
-        let instance = wgpu::Instance::new(wgpu::Backends::PRIMARY);
+        let force_fallback = force_fallback_adapter();
+        let backends = wgpu::util::backend_bits_from_env().unwrap_or(if force_fallback {
+            wgpu::Backends::all()
+        } else {
+            wgpu::Backends::PRIMARY
+        });
+        let instance = wgpu::Instance::new(backends);
         let surface = unsafe { instance.create_surface(window) };
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: Default::default(),
                 compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
+                force_fallback_adapter: force_fallback,
             })
             .await
-            .expect("error finding adapter");
+            .unwrap_or_else(|| no_adapter(force_fallback));
         let features = wgpu::Features::PUSH_CONSTANTS;
         let limits = wgpu::Limits {
             max_push_constant_size: std::mem::size_of::<Config>() as u32,
@@ -119,7 +124,6 @@ impl State {
         let layouts = Self::create_layouts(&device);
         let pipelines = Self::create_pipelines(&device, &surface_config, &layouts, &compilation);
         let (compute_group, copy_group) = Self::bind_for_size(&device, &sampler, size, &layouts);
-        let start_time = Instant::now();
         State {
             pipelines,
             layouts,
@@ -131,7 +135,7 @@ impl State {
             sampler,
             compute_bind_group: compute_group,
             copy_bind_group: copy_group,
-            start_time,
+            inputs: Inputs::new(),
         }
     }
 
@@ -312,7 +316,7 @@ impl State {
         })
     }
 
-    fn render_frame(&self) {
+    fn render_frame(&mut self) {
         let frame = match self.surface.get_current_texture() {
             Ok(output) => output,
             Err(err) => {
@@ -332,13 +336,8 @@ impl State {
         let frame_view = frame
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
-        let i_time: f32 = 0.5 + self.start_time.elapsed().as_micros() as f32 * 1e-6;
         let size = self.size;
-        let config = Config {
-            width: size.width,
-            height: size.height,
-            time: i_time,
-        };
+        let config = config(size, &self.inputs.next_frame());
 
         let mut encoder = self.device.create_command_encoder(&Default::default());
         {
@@ -371,6 +370,53 @@ impl State {
     }
 }
 
+/// The push constants for a frame of the given size.
+fn config(size: PhysicalSize<u32>, frame: &Frame) -> Config {
+    Config {
+        width: size.width,
+        height: size.height,
+        time: frame.time,
+        time_delta: frame.time_delta,
+        frame: frame.frame,
+        frame_rate: frame.frame_rate,
+        mouse: frame.mouse,
+        date: frame.date,
+        resolution: [size.width as f32, size.height as f32, 1.0],
+        ..bytemuck::Zeroable::zeroed()
+    }
+}
+
+/// Whether to use a software adapter, requested by passing `--fallback` or
+/// setting `WGPU_FORCE_FALLBACK_ADAPTER`.
+fn force_fallback_adapter() -> bool {
+    std::env::args().any(|arg| arg == "--fallback")
+        || std::env::var("WGPU_FORCE_FALLBACK_ADAPTER").map_or(false, |v| !v.is_empty() && v != "0")
+}
+
+/// Report that no adapter was found, listing the ones that exist.
+fn no_adapter(force_fallback: bool) -> ! {
+    let kind = if force_fallback {
+        "software fallback"
+    } else {
+        "suitable GPU"
+    };
+    eprintln!("no {} adapter found; available adapters:", kind);
+    let instance = wgpu::Instance::new(wgpu::Backends::all());
+    for adapter in instance.enumerate_adapters(wgpu::Backends::all()) {
+        let info = adapter.get_info();
+        eprintln!(
+            "    {} ({:?}, {:?})",
+            info.name, info.backend, info.device_type
+        );
+    }
+    if !force_fallback {
+        eprintln!(
+            "to use a software adapter, pass --fallback or set WGPU_FORCE_FALLBACK_ADAPTER=1"
+        );
+    }
+    std::process::exit(1);
+}
+
 async fn run(
     event_loop: EventLoop<CompileResult>,
     window: Window,
@@ -397,7 +443,7 @@ async fn run(
                     state.resize(new_size);
                     window.request_redraw();
                 }
-                _ => (),
+                event => state.inputs.event(&event),
             },
             _ => (),
         }
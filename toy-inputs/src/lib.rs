@@ -0,0 +1,306 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Tracking the Shadertoy inputs from the event loop.
+//!
+//! Shadertoy shaders read the time, frame count, mouse state and date from
+//! uniforms. The tracker follows the window's mouse events and computes the
+//! rest once per frame, so that each toy only has to copy them into its own
+//! parameter struct.
+
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use winit::event::{ElementState, MouseButton, WindowEvent};
+
+/// The inputs for one frame, with the meanings Shadertoy gives them.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Frame {
+    /// Seconds since the toy started (`iTime`).
+    pub time: f32,
+    /// Seconds since the previous frame, 0 for the first (`iTimeDelta`).
+    pub time_delta: f32,
+    /// Frames rendered before this one (`iFrame`).
+    pub frame: i32,
+    /// Frames per second, smoothed over recent frames (`iFrameRate`).
+    pub frame_rate: f32,
+    /// Pointer and click positions in pixels (`iMouse`).
+    ///
+    /// `xy` follows the pointer while the left button is held, and keeps its
+    /// last position after it is released. `zw` is where the button was
+    /// pressed; `z` is negated once it is released, and `w` is negated after
+    /// the frame in which it was pressed. Coordinates have their origin at
+    /// the top left, like `global_invocation_id`.
+    pub mouse: [f32; 4],
+    /// Year, month (from 0), day (from 1) and seconds since midnight, in UTC
+    /// (`iDate`).
+    pub date: [f32; 4],
+}
+
+impl Frame {
+    /// The inputs for a single frame rendered at `time`, with no mouse.
+    pub fn at(time: f32) -> Frame {
+        Frame {
+            time,
+            date: date(SystemTime::now()),
+            ..Default::default()
+        }
+    }
+}
+
+/// Tracks the inputs across frames.
+pub struct Inputs {
+    start: Instant,
+    last_frame: Option<Instant>,
+    frame: i32,
+    frame_rate: f32,
+    cursor: [f32; 2],
+    mouse: [f32; 2],
+    click: [f32; 2],
+    pressed: bool,
+    clicked: bool,
+}
+
+impl Default for Inputs {
+    fn default() -> Inputs {
+        Inputs::new()
+    }
+}
+
+impl Inputs {
+    pub fn new() -> Inputs {
+        Inputs {
+            start: Instant::now(),
+            last_frame: None,
+            frame: 0,
+            frame_rate: 0.0,
+            cursor: [0.0; 2],
+            mouse: [0.0; 2],
+            click: [0.0; 2],
+            pressed: false,
+            clicked: false,
+        }
+    }
+
+    /// Update the mouse state from a window event. Other events are ignored.
+    pub fn event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = [position.x as f32, position.y as f32];
+                if self.pressed {
+                    self.mouse = self.cursor;
+                }
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => match state {
+                ElementState::Pressed => {
+                    self.mouse = self.cursor;
+                    self.click = self.cursor;
+                    self.pressed = true;
+                    self.clicked = true;
+                }
+                ElementState::Released => self.pressed = false,
+            },
+            _ => (),
+        }
+    }
+
+    /// The inputs for the next frame, which is counted as rendered.
+    pub fn next_frame(&mut self) -> Frame {
+        self.frame_at(Instant::now(), SystemTime::now())
+    }
+
+    /// The inputs for a frame rendered at `now`, which is `wall` by the
+    /// system clock.
+    fn frame_at(&mut self, now: Instant, wall: SystemTime) -> Frame {
+        let time_delta = self
+            .last_frame
+            .map_or(0.0, |last| (now - last).as_secs_f32());
+        if time_delta > 0.0 {
+            // An exponential moving average, so the rate doesn't jitter.
+            self.frame_rate = if self.frame_rate == 0.0 {
+                1.0 / time_delta
+            } else {
+                0.9 * self.frame_rate + 0.1 / time_delta
+            };
+        }
+        let sign = |positive: bool| if positive { 1.0 } else { -1.0 };
+        let frame = Frame {
+            // Start half a second in, as the toys always have.
+            time: 0.5 + (now - self.start).as_secs_f32(),
+            time_delta,
+            frame: self.frame,
+            frame_rate: self.frame_rate,
+            mouse: [
+                self.mouse[0],
+                self.mouse[1],
+                sign(self.pressed) * self.click[0],
+                sign(self.clicked) * self.click[1],
+            ],
+            date: date(wall),
+        };
+        self.last_frame = Some(now);
+        self.frame += 1;
+        self.clicked = false;
+        frame
+    }
+}
+
+/// The `iDate` of a point in time, in UTC.
+fn date(time: SystemTime) -> [f32; 4] {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let days = (since_epoch.as_secs() / 86400) as i64;
+    let seconds = since_epoch.as_secs_f64() - days as f64 * 86400.0;
+    // Convert days since 1970-01-01 to a civil date, treating the year as
+    // starting in March so that leap days come last. See
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 2 } else { mp - 10 };
+    let year = yoe + era * 400 + if month < 2 { 1 } else { 0 };
+    [year as f32, month as f32, day as f32, seconds as f32]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use winit::dpi::PhysicalPosition;
+    use winit::event::{DeviceId, ModifiersState};
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[allow(deprecated)]
+    fn cursor_moved(x: f64, y: f64) -> WindowEvent<'static> {
+        WindowEvent::CursorMoved {
+            device_id: unsafe { DeviceId::dummy() },
+            position: PhysicalPosition::new(x, y),
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    #[allow(deprecated)]
+    fn left_button(state: ElementState) -> WindowEvent<'static> {
+        WindowEvent::MouseInput {
+            device_id: unsafe { DeviceId::dummy() },
+            state,
+            button: MouseButton::Left,
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    #[test]
+    fn the_epoch_is_the_first_of_january_1970() {
+        assert_eq!(date(UNIX_EPOCH), [1970.0, 0.0, 1.0, 0.0]);
+        assert_eq!(date(at(86399)), [1970.0, 0.0, 1.0, 86399.0]);
+        assert_eq!(date(at(86400)), [1970.0, 0.0, 2.0, 0.0]);
+    }
+
+    #[test]
+    fn leap_days() {
+        // 2024-02-29 12:34:56
+        assert_eq!(date(at(1709210096)), [2024.0, 1.0, 29.0, 45296.0]);
+        assert_eq!(date(at(1709210096 + 86400)), [2024.0, 2.0, 1.0, 45296.0]);
+        // 2000 is a leap year, being divisible by 400; 2100 isn't.
+        assert_eq!(date(at(951782400)), [2000.0, 1.0, 29.0, 0.0]);
+        assert_eq!(date(at(4107542400 - 86400)), [2100.0, 1.0, 28.0, 0.0]);
+        assert_eq!(date(at(4107542400)), [2100.0, 2.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn seconds_of_the_day() {
+        // 1999-12-31 23:59:59.5
+        let time = at(946684799) + Duration::from_millis(500);
+        assert_eq!(date(time), [1999.0, 11.0, 31.0, 86399.5]);
+        assert_eq!(date(at(946684800)), [2000.0, 0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn mouse_signs() {
+        let mut inputs = Inputs::new();
+        inputs.event(&cursor_moved(10.0, 20.0));
+        // Moving without the button held doesn't change iMouse.
+        assert_eq!(inputs.next_frame().mouse, [0.0; 4]);
+
+        inputs.event(&left_button(ElementState::Pressed));
+        // Both positive in the frame of the click, then w is negated.
+        assert_eq!(inputs.next_frame().mouse, [10.0, 20.0, 10.0, 20.0]);
+        assert_eq!(inputs.next_frame().mouse, [10.0, 20.0, 10.0, -20.0]);
+
+        inputs.event(&cursor_moved(30.0, 40.0));
+        assert_eq!(inputs.next_frame().mouse, [30.0, 40.0, 10.0, -20.0]);
+
+        inputs.event(&left_button(ElementState::Released));
+        inputs.event(&cursor_moved(50.0, 60.0));
+        // Released: xy stays where it was, and z is negated too.
+        assert_eq!(inputs.next_frame().mouse, [30.0, 40.0, -10.0, -20.0]);
+    }
+
+    #[test]
+    fn a_click_released_within_a_frame_still_shows() {
+        let mut inputs = Inputs::new();
+        inputs.event(&cursor_moved(5.0, 6.0));
+        inputs.event(&left_button(ElementState::Pressed));
+        inputs.event(&left_button(ElementState::Released));
+        assert_eq!(inputs.next_frame().mouse, [5.0, 6.0, -5.0, 6.0]);
+        assert_eq!(inputs.next_frame().mouse, [5.0, 6.0, -5.0, -6.0]);
+    }
+
+    #[test]
+    fn frames_are_counted_and_timed() {
+        let mut inputs = Inputs::new();
+        let start = inputs.start;
+        let mut frame = |ms| inputs.frame_at(start + Duration::from_millis(ms), UNIX_EPOCH);
+
+        let first = frame(0);
+        assert_eq!((first.frame, first.time, first.time_delta), (0, 0.5, 0.0));
+        assert_eq!(first.frame_rate, 0.0);
+
+        let second = frame(100);
+        assert_eq!(second.frame, 1);
+        assert!((second.time - 0.6).abs() < 1e-6);
+        assert!((second.time_delta - 0.1).abs() < 1e-6);
+        // The first rate is taken as it is.
+        assert!((second.frame_rate - 10.0).abs() < 1e-4);
+
+        // Then it moves a tenth of the way to each new rate.
+        let third = frame(150);
+        assert_eq!(third.frame, 2);
+        assert!((third.frame_rate - (0.9 * 10.0 + 0.1 * 20.0)).abs() < 1e-3);
+
+        // A frame at the same instant leaves the rate alone.
+        let fourth = frame(150);
+        assert_eq!((fourth.frame, fourth.time_delta), (3, 0.0));
+        assert_eq!(fourth.frame_rate, third.frame_rate);
+    }
+
+    #[test]
+    fn a_single_frame_has_no_mouse_or_count() {
+        let frame = Frame::at(2.0);
+        assert_eq!(frame.time, 2.0);
+        assert_eq!((frame.frame, frame.mouse), (0, [0.0; 4]));
+    }
+}